@@ -1,5 +1,6 @@
 use proc_macro::TokenStream;
 
+mod mapping;
 mod proto;
 
 /// Declares a new enum which is compatible with the `arcorn::{enwrap, unwrap, is}` API.
@@ -15,3 +16,13 @@ mod proto;
 pub fn proto(_: TokenStream, input: TokenStream) -> TokenStream {
     proto::execute(input)
 }
+
+/// Derives `es_properties()`, the Elasticsearch mapping of every field of a
+/// struct, from the field types.
+///
+/// * Integers map to `integer` or `long`, floats to `float` or `double`.
+/// * `String` maps to `keyword`, so that it can be aggregated in Kibana.
+#[proc_macro_derive(EsMapping)]
+pub fn es_mapping(input: TokenStream) -> TokenStream {
+    mapping::execute(input)
+}
@@ -0,0 +1,60 @@
+use proc_macro as pm;
+use proc_macro2 as pm2;
+use quote::quote;
+
+pub(super) fn execute(item: pm::TokenStream) -> pm::TokenStream {
+    let item: syn::ItemStruct = syn::parse_macro_input!(item as syn::ItemStruct);
+    let ident = &item.ident;
+    let properties = item
+        .fields
+        .iter()
+        .map(|field| {
+            let name = field
+                .ident
+                .as_ref()
+                .expect("#[derive(EsMapping)] expects structs to have named fields")
+                .to_string();
+            let name = syn::LitStr::new(&name, pm2::Span::call_site());
+            match ty_to_es_type(&field.ty) {
+                Some(ty) => {
+                    let ty = syn::LitStr::new(ty, pm2::Span::call_site());
+                    quote!(properties.insert(#name.to_owned(), serde_json::json!({ "type": #ty }));)
+                }
+                None => syn::Error::new_spanned(
+                    &field.ty,
+                    "#[derive(EsMapping)] only supports integer, float, bool and String fields",
+                )
+                .to_compile_error(),
+            }
+        })
+        .collect::<Vec<_>>();
+    quote!(
+        impl #ident {
+            /// Elasticsearch mapping of every field of this type.
+            pub fn es_properties() -> serde_json::Map<String, serde_json::Value> {
+                let mut properties = serde_json::Map::new();
+                #(#properties)*
+                properties
+            }
+        }
+    )
+    .into()
+}
+
+fn ty_to_es_type(ty: &syn::Type) -> Option<&'static str> {
+    match ty {
+        syn::Type::Path(ty) => {
+            let seg = ty.path.segments.iter().last()?;
+            match seg.ident.to_string().as_str() {
+                "i32" | "u32" => Some("integer"),
+                "i64" | "u64" => Some("long"),
+                "f32" => Some("float"),
+                "f64" => Some("double"),
+                "bool" => Some("boolean"),
+                "String" => Some("keyword"),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
@@ -92,37 +92,70 @@ fn rewrite_struct(mut item: syn::ItemStruct) -> pm::TokenStream {
     .into()
 }
 
+fn path_to_prost_ty(seg: &syn::PathSegment) -> &'static str {
+    match seg.ident.to_string().as_str() {
+        "i32" => "int32",
+        "i64" => "int64",
+        "bool" => "bool",
+        "f32" => "float",
+        "f64" => "double",
+        "u32" => "uint32",
+        "u64" => "uint64",
+        "String" => "string",
+        // This case covers messages which are wrapped in Box<T> as well
+        _ => "message",
+    }
+}
+
+/// Returns the element type of a `Vec<T>`.
+fn vec_elem(seg: &syn::PathSegment) -> Option<&syn::PathSegment> {
+    if seg.ident != "Vec" {
+        return None;
+    }
+    match &seg.arguments {
+        syn::PathArguments::AngleBracketed(args) => match args.args.iter().next() {
+            Some(syn::GenericArgument::Type(syn::Type::Path(elem))) => {
+                elem.path.segments.iter().next()
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
 fn ty_to_prost_attr(ty: &syn::Type, tag: Option<usize>) -> (syn::Attribute, bool) {
     let mut is_unit = false;
+    let mut is_repeated = false;
     let ty = match &ty {
         syn::Type::Path(ty) => {
             let seg = ty.path.segments.iter().next().unwrap();
-            match seg.ident.to_string().as_str() {
-                "i32" => "int32",
-                "i64" => "int64",
-                "bool" => "bool",
-                "f32" => "float",
-                "f64" => "double",
-                "u32" => "uint32",
-                "u64" => "uint64",
-                "String" => "string",
-                // This case covers messages which are wrapped in Box<T> as well
-                _ => "message",
+            match vec_elem(seg) {
+                Some(elem) if elem.ident == "u8" => "bytes",
+                Some(elem) => {
+                    is_repeated = true;
+                    path_to_prost_ty(elem)
+                }
+                None => path_to_prost_ty(seg),
             }
-            .to_string()
         }
         syn::Type::Tuple(ty) if ty.elems.is_empty() => {
             is_unit = true;
-            "message".to_string()
+            "message"
         }
         _ => panic!("#[macros::proto] expects all types to be mangled and de-aliased."),
     };
-    let ident = syn::Ident::new(&ty, pm2::Span::call_site());
-    let attr = if let Some(tag) = tag {
-        let lit = syn::LitStr::new(&format!("{}", tag), pm2::Span::call_site());
-        syn::parse_quote!(#[prost(#ident, tag = #lit)])
-    } else {
-        syn::parse_quote!(#[prost(#ident, required)])
+    let ident = syn::Ident::new(ty, pm2::Span::call_site());
+    let attr = match tag {
+        Some(tag) => {
+            assert!(
+                !is_repeated,
+                "#[macros::proto] does not support Vec<T> in enum variants"
+            );
+            let lit = syn::LitStr::new(&format!("{}", tag), pm2::Span::call_site());
+            syn::parse_quote!(#[prost(#ident, tag = #lit)])
+        }
+        None if is_repeated => syn::parse_quote!(#[prost(#ident, repeated)]),
+        None => syn::parse_quote!(#[prost(#ident, required)]),
     };
     (attr, is_unit)
 }
@@ -1,53 +1,160 @@
+use crate::data::Breakdown;
+use crate::data::OdAccumulator;
+use crate::data::OdWindowedData;
+use crate::data::PaymentType;
+use crate::data::RateCode;
+use crate::data::RideAccumulator;
 use crate::data::RideData;
 use crate::data::RideWindowedData;
+use crate::data::Vendor;
+use crate::sketch::Distribution;
 
-fn agg_u64(buffer: &[RideData], f: impl FnMut(&RideData) -> u64 + Copy) -> (u64, u64, u64, u64) {
-    let count = buffer.len() as u64;
+/// Mean of `count` values that add up to `sum`, without truncating it.
+fn average(sum: f64, count: u64) -> f64 {
+    sum / count as f64
+}
+
+fn agg_u64(buffer: &[RideData], f: impl FnMut(&RideData) -> u64 + Copy) -> (u64, u64, u64, f64) {
     let sum = buffer.iter().map(f).sum();
     let max = buffer.iter().map(f).max().unwrap();
     let min = buffer.iter().map(f).min().unwrap();
-    let avg = sum / count;
+    let avg = average(sum as f64, buffer.len() as u64);
     (sum, max, min, avg)
 }
 
-fn agg_f32(buffer: &[RideData], f: impl FnMut(&RideData) -> f32) -> (f32, f32) {
-    let count = buffer.len() as f32;
+fn agg_i64(buffer: &[RideData], f: impl FnMut(&RideData) -> i64 + Copy) -> (i64, i64, i64, f64) {
     let sum = buffer.iter().map(f).sum();
-    let avg = sum / count;
+    let max = buffer.iter().map(f).max().unwrap();
+    let min = buffer.iter().map(f).min().unwrap();
+    let avg = average(sum as f64, buffer.len() as u64);
+    (sum, max, min, avg)
+}
+
+fn agg_f32(buffer: &[RideData], f: impl FnMut(&RideData) -> f32) -> (f32, f64) {
+    let sum: f32 = buffer.iter().map(f).sum();
+    let avg = average(sum as f64, buffer.len() as u64);
     (sum, avg)
 }
 
+fn agg_distribution(buffer: &[RideData], f: impl FnMut(&RideData) -> f64) -> Distribution {
+    buffer
+        .iter()
+        .map(f)
+        .map(Distribution::of)
+        .fold(Distribution::default(), |acc, d| acc.merge(&d))
+}
+
 pub fn window_sum(buffer: &[RideData]) -> RideWindowedData {
     let count = buffer.len() as u64;
+    let flagged_count = buffer.iter().filter(|x| x.quality_flags != 0).count() as u64;
 
-    let (sum_fare_amount, max_fare_amount, avg_fare_amount, min_fare_amount) =
-        agg_u64(buffer, |x| x.fare_amount);
+    let (sum_fare_amount, max_fare_amount, min_fare_amount, avg_fare_amount) =
+        agg_i64(buffer, |x| x.fare_amount);
+
+    let (sum_tip_amount, max_tip_amount, min_tip_amount, avg_tip_amount) =
+        agg_i64(buffer, |x| x.tip_amount);
+
+    let (sum_total_amount, max_total_amount, min_total_amount, avg_total_amount) =
+        agg_i64(buffer, |x| x.total_amount);
+
+    let (sum_tolls_amount, max_tolls_amount, min_tolls_amount, avg_tolls_amount) =
+        agg_i64(buffer, |x| x.tolls_amount);
+
+    let (sum_extra, max_extra, min_extra, avg_extra) = agg_i64(buffer, |x| x.extra);
+
+    let (sum_mta_tax, max_mta_tax, min_mta_tax, avg_mta_tax) = agg_i64(buffer, |x| x.mta_tax);
+
+    let (
+        sum_improvement_surcharge,
+        max_improvement_surcharge,
+        min_improvement_surcharge,
+        avg_improvement_surcharge,
+    ) = agg_i64(buffer, |x| x.improvement_surcharge);
+
+    let (
+        sum_congestion_surcharge,
+        max_congestion_surcharge,
+        min_congestion_surcharge,
+        avg_congestion_surcharge,
+    ) = agg_i64(buffer, |x| x.congestion_surcharge);
 
     let (sum_trip_distance, avg_trip_distance) = agg_f32(buffer, |x| x.trip_distance);
 
-    let (sum_passenger_count, max_passenger_count, avg_passenger_count, min_passenger_count) =
+    let (sum_passenger_count, max_passenger_count, min_passenger_count, avg_passenger_count) =
         agg_u64(buffer, |x| x.passenger_count);
 
-    let (sum_duration, max_duration, avg_duration, min_duration) =
-        agg_u64(buffer, |x| x.do_time.checked_sub(x.pu_time).unwrap_or(0));
+    let (sum_duration, max_duration, min_duration, avg_duration) = agg_u64(buffer, duration);
+
+    let dist_fare_amount = agg_distribution(buffer, |x| x.fare_amount as f64);
+    let dist_duration = agg_distribution(buffer, |x| duration(x) as f64);
+    let dist_trip_distance = agg_distribution(buffer, |x| x.trip_distance as f64);
 
-    let pu_location_id = buffer[0].pu_location_id;
+    let breakdown = buffer
+        .iter()
+        .map(breakdown_init)
+        .fold(Breakdown::default(), |acc, b| breakdown_merge(&acc, &b));
+
+    let location_id = buffer[0].location_id;
     let pu_time = buffer[0].pu_time;
-    let pu_location_name = loc_to_name(pu_location_id);
 
     RideWindowedData {
-        pu_location_id,
+        location_id,
         pu_time,
 
-        pu_location_name,
+        // Set by the window operator
+        window_start: 0,
+        window_end: 0,
+        revision: 0,
+
+        // Set after the window
+        direction: String::new(),
+        location_name: String::new(),
+        borough: String::new(),
+        zone: String::new(),
 
         count,
+        flagged_count,
 
         sum_fare_amount,
         max_fare_amount,
         avg_fare_amount,
         min_fare_amount,
 
+        sum_tip_amount,
+        max_tip_amount,
+        avg_tip_amount,
+        min_tip_amount,
+
+        sum_total_amount,
+        max_total_amount,
+        avg_total_amount,
+        min_total_amount,
+
+        sum_tolls_amount,
+        max_tolls_amount,
+        avg_tolls_amount,
+        min_tolls_amount,
+
+        sum_extra,
+        max_extra,
+        avg_extra,
+        min_extra,
+
+        sum_mta_tax,
+        max_mta_tax,
+        avg_mta_tax,
+        min_mta_tax,
+
+        sum_improvement_surcharge,
+        max_improvement_surcharge,
+        avg_improvement_surcharge,
+        min_improvement_surcharge,
+
+        sum_congestion_surcharge,
+        max_congestion_surcharge,
+        avg_congestion_surcharge,
+        min_congestion_surcharge,
+
         sum_trip_distance,
         avg_trip_distance,
 
@@ -60,277 +167,650 @@ pub fn window_sum(buffer: &[RideData]) -> RideWindowedData {
         max_duration,
         avg_duration,
         min_duration,
+
+        p50_fare_amount: dist_fare_amount.quantile(0.5).round() as i64,
+        p90_fare_amount: dist_fare_amount.quantile(0.9).round() as i64,
+        p99_fare_amount: dist_fare_amount.quantile(0.99).round() as i64,
+        stddev_fare_amount: dist_fare_amount.stddev() as f32,
+
+        p50_duration: dist_duration.quantile(0.5).round() as u64,
+        p90_duration: dist_duration.quantile(0.9).round() as u64,
+        p99_duration: dist_duration.quantile(0.99).round() as u64,
+        stddev_duration: dist_duration.stddev() as f32,
+
+        p50_trip_distance: dist_trip_distance.quantile(0.5) as f32,
+        p90_trip_distance: dist_trip_distance.quantile(0.9) as f32,
+        p99_trip_distance: dist_trip_distance.quantile(0.99) as f32,
+        stddev_trip_distance: dist_trip_distance.stddev() as f32,
+
+        tip_percentage: tip_percentage(sum_tip_amount, sum_fare_amount),
+        congestion_surcharge_share: share(sum_congestion_surcharge, sum_total_amount),
+
+        payment_credit_card_count: breakdown.payment_credit_card_count,
+        payment_credit_card_revenue: breakdown.payment_credit_card_revenue,
+        payment_cash_count: breakdown.payment_cash_count,
+        payment_cash_revenue: breakdown.payment_cash_revenue,
+        payment_no_charge_count: breakdown.payment_no_charge_count,
+        payment_no_charge_revenue: breakdown.payment_no_charge_revenue,
+        payment_dispute_count: breakdown.payment_dispute_count,
+        payment_dispute_revenue: breakdown.payment_dispute_revenue,
+        payment_unknown_count: breakdown.payment_unknown_count,
+        payment_unknown_revenue: breakdown.payment_unknown_revenue,
+        payment_voided_count: breakdown.payment_voided_count,
+        payment_voided_revenue: breakdown.payment_voided_revenue,
+
+        rate_standard_count: breakdown.rate_standard_count,
+        rate_jfk_count: breakdown.rate_jfk_count,
+        rate_newark_count: breakdown.rate_newark_count,
+        rate_nassau_westchester_count: breakdown.rate_nassau_westchester_count,
+        rate_negotiated_count: breakdown.rate_negotiated_count,
+        rate_group_ride_count: breakdown.rate_group_ride_count,
+        rate_unknown_count: breakdown.rate_unknown_count,
+
+        vendor_creative_mobile_count: breakdown.vendor_creative_mobile_count,
+        vendor_verifone_count: breakdown.vendor_verifone_count,
+        vendor_unknown_count: breakdown.vendor_unknown_count,
     }
 }
 
-fn loc_to_name(loc: u64) -> String {
-    match loc {
-        1 => "EWR, Newark Airport",
-        2 => "Queens, Jamaica Bay",
-        3 => "Bronx, Allerton/Pelham Gardens",
-        4 => "Manhattan, Alphabet City",
-        5 => "Staten Island, Arden Heights",
-        6 => "Staten Island, Arrochar/Fort Wadsworth",
-        7 => "Queens, Astoria",
-        8 => "Queens, Astoria Park",
-        9 => "Queens, Auburndale",
-        10 => "Queens, Baisley Park",
-        11 => "Brooklyn, Bath Beach",
-        12 => "Manhattan, Battery Park",
-        13 => "Manhattan, Battery Park City",
-        14 => "Brooklyn, Bay Ridge",
-        15 => "Queens, Bay Terrace/Fort Totten",
-        16 => "Queens, Bayside",
-        17 => "Brooklyn, Bedford",
-        18 => "Bronx, Bedford Park",
-        19 => "Queens, Bellerose",
-        20 => "Bronx, Belmont",
-        21 => "Brooklyn, Bensonhurst East",
-        22 => "Brooklyn, Bensonhurst West",
-        23 => "Staten Island, Bloomfield/Emerson Hill",
-        24 => "Manhattan, Bloomingdale",
-        25 => "Brooklyn, Boerum Hill",
-        26 => "Brooklyn, Borough Park",
-        27 => "Queens, Breezy Point/Fort Tilden/Riis Beach",
-        28 => "Queens, Briarwood/Jamaica Hills",
-        29 => "Brooklyn, Brighton Beach",
-        30 => "Queens, Broad Channel",
-        31 => "Bronx, Bronx Park",
-        32 => "Bronx, Bronxdale",
-        33 => "Brooklyn, Brooklyn Heights",
-        34 => "Brooklyn, Brooklyn Navy Yard",
-        35 => "Brooklyn, Brownsville",
-        36 => "Brooklyn, Bushwick North",
-        37 => "Brooklyn, Bushwick South",
-        38 => "Queens, Cambria Heights",
-        39 => "Brooklyn, Canarsie",
-        40 => "Brooklyn, Carroll Gardens",
-        41 => "Manhattan, Central Harlem",
-        42 => "Manhattan, Central Harlem North",
-        43 => "Manhattan, Central Park",
-        44 => "Staten Island, Charleston/Tottenville",
-        45 => "Manhattan, Chinatown",
-        46 => "Bronx, City Island",
-        47 => "Bronx, Claremont/Bathgate",
-        48 => "Manhattan, Clinton East",
-        49 => "Brooklyn, Clinton Hill",
-        50 => "Manhattan, Clinton West",
-        51 => "Bronx, Co-Op City",
-        52 => "Brooklyn, Cobble Hill",
-        53 => "Queens, College Point",
-        54 => "Brooklyn, Columbia Street",
-        55 => "Brooklyn, Coney Island",
-        56 => "Queens, Corona",
-        57 => "Queens, Corona",
-        58 => "Bronx, Country Club",
-        59 => "Bronx, Crotona Park",
-        60 => "Bronx, Crotona Park East",
-        61 => "Brooklyn, Crown Heights North",
-        62 => "Brooklyn, Crown Heights South",
-        63 => "Brooklyn, Cypress Hills",
-        64 => "Queens, Douglaston",
-        65 => "Brooklyn, Downtown Brooklyn/MetroTech",
-        66 => "Brooklyn, DUMBO/Vinegar Hill",
-        67 => "Brooklyn, Dyker Heights",
-        68 => "Manhattan, East Chelsea",
-        69 => "Bronx, East Concourse/Concourse Village",
-        70 => "Queens, East Elmhurst",
-        71 => "Brooklyn, East Flatbush/Farragut",
-        72 => "Brooklyn, East Flatbush/Remsen Village",
-        73 => "Queens, East Flushing",
-        74 => "Manhattan, East Harlem North",
-        75 => "Manhattan, East Harlem South",
-        76 => "Brooklyn, East New York",
-        77 => "Brooklyn, East New York/Pennsylvania Avenue",
-        78 => "Bronx, East Tremont",
-        79 => "Manhattan, East Village",
-        80 => "Brooklyn, East Williamsburg",
-        81 => "Bronx, Eastchester",
-        82 => "Queens, Elmhurst",
-        83 => "Queens, Elmhurst/Maspeth",
-        84 => "Staten Island, Eltingville/Annadale/Prince's Bay",
-        85 => "Brooklyn, Erasmus",
-        86 => "Queens, Far Rockaway",
-        87 => "Manhattan, Financial District North",
-        88 => "Manhattan, Financial District South",
-        89 => "Brooklyn, Flatbush/Ditmas Park",
-        90 => "Manhattan, Flatiron",
-        91 => "Brooklyn, Flatlands",
-        92 => "Queens, Flushing",
-        93 => "Queens, Flushing Meadows-Corona Park",
-        94 => "Bronx, Fordham South",
-        95 => "Queens, Forest Hills",
-        96 => "Queens, Forest Park/Highland Park",
-        97 => "Brooklyn, Fort Greene",
-        98 => "Queens, Fresh Meadows",
-        99 => "Staten, Island Freshkills Park",
-        100 => "Manhattan, Garment District",
-        101 => "Queens, Glen Oaks",
-        102 => "Queens, Glendale",
-        103 => "Manhattan, Governor's Island/Ellis Island/Liberty Island",
-        104 => "Manhattan, Governor's Island/Ellis Island/Liberty Island",
-        105 => "Manhattan, Governor's Island/Ellis Island/Liberty Island",
-        106 => "Brooklyn, Gowanus",
-        107 => "Manhattan, Gramercy",
-        108 => "Brooklyn, Gravesend",
-        109 => "Staten, Island Great Kills",
-        110 => "Staten, Island Great Kills Park",
-        111 => "Brooklyn, Green-Wood Cemetery",
-        112 => "Brooklyn, Greenpoint",
-        113 => "Manhattan, Greenwich Village North",
-        114 => "Manhattan, Greenwich Village South",
-        115 => "Staten Island, Grymes Hill/Clifton",
-        116 => "Manhattan, Hamilton Heights",
-        117 => "Queens, Hammels/Arverne",
-        118 => "Staten Island, Heartland Village/Todt Hill",
-        119 => "Bronx, Highbridge",
-        120 => "Manhattan, Highbridge Park",
-        121 => "Queens, Hillcrest/Pomonok",
-        122 => "Queens, Hollis",
-        123 => "Brooklyn, Homecrest",
-        124 => "Queens, Howard Beach",
-        125 => "Manhattan, Hudson Sq",
-        126 => "Bronx, Hunts Point",
-        127 => "Manhattan, Inwood",
-        128 => "Manhattan, Inwood Hill Park",
-        129 => "Queens, Jackson Heights",
-        130 => "Queens, Jamaica",
-        131 => "Queens, Jamaica Estates",
-        132 => "Queens, JFK Airport",
-        133 => "Brooklyn, Kensington",
-        134 => "Queens, Kew Gardens",
-        135 => "Queens, Kew Gardens Hills",
-        136 => "Bronx, Kingsbridge Heights",
-        137 => "Manhattan, Kips Bay",
-        138 => "Queens, LaGuardia Airport",
-        139 => "Queens, Laurelton",
-        140 => "Manhattan, Lenox Hill East",
-        141 => "Manhattan, Lenox Hill West",
-        142 => "Manhattan, Lincoln Square East",
-        143 => "Manhattan, Lincoln Square West",
-        144 => "Manhattan, Little Italy/NoLiTa",
-        145 => "Queens, Long Island City/Hunters Point",
-        146 => "Queens, Long Island City/Queens Plaza",
-        147 => "Bronx, Longwood",
-        148 => "Manhattan, Lower East Side",
-        149 => "Brooklyn, Madison",
-        150 => "Brooklyn, Manhattan Beach",
-        151 => "Manhattan, Manhattan Valley",
-        152 => "Manhattan, Manhattanville",
-        153 => "Manhattan, Marble Hill",
-        154 => "Brooklyn, Marine Park/Floyd Bennett Field",
-        155 => "Brooklyn, Marine Park/Mill Basin",
-        156 => "Staten Island, Mariners Harbor",
-        157 => "Queens, Maspeth",
-        158 => "Manhattan, Meatpacking/West Village West",
-        159 => "Bronx, Melrose South",
-        160 => "Queens, Middle Village",
-        161 => "Manhattan, Midtown Center",
-        162 => "Manhattan, Midtown East",
-        163 => "Manhattan, Midtown North",
-        164 => "Manhattan, Midtown South",
-        165 => "Brooklyn, Midwood",
-        166 => "Manhattan, Morningside Heights",
-        167 => "Bronx, Morrisania/Melrose",
-        168 => "Bronx, Mott Haven/Port Morris",
-        169 => "Bronx, Mount Hope",
-        170 => "Manhattan, Murray Hill",
-        171 => "Queens, Murray Hill-Queens",
-        172 => "Staten Island, New Dorp/Midland Beach",
-        173 => "Queens, North Corona",
-        174 => "Bronx, Norwood",
-        175 => "Queens, Oakland Gardens",
-        176 => "Staten, Island Oakwood",
-        177 => "Brooklyn, Ocean Hill",
-        178 => "Brooklyn, Ocean Parkway South",
-        179 => "Queens, Old Astoria",
-        180 => "Queens, Ozone Park",
-        181 => "Brooklyn, Park Slope",
-        182 => "Bronx, Parkchester",
-        183 => "Bronx, Pelham Bay",
-        184 => "Bronx, Pelham Bay Park",
-        185 => "Bronx, Pelham Parkway",
-        186 => "Manhattan, Penn Station/Madison Sq West",
-        187 => "Staten, Island Port Richmond",
-        188 => "Brooklyn, Prospect-Lefferts Gardens",
-        189 => "Brooklyn, Prospect Heights",
-        190 => "Brooklyn, Prospect Park",
-        191 => "Queens, Queens Village",
-        192 => "Queens, Queensboro Hill",
-        193 => "Queens, Queensbridge/Ravenswood",
-        194 => "Manhattan, Randalls Island",
-        195 => "Brooklyn, Red Hook",
-        196 => "Queens, Rego Park",
-        197 => "Queens, Richmond Hill",
-        198 => "Queens, Ridgewood",
-        199 => "Bronx, Rikers Island",
-        200 => "Bronx, Riverdale/North Riverdale/Fieldston",
-        201 => "Queens, Rockaway Park",
-        202 => "Manhattan, Roosevelt Island",
-        203 => "Queens, Rosedale",
-        204 => "Staten Island, Rossville/Woodrow",
-        205 => "Queens, Saint Albans",
-        206 => "Staten Island, Saint George/New Brighton",
-        207 => "Queens, Saint Michaels Cemetery/Woodside",
-        208 => "Bronx, Schuylerville/Edgewater Park",
-        209 => "Manhattan, Seaport",
-        210 => "Brooklyn, Sheepshead Bay",
-        211 => "Manhattan, SoHo",
-        212 => "Bronx, Soundview/Bruckner",
-        213 => "Bronx, Soundview/Castle Hill",
-        214 => "Staten Island, South Beach/Dongan Hills",
-        215 => "Queens, South Jamaica",
-        216 => "Queens, South Ozone Park",
-        217 => "Brooklyn, South Williamsburg",
-        218 => "Queens, Springfield Gardens North",
-        219 => "Queens, Springfield Gardens South",
-        220 => "Bronx, Spuyten Duyvil/Kingsbridge",
-        221 => "Staten, Island Stapleton",
-        222 => "Brooklyn, Starrett City",
-        223 => "Queens, Steinway",
-        224 => "Manhattan, Stuy Town/Peter Cooper Village",
-        225 => "Brooklyn, Stuyvesant Heights",
-        226 => "Queens, Sunnyside",
-        227 => "Brooklyn, Sunset Park East",
-        228 => "Brooklyn, Sunset Park West",
-        229 => "Manhattan, Sutton Place/Turtle Bay North",
-        230 => "Manhattan, Times Sq/Theatre District",
-        231 => "Manhattan, TriBeCa/Civic Center",
-        232 => "Manhattan, Two Bridges/Seward Park",
-        233 => "Manhattan, UN/Turtle Bay South",
-        234 => "Manhattan, Union Sq",
-        235 => "Bronx, University Heights/Morris Heights",
-        236 => "Manhattan, Upper East Side North",
-        237 => "Manhattan, Upper East Side South",
-        238 => "Manhattan, Upper West Side North",
-        239 => "Manhattan, Upper West Side South",
-        240 => "Bronx Van, Cortlandt Park",
-        241 => "Bronx Van, Cortlandt Village",
-        242 => "Bronx Van, Nest/Morris Park",
-        243 => "Manhattan, Washington Heights North",
-        244 => "Manhattan, Washington Heights South",
-        245 => "Staten Island, West Brighton",
-        246 => "Manhattan, West Chelsea/Hudson Yards",
-        247 => "Bronx, West Concourse",
-        248 => "Bronx, West Farms/Bronx River",
-        249 => "Manhattan, West Village",
-        250 => "Bronx, Westchester Village/Unionport",
-        251 => "Staten Island, Westerleigh",
-        252 => "Queens, Whitestone",
-        253 => "Queens, Willets Point",
-        254 => "Bronx, Williamsbridge/Olinville",
-        255 => "Brooklyn, Williamsburg (North Side)",
-        256 => "Brooklyn, Williamsburg (South Side)",
-        257 => "Brooklyn, Windsor Terrace",
-        258 => "Queens, Woodhaven",
-        259 => "Bronx, Woodlawn/Wakefield",
-        260 => "Queens, Woodside",
-        261 => "Manhattan, World Trade Center",
-        262 => "Manhattan, Yorkville East",
-        263 => "Manhattan, Yorkville West",
-        264 => "Unknown, NV",
-        265 => "Unknown, NA",
-        _ => panic!("Unknown location id"),
+fn duration(ride: &RideData) -> u64 {
+    ride.do_time.checked_sub(ride.pu_time).unwrap_or(0)
+}
+
+/// Starts a partial aggregate from the first ride of a window.
+pub fn window_init(ride: RideData) -> RideAccumulator {
+    let duration = duration(&ride);
+    RideAccumulator {
+        location_id: ride.location_id,
+        pu_time: ride.pu_time,
+
+        count: 1,
+        flagged_count: if ride.quality_flags != 0 { 1 } else { 0 },
+
+        sum_fare_amount: ride.fare_amount,
+        max_fare_amount: ride.fare_amount,
+        min_fare_amount: ride.fare_amount,
+
+        sum_tip_amount: ride.tip_amount,
+        max_tip_amount: ride.tip_amount,
+        min_tip_amount: ride.tip_amount,
+
+        sum_total_amount: ride.total_amount,
+        max_total_amount: ride.total_amount,
+        min_total_amount: ride.total_amount,
+
+        sum_tolls_amount: ride.tolls_amount,
+        max_tolls_amount: ride.tolls_amount,
+        min_tolls_amount: ride.tolls_amount,
+
+        sum_extra: ride.extra,
+        max_extra: ride.extra,
+        min_extra: ride.extra,
+
+        sum_mta_tax: ride.mta_tax,
+        max_mta_tax: ride.mta_tax,
+        min_mta_tax: ride.mta_tax,
+
+        sum_improvement_surcharge: ride.improvement_surcharge,
+        max_improvement_surcharge: ride.improvement_surcharge,
+        min_improvement_surcharge: ride.improvement_surcharge,
+
+        sum_congestion_surcharge: ride.congestion_surcharge,
+        max_congestion_surcharge: ride.congestion_surcharge,
+        min_congestion_surcharge: ride.congestion_surcharge,
+
+        sum_trip_distance: ride.trip_distance,
+
+        sum_passenger_count: ride.passenger_count,
+        max_passenger_count: ride.passenger_count,
+        min_passenger_count: ride.passenger_count,
+
+        sum_duration: duration,
+        max_duration: duration,
+        min_duration: duration,
+
+        dist_fare_amount: Distribution::of(ride.fare_amount as f64),
+        dist_duration: Distribution::of(duration as f64),
+        dist_trip_distance: Distribution::of(ride.trip_distance as f64),
+
+        breakdown: breakdown_init(&ride),
+    }
+}
+
+/// Adds a ride to a copy of a partial aggregate, as arcon's
+/// `IncrementalWindow` expects. Operators that own their accumulator use
+/// `window_add` instead.
+pub fn window_aggregate(ride: RideData, acc: &RideAccumulator) -> RideAccumulator {
+    let mut acc = acc.clone();
+    window_add(&mut acc, &ride);
+    acc
+}
+
+/// Adds a ride to a partial aggregate in place.
+pub fn window_add(acc: &mut RideAccumulator, ride: &RideData) {
+    fn add_i64(sum: &mut i64, max: &mut i64, min: &mut i64, value: i64) {
+        *sum += value;
+        *max = (*max).max(value);
+        *min = (*min).min(value);
+    }
+    let duration = duration(ride);
+
+    acc.count += 1;
+    if ride.quality_flags != 0 {
+        acc.flagged_count += 1;
+    }
+
+    add_i64(
+        &mut acc.sum_fare_amount,
+        &mut acc.max_fare_amount,
+        &mut acc.min_fare_amount,
+        ride.fare_amount,
+    );
+    add_i64(
+        &mut acc.sum_tip_amount,
+        &mut acc.max_tip_amount,
+        &mut acc.min_tip_amount,
+        ride.tip_amount,
+    );
+    add_i64(
+        &mut acc.sum_total_amount,
+        &mut acc.max_total_amount,
+        &mut acc.min_total_amount,
+        ride.total_amount,
+    );
+    add_i64(
+        &mut acc.sum_tolls_amount,
+        &mut acc.max_tolls_amount,
+        &mut acc.min_tolls_amount,
+        ride.tolls_amount,
+    );
+    add_i64(
+        &mut acc.sum_extra,
+        &mut acc.max_extra,
+        &mut acc.min_extra,
+        ride.extra,
+    );
+    add_i64(
+        &mut acc.sum_mta_tax,
+        &mut acc.max_mta_tax,
+        &mut acc.min_mta_tax,
+        ride.mta_tax,
+    );
+    add_i64(
+        &mut acc.sum_improvement_surcharge,
+        &mut acc.max_improvement_surcharge,
+        &mut acc.min_improvement_surcharge,
+        ride.improvement_surcharge,
+    );
+    add_i64(
+        &mut acc.sum_congestion_surcharge,
+        &mut acc.max_congestion_surcharge,
+        &mut acc.min_congestion_surcharge,
+        ride.congestion_surcharge,
+    );
+
+    acc.sum_trip_distance += ride.trip_distance;
+
+    acc.sum_passenger_count += ride.passenger_count;
+    acc.max_passenger_count = acc.max_passenger_count.max(ride.passenger_count);
+    acc.min_passenger_count = acc.min_passenger_count.min(ride.passenger_count);
+
+    acc.sum_duration += duration;
+    acc.max_duration = acc.max_duration.max(duration);
+    acc.min_duration = acc.min_duration.min(duration);
+
+    acc.dist_fare_amount.add(ride.fare_amount as f64);
+    acc.dist_duration.add(duration as f64);
+    acc.dist_trip_distance.add(ride.trip_distance as f64);
+
+    breakdown_add(&mut acc.breakdown, ride);
+}
+
+/// Combines two partial aggregates, keeping the keys of the first one.
+pub fn window_merge(a: &RideAccumulator, b: &RideAccumulator) -> RideAccumulator {
+    RideAccumulator {
+        location_id: a.location_id,
+        pu_time: a.pu_time,
+
+        count: a.count + b.count,
+        flagged_count: a.flagged_count + b.flagged_count,
+
+        sum_fare_amount: a.sum_fare_amount + b.sum_fare_amount,
+        max_fare_amount: a.max_fare_amount.max(b.max_fare_amount),
+        min_fare_amount: a.min_fare_amount.min(b.min_fare_amount),
+
+        sum_tip_amount: a.sum_tip_amount + b.sum_tip_amount,
+        max_tip_amount: a.max_tip_amount.max(b.max_tip_amount),
+        min_tip_amount: a.min_tip_amount.min(b.min_tip_amount),
+
+        sum_total_amount: a.sum_total_amount + b.sum_total_amount,
+        max_total_amount: a.max_total_amount.max(b.max_total_amount),
+        min_total_amount: a.min_total_amount.min(b.min_total_amount),
+
+        sum_tolls_amount: a.sum_tolls_amount + b.sum_tolls_amount,
+        max_tolls_amount: a.max_tolls_amount.max(b.max_tolls_amount),
+        min_tolls_amount: a.min_tolls_amount.min(b.min_tolls_amount),
+
+        sum_extra: a.sum_extra + b.sum_extra,
+        max_extra: a.max_extra.max(b.max_extra),
+        min_extra: a.min_extra.min(b.min_extra),
+
+        sum_mta_tax: a.sum_mta_tax + b.sum_mta_tax,
+        max_mta_tax: a.max_mta_tax.max(b.max_mta_tax),
+        min_mta_tax: a.min_mta_tax.min(b.min_mta_tax),
+
+        sum_improvement_surcharge: a.sum_improvement_surcharge + b.sum_improvement_surcharge,
+        max_improvement_surcharge: a.max_improvement_surcharge.max(b.max_improvement_surcharge),
+        min_improvement_surcharge: a.min_improvement_surcharge.min(b.min_improvement_surcharge),
+
+        sum_congestion_surcharge: a.sum_congestion_surcharge + b.sum_congestion_surcharge,
+        max_congestion_surcharge: a.max_congestion_surcharge.max(b.max_congestion_surcharge),
+        min_congestion_surcharge: a.min_congestion_surcharge.min(b.min_congestion_surcharge),
+
+        sum_trip_distance: a.sum_trip_distance + b.sum_trip_distance,
+
+        sum_passenger_count: a.sum_passenger_count + b.sum_passenger_count,
+        max_passenger_count: a.max_passenger_count.max(b.max_passenger_count),
+        min_passenger_count: a.min_passenger_count.min(b.min_passenger_count),
+
+        sum_duration: a.sum_duration + b.sum_duration,
+        max_duration: a.max_duration.max(b.max_duration),
+        min_duration: a.min_duration.min(b.min_duration),
+
+        dist_fare_amount: a.dist_fare_amount.merge(&b.dist_fare_amount),
+        dist_duration: a.dist_duration.merge(&b.dist_duration),
+        dist_trip_distance: a.dist_trip_distance.merge(&b.dist_trip_distance),
+
+        breakdown: breakdown_merge(&a.breakdown, &b.breakdown),
+    }
+}
+
+/// Turns a partial aggregate into the same output `window_sum` produces.
+pub fn window_finish(acc: RideAccumulator) -> RideWindowedData {
+    RideWindowedData {
+        location_id: acc.location_id,
+        pu_time: acc.pu_time,
+
+        // Set by the window operator
+        window_start: 0,
+        window_end: 0,
+        revision: 0,
+
+        // Set after the window
+        direction: String::new(),
+        location_name: String::new(),
+        borough: String::new(),
+        zone: String::new(),
+
+        count: acc.count,
+        flagged_count: acc.flagged_count,
+
+        sum_fare_amount: acc.sum_fare_amount,
+        max_fare_amount: acc.max_fare_amount,
+        avg_fare_amount: average(acc.sum_fare_amount as f64, acc.count),
+        min_fare_amount: acc.min_fare_amount,
+
+        sum_tip_amount: acc.sum_tip_amount,
+        max_tip_amount: acc.max_tip_amount,
+        avg_tip_amount: average(acc.sum_tip_amount as f64, acc.count),
+        min_tip_amount: acc.min_tip_amount,
+
+        sum_total_amount: acc.sum_total_amount,
+        max_total_amount: acc.max_total_amount,
+        avg_total_amount: average(acc.sum_total_amount as f64, acc.count),
+        min_total_amount: acc.min_total_amount,
+
+        sum_tolls_amount: acc.sum_tolls_amount,
+        max_tolls_amount: acc.max_tolls_amount,
+        avg_tolls_amount: average(acc.sum_tolls_amount as f64, acc.count),
+        min_tolls_amount: acc.min_tolls_amount,
+
+        sum_extra: acc.sum_extra,
+        max_extra: acc.max_extra,
+        avg_extra: average(acc.sum_extra as f64, acc.count),
+        min_extra: acc.min_extra,
+
+        sum_mta_tax: acc.sum_mta_tax,
+        max_mta_tax: acc.max_mta_tax,
+        avg_mta_tax: average(acc.sum_mta_tax as f64, acc.count),
+        min_mta_tax: acc.min_mta_tax,
+
+        sum_improvement_surcharge: acc.sum_improvement_surcharge,
+        max_improvement_surcharge: acc.max_improvement_surcharge,
+        avg_improvement_surcharge: average(acc.sum_improvement_surcharge as f64, acc.count),
+        min_improvement_surcharge: acc.min_improvement_surcharge,
+
+        sum_congestion_surcharge: acc.sum_congestion_surcharge,
+        max_congestion_surcharge: acc.max_congestion_surcharge,
+        avg_congestion_surcharge: average(acc.sum_congestion_surcharge as f64, acc.count),
+        min_congestion_surcharge: acc.min_congestion_surcharge,
+
+        sum_trip_distance: acc.sum_trip_distance,
+        avg_trip_distance: average(acc.sum_trip_distance as f64, acc.count),
+
+        sum_passenger_count: acc.sum_passenger_count,
+        max_passenger_count: acc.max_passenger_count,
+        avg_passenger_count: average(acc.sum_passenger_count as f64, acc.count),
+        min_passenger_count: acc.min_passenger_count,
+
+        sum_duration: acc.sum_duration,
+        max_duration: acc.max_duration,
+        avg_duration: average(acc.sum_duration as f64, acc.count),
+        min_duration: acc.min_duration,
+
+        p50_fare_amount: acc.dist_fare_amount.quantile(0.5).round() as i64,
+        p90_fare_amount: acc.dist_fare_amount.quantile(0.9).round() as i64,
+        p99_fare_amount: acc.dist_fare_amount.quantile(0.99).round() as i64,
+        stddev_fare_amount: acc.dist_fare_amount.stddev() as f32,
+
+        p50_duration: acc.dist_duration.quantile(0.5).round() as u64,
+        p90_duration: acc.dist_duration.quantile(0.9).round() as u64,
+        p99_duration: acc.dist_duration.quantile(0.99).round() as u64,
+        stddev_duration: acc.dist_duration.stddev() as f32,
+
+        p50_trip_distance: acc.dist_trip_distance.quantile(0.5) as f32,
+        p90_trip_distance: acc.dist_trip_distance.quantile(0.9) as f32,
+        p99_trip_distance: acc.dist_trip_distance.quantile(0.99) as f32,
+        stddev_trip_distance: acc.dist_trip_distance.stddev() as f32,
+
+        tip_percentage: tip_percentage(acc.sum_tip_amount, acc.sum_fare_amount),
+        congestion_surcharge_share: share(acc.sum_congestion_surcharge, acc.sum_total_amount),
+
+        payment_credit_card_count: acc.breakdown.payment_credit_card_count,
+        payment_credit_card_revenue: acc.breakdown.payment_credit_card_revenue,
+        payment_cash_count: acc.breakdown.payment_cash_count,
+        payment_cash_revenue: acc.breakdown.payment_cash_revenue,
+        payment_no_charge_count: acc.breakdown.payment_no_charge_count,
+        payment_no_charge_revenue: acc.breakdown.payment_no_charge_revenue,
+        payment_dispute_count: acc.breakdown.payment_dispute_count,
+        payment_dispute_revenue: acc.breakdown.payment_dispute_revenue,
+        payment_unknown_count: acc.breakdown.payment_unknown_count,
+        payment_unknown_revenue: acc.breakdown.payment_unknown_revenue,
+        payment_voided_count: acc.breakdown.payment_voided_count,
+        payment_voided_revenue: acc.breakdown.payment_voided_revenue,
+
+        rate_standard_count: acc.breakdown.rate_standard_count,
+        rate_jfk_count: acc.breakdown.rate_jfk_count,
+        rate_newark_count: acc.breakdown.rate_newark_count,
+        rate_nassau_westchester_count: acc.breakdown.rate_nassau_westchester_count,
+        rate_negotiated_count: acc.breakdown.rate_negotiated_count,
+        rate_group_ride_count: acc.breakdown.rate_group_ride_count,
+        rate_unknown_count: acc.breakdown.rate_unknown_count,
+
+        vendor_creative_mobile_count: acc.breakdown.vendor_creative_mobile_count,
+        vendor_verifone_count: acc.breakdown.vendor_verifone_count,
+        vendor_unknown_count: acc.breakdown.vendor_unknown_count,
+    }
+}
+
+/// Breakdown of a single ride.
+fn breakdown_init(ride: &RideData) -> Breakdown {
+    let mut b = Breakdown::default();
+    breakdown_add(&mut b, ride);
+    b
+}
+
+/// Adds a ride to a breakdown in place.
+fn breakdown_add(b: &mut Breakdown, ride: &RideData) {
+    let revenue = ride.total_amount;
+    match ride.payment() {
+        PaymentType::CreditCard => {
+            b.payment_credit_card_count += 1;
+            b.payment_credit_card_revenue += revenue;
+        }
+        PaymentType::Cash => {
+            b.payment_cash_count += 1;
+            b.payment_cash_revenue += revenue;
+        }
+        PaymentType::NoCharge => {
+            b.payment_no_charge_count += 1;
+            b.payment_no_charge_revenue += revenue;
+        }
+        PaymentType::Dispute => {
+            b.payment_dispute_count += 1;
+            b.payment_dispute_revenue += revenue;
+        }
+        PaymentType::Unknown => {
+            b.payment_unknown_count += 1;
+            b.payment_unknown_revenue += revenue;
+        }
+        PaymentType::Voided => {
+            b.payment_voided_count += 1;
+            b.payment_voided_revenue += revenue;
+        }
+    }
+
+    match ride.rate_code() {
+        RateCode::Standard => b.rate_standard_count += 1,
+        RateCode::Jfk => b.rate_jfk_count += 1,
+        RateCode::Newark => b.rate_newark_count += 1,
+        RateCode::NassauWestchester => b.rate_nassau_westchester_count += 1,
+        RateCode::Negotiated => b.rate_negotiated_count += 1,
+        RateCode::GroupRide => b.rate_group_ride_count += 1,
+        RateCode::Unknown => b.rate_unknown_count += 1,
+    }
+
+    match ride.vendor() {
+        Vendor::CreativeMobile => b.vendor_creative_mobile_count += 1,
+        Vendor::VeriFone => b.vendor_verifone_count += 1,
+        Vendor::Unknown => b.vendor_unknown_count += 1,
+    }
+}
+
+fn breakdown_merge(a: &Breakdown, b: &Breakdown) -> Breakdown {
+    Breakdown {
+        payment_credit_card_count: a.payment_credit_card_count + b.payment_credit_card_count,
+        payment_credit_card_revenue: a.payment_credit_card_revenue + b.payment_credit_card_revenue,
+        payment_cash_count: a.payment_cash_count + b.payment_cash_count,
+        payment_cash_revenue: a.payment_cash_revenue + b.payment_cash_revenue,
+        payment_no_charge_count: a.payment_no_charge_count + b.payment_no_charge_count,
+        payment_no_charge_revenue: a.payment_no_charge_revenue + b.payment_no_charge_revenue,
+        payment_dispute_count: a.payment_dispute_count + b.payment_dispute_count,
+        payment_dispute_revenue: a.payment_dispute_revenue + b.payment_dispute_revenue,
+        payment_unknown_count: a.payment_unknown_count + b.payment_unknown_count,
+        payment_unknown_revenue: a.payment_unknown_revenue + b.payment_unknown_revenue,
+        payment_voided_count: a.payment_voided_count + b.payment_voided_count,
+        payment_voided_revenue: a.payment_voided_revenue + b.payment_voided_revenue,
+
+        rate_standard_count: a.rate_standard_count + b.rate_standard_count,
+        rate_jfk_count: a.rate_jfk_count + b.rate_jfk_count,
+        rate_newark_count: a.rate_newark_count + b.rate_newark_count,
+        rate_nassau_westchester_count: a.rate_nassau_westchester_count
+            + b.rate_nassau_westchester_count,
+        rate_negotiated_count: a.rate_negotiated_count + b.rate_negotiated_count,
+        rate_group_ride_count: a.rate_group_ride_count + b.rate_group_ride_count,
+        rate_unknown_count: a.rate_unknown_count + b.rate_unknown_count,
+
+        vendor_creative_mobile_count: a.vendor_creative_mobile_count
+            + b.vendor_creative_mobile_count,
+        vendor_verifone_count: a.vendor_verifone_count + b.vendor_verifone_count,
+        vendor_unknown_count: a.vendor_unknown_count + b.vendor_unknown_count,
+    }
+}
+
+fn tip_percentage(sum_tip_amount: i64, sum_fare_amount: i64) -> f32 {
+    share(sum_tip_amount, sum_fare_amount) * 100.0
+}
+
+/// Ratio of `part` to `total`, or 0 if there is no positive total.
+fn share(part: i64, total: i64) -> f32 {
+    if total > 0 {
+        part as f32 / total as f32
+    } else {
+        0.0
+    }
+}
+
+/// Starts a partial aggregate of a zone pair from the first ride of a window.
+pub fn od_init(ride: RideData) -> OdAccumulator {
+    OdAccumulator {
+        pu_location_id: ride.pu_location_id,
+        du_location_id: ride.du_location_id,
+        pu_time: ride.pu_time,
+        count: 1,
+        sum_fare_amount: ride.fare_amount,
+        sum_trip_distance: ride.trip_distance,
+        sum_duration: duration(&ride),
+    }
+}
+
+/// Adds a ride to a partial aggregate of a zone pair.
+pub fn od_aggregate(ride: RideData, acc: &OdAccumulator) -> OdAccumulator {
+    od_merge(acc, &od_init(ride))
+}
+
+/// Combines two partial aggregates of a zone pair, keeping the keys of the
+/// first one.
+pub fn od_merge(a: &OdAccumulator, b: &OdAccumulator) -> OdAccumulator {
+    OdAccumulator {
+        pu_location_id: a.pu_location_id,
+        du_location_id: a.du_location_id,
+        pu_time: a.pu_time,
+        count: a.count + b.count,
+        sum_fare_amount: a.sum_fare_amount + b.sum_fare_amount,
+        sum_trip_distance: a.sum_trip_distance + b.sum_trip_distance,
+        sum_duration: a.sum_duration + b.sum_duration,
+    }
+}
+
+pub fn od_finish(acc: OdAccumulator) -> OdWindowedData {
+    OdWindowedData {
+        pu_location_id: acc.pu_location_id,
+        du_location_id: acc.du_location_id,
+        pu_time: acc.pu_time,
+
+        // Set after the window
+        window_start: 0,
+        window_end: 0,
+        pu_location_name: String::new(),
+        du_location_name: String::new(),
+        rank: 0,
+
+        count: acc.count,
+        avg_fare_amount: average(acc.sum_fare_amount as f64, acc.count),
+        avg_trip_distance: average(acc.sum_trip_distance as f64, acc.count),
+        avg_duration: average(acc.sum_duration as f64, acc.count),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+    use std::collections::BTreeMap;
+    use std::convert::TryFrom;
+
+    /// Rides of `test_data` by pickup zone, in file order.
+    fn rides_by_zone() -> BTreeMap<u64, Vec<RideData>> {
+        let text = std::fs::read_to_string("test_data").unwrap();
+        let mut zones: BTreeMap<u64, Vec<RideData>> = BTreeMap::new();
+        for line in text.lines() {
+            let ride = parse::parse_taxi_ride(line).and_then(RideData::try_from);
+            if let Ok(ride) = ride {
+                zones.entry(ride.location_id).or_default().push(ride);
+            }
+        }
+        zones
+    }
+
+    #[test]
+    fn incremental_and_appender_windows_agree() {
+        let zones = rides_by_zone();
+        assert!(zones.len() > 1);
+        for rides in zones.values() {
+            let appender = serde_json::to_value(window_sum(rides)).unwrap();
+
+            // As folded by arcon's IncrementalWindow
+            let acc = rides[1..]
+                .iter()
+                .fold(window_init(rides[0].clone()), |acc, ride| {
+                    window_aggregate(ride.clone(), &acc)
+                });
+            let incremental = serde_json::to_value(window_finish(acc)).unwrap();
+            assert_eq!(incremental, appender);
+
+            // As updated by the session and updating windows
+            let mut acc = window_init(rides[0].clone());
+            for ride in &rides[1..] {
+                window_add(&mut acc, ride);
+            }
+            let in_place = serde_json::to_value(window_finish(acc)).unwrap();
+            assert_eq!(in_place, appender);
+        }
+    }
+
+    /// Cent totals of fare, extra, MTA tax, tip, tolls, improvement
+    /// surcharge, total and congestion surcharge of a window.
+    fn cents(window: &RideWindowedData) -> [i64; 8] {
+        [
+            window.sum_fare_amount,
+            window.sum_extra,
+            window.sum_mta_tax,
+            window.sum_tip_amount,
+            window.sum_tolls_amount,
+            window.sum_improvement_surcharge,
+            window.sum_total_amount,
+            window.sum_congestion_surcharge,
+        ]
+    }
+
+    fn accumulated(rides: &[RideData]) -> RideWindowedData {
+        let mut acc = window_init(rides[0].clone());
+        for ride in &rides[1..] {
+            window_add(&mut acc, ride);
+        }
+        window_finish(acc)
+    }
+
+    #[test]
+    fn test_data_amounts_add_up_to_the_cent() {
+        let rides: Vec<RideData> = rides_by_zone().into_iter().flat_map(|(_, r)| r).collect();
+        assert_eq!(rides.len(), 2000);
+
+        // Sums of the decimal amounts of every row of test_data
+        let expected = [
+            2_475_461, 272_380, 99_100, 410_420, 22_804, 59_550, 3_627_635, 460_500,
+        ];
+        assert_eq!(cents(&window_sum(&rides)), expected);
+        assert_eq!(cents(&accumulated(&rides)), expected);
+    }
+
+    #[test]
+    fn test_data_refunds_add_up_to_the_cent() {
+        let refunds: Vec<RideData> = rides_by_zone()
+            .into_iter()
+            .flat_map(|(_, r)| r)
+            .filter(|ride| ride.total_amount < 0)
+            .collect();
+        assert_eq!(refunds.len(), 7);
+
+        let expected = [-2750, -350, -350, 0, 0, -210, -5160, -1500];
+        assert_eq!(cents(&window_sum(&refunds)), expected);
+        assert_eq!(cents(&accumulated(&refunds)), expected);
+        assert_eq!(window_sum(&refunds).min_total_amount, -1230);
+        assert_eq!(window_sum(&refunds).max_total_amount, -380);
+    }
+
+    #[test]
+    fn fractional_amounts_are_parsed_to_the_cent() {
+        let line = std::fs::read_to_string("test_data").unwrap();
+        let first = line.lines().next().unwrap();
+        let ride = parse::parse_taxi_ride(first)
+            .and_then(RideData::try_from)
+            .unwrap();
+        // 6,3,0.5,1.47,0,0.3,11.27,2.5
+        assert_eq!(
+            (ride.fare_amount, ride.extra, ride.mta_tax, ride.tip_amount),
+            (600, 300, 50, 147)
+        );
+        assert_eq!(
+            (
+                ride.tolls_amount,
+                ride.improvement_surcharge,
+                ride.total_amount,
+                ride.congestion_surcharge
+            ),
+            (0, 30, 1127, 250)
+        );
     }
-    .to_owned()
 }
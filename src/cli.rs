@@ -0,0 +1,602 @@
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use std::str::FromStr;
+
+use crate::conf::{ConfError, TaxiConf};
+
+pub enum Command {
+    /// Build and run the pipeline
+    Run(TaxiConf),
+    /// Print the resolved configuration and exit
+    Config(TaxiConf),
+    /// Print the Elasticsearch index templates and exit
+    Mapping(TaxiConf),
+}
+
+fn override_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("source")
+            .long("source")
+            .takes_value(true)
+            .possible_values(&["file", "kafka"])
+            .help("Read rides from files or from Kafka"),
+        Arg::with_name("input")
+            .long("input")
+            .takes_value(true)
+            .help("TLC trip data file, directory or glob pattern"),
+        Arg::with_name("input-format")
+            .long("input-format")
+            .takes_value(true)
+            .possible_values(&["csv", "parquet"])
+            .help("Format of the input files"),
+        Arg::with_name("max-delay")
+            .long("max-delay")
+            .takes_value(true)
+            .help("Seconds of event time rides may arrive out of order before they are late"),
+        Arg::with_name("replay-speedup")
+            .long("replay-speedup")
+            .takes_value(true)
+            .help("Replay the input files at this multiple of real time, e.g. 3600"),
+        Arg::with_name("replay-shuffle")
+            .long("replay-shuffle")
+            .takes_value(true)
+            .help("Shuffle replayed rides within this many seconds of event time"),
+        Arg::with_name("replay-seed")
+            .long("replay-seed")
+            .takes_value(true)
+            .help("Seed of the shuffle of replayed rides"),
+        Arg::with_name("kafka-brokers")
+            .long("kafka-brokers")
+            .takes_value(true)
+            .help("Comma-separated host:port list of Kafka brokers"),
+        Arg::with_name("kafka-topic")
+            .long("kafka-topic")
+            .takes_value(true)
+            .help("Kafka topic of the trip records"),
+        Arg::with_name("kafka-group")
+            .long("kafka-group")
+            .takes_value(true)
+            .help("Prefix of the Kafka consumer group of each pipeline"),
+        Arg::with_name("kafka-payload")
+            .long("kafka-payload")
+            .takes_value(true)
+            .possible_values(&["csv", "protobuf"])
+            .help("Encoding of the Kafka messages"),
+        Arg::with_name("batch-size")
+            .long("batch-size")
+            .takes_value(true)
+            .help("Number of records per source batch"),
+        Arg::with_name("dead-letter")
+            .long("dead-letter")
+            .takes_value(true)
+            .help("File that rejected rows are appended to"),
+        Arg::with_name("window-kind")
+            .long("window-kind")
+            .takes_value(true)
+            .possible_values(&["tumbling", "sliding", "session"])
+            .help("Kind of window to aggregate over"),
+        Arg::with_name("window-length")
+            .long("window-length")
+            .takes_value(true)
+            .help("Window length in seconds"),
+        Arg::with_name("window-slide")
+            .long("window-slide")
+            .takes_value(true)
+            .help("Slide of sliding windows in seconds"),
+        Arg::with_name("session-gap")
+            .long("session-gap")
+            .takes_value(true)
+            .help("Inactivity gap closing a session window in seconds"),
+        Arg::with_name("late-arrival")
+            .long("late-arrival")
+            .takes_value(true)
+            .help("Allowed lateness in seconds"),
+        Arg::with_name("late-updates")
+            .long("late-updates")
+            .help("Emit windows again when late rides arrive within the allowed lateness"),
+        Arg::with_name("late-data")
+            .long("late-data")
+            .takes_value(true)
+            .help("File that rides too late for their windows are appended to"),
+        Arg::with_name("aggregation")
+            .long("aggregation")
+            .takes_value(true)
+            .possible_values(&["incremental", "appender"])
+            .help("Fold rides into an accumulator or buffer them per window"),
+        Arg::with_name("quality")
+            .long("quality")
+            .takes_value(true)
+            .possible_values(&["drop", "tag", "off"])
+            .help("What to do with rides that break a data-quality rule"),
+        Arg::with_name("max-duration")
+            .long("max-duration")
+            .takes_value(true)
+            .help("Longest valid trip in seconds"),
+        Arg::with_name("min-speed")
+            .long("min-speed")
+            .takes_value(true)
+            .help("Lowest valid average speed in miles per hour"),
+        Arg::with_name("max-speed")
+            .long("max-speed")
+            .takes_value(true)
+            .help("Highest valid average speed in miles per hour"),
+        Arg::with_name("min-passengers")
+            .long("min-passengers")
+            .takes_value(true)
+            .help("Fewest valid passengers"),
+        Arg::with_name("valid-from")
+            .long("valid-from")
+            .takes_value(true)
+            .help("Earliest valid pickup, as \"%Y-%m-%d %H:%M:%S\""),
+        Arg::with_name("valid-until")
+            .long("valid-until")
+            .takes_value(true)
+            .help("First pickup that is no longer valid, as \"%Y-%m-%d %H:%M:%S\""),
+        Arg::with_name("min-fare")
+            .long("min-fare")
+            .takes_value(true)
+            .help("Lowest valid fare in cents"),
+        Arg::with_name("max-fare")
+            .long("max-fare")
+            .takes_value(true)
+            .help("Highest valid fare in cents"),
+        Arg::with_name("directions")
+            .long("directions")
+            .takes_value(true)
+            .use_delimiter(true)
+            .possible_values(&["pickup", "dropoff"])
+            .help("Trip ends to aggregate by, comma separated"),
+        Arg::with_name("od")
+            .long("od")
+            .help("Also aggregate rides per (pickup, dropoff) zone pair"),
+        Arg::with_name("top-k")
+            .long("top-k")
+            .takes_value(true)
+            .help("Destinations kept per origin and window, 0 keeps all"),
+        Arg::with_name("zones")
+            .long("zones")
+            .takes_value(true)
+            .help("Taxi zone lookup CSV or GeoJSON file"),
+        Arg::with_name("es-url")
+            .long("es-url")
+            .takes_value(true)
+            .help("URL of the Elasticsearch node"),
+        Arg::with_name("es-username")
+            .long("es-username")
+            .takes_value(true)
+            .help("Elasticsearch user name"),
+        Arg::with_name("es-password")
+            .long("es-password")
+            .takes_value(true)
+            .env("ES_PASSWORD")
+            .hide_env_values(true)
+            .help("Elasticsearch password"),
+        Arg::with_name("es-ca-cert")
+            .long("es-ca-cert")
+            .takes_value(true)
+            .help("PEM file of the CA certificate of the Elasticsearch node"),
+        Arg::with_name("es-tls-insecure")
+            .long("es-tls-insecure")
+            .help("Accept any TLS certificate from Elasticsearch"),
+        Arg::with_name("index")
+            .long("index")
+            .takes_value(true)
+            .help("Index of the per-zone documents"),
+        Arg::with_name("od-index")
+            .long("od-index")
+            .takes_value(true)
+            .help("Index of the origin-destination documents"),
+        Arg::with_name("id-template")
+            .long("id-template")
+            .takes_value(true)
+            .help("Id of the per-zone documents, with {field} placeholders"),
+        Arg::with_name("od-id-template")
+            .long("od-id-template")
+            .takes_value(true)
+            .help("Id of the origin-destination documents, with {field} placeholders"),
+        Arg::with_name("index-date-suffix")
+            .long("index-date-suffix")
+            .takes_value(true)
+            .help("Write to time-based indices, suffixed with the window start in this format, e.g. %Y.%m"),
+        Arg::with_name("data-stream")
+            .long("data-stream")
+            .help("Write to Elasticsearch data streams instead of indices"),
+        Arg::with_name("no-index-templates")
+            .long("no-index-templates")
+            .help("Do not create or verify the Elasticsearch index templates at startup"),
+        Arg::with_name("max-retries")
+            .long("max-retries")
+            .takes_value(true)
+            .help("Retries of a failed Elasticsearch request or document"),
+        Arg::with_name("retry-backoff")
+            .long("retry-backoff")
+            .takes_value(true)
+            .help("Wait in milliseconds before the first retry, doubled on every following one"),
+        Arg::with_name("sink-dead-letter")
+            .long("sink-dead-letter")
+            .takes_value(true)
+            .help("File that documents which could not be indexed are appended to"),
+        Arg::with_name("bulk-size")
+            .long("bulk-size")
+            .takes_value(true)
+            .help("Number of documents sent per Elasticsearch bulk request"),
+        Arg::with_name("flush-interval")
+            .long("flush-interval")
+            .takes_value(true)
+            .help("Seconds of event time documents are buffered at most"),
+        Arg::with_name("epoch-interval")
+            .long("epoch-interval")
+            .takes_value(true)
+            .help("Epoch interval in milliseconds"),
+        Arg::with_name("watermark-interval")
+            .long("watermark-interval")
+            .takes_value(true)
+            .help("Watermark interval in milliseconds"),
+        Arg::with_name("allocator-capacity")
+            .long("allocator-capacity")
+            .takes_value(true)
+            .help("Capacity in bytes of the arcon allocator"),
+        Arg::with_name("state-dir")
+            .long("state-dir")
+            .takes_value(true)
+            .help("Directory for operator state"),
+        Arg::with_name("query-listen")
+            .long("query-listen")
+            .takes_value(true)
+            .help("Address of the HTTP query API"),
+        Arg::with_name("no-query")
+            .long("no-query")
+            .conflicts_with("query-listen")
+            .help("Do not serve the HTTP query API"),
+        Arg::with_name("query-retention")
+            .long("query-retention")
+            .takes_value(true)
+            .help("Seconds of event time that windows can be queried after the latest one"),
+    ]
+}
+
+fn app<'a, 'b>() -> App<'a, 'b> {
+    App::new("arcon_taxi")
+        .about("Windowed aggregation of NYC TLC taxi trips on arcon")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .arg(
+            Arg::with_name("config")
+                .short("c")
+                .long("config")
+                .takes_value(true)
+                .global(true)
+                .help("TOML or YAML config file"),
+        )
+        .subcommand(
+            SubCommand::with_name("run")
+                .about("Run the pipeline")
+                .args(&override_args()),
+        )
+        .subcommand(
+            SubCommand::with_name("config")
+                .about("Print the resolved configuration and exit")
+                .args(&override_args()),
+        )
+        .subcommand(
+            SubCommand::with_name("mapping")
+                .about("Print the Elasticsearch index templates and exit")
+                .args(&override_args()),
+        )
+}
+
+fn parse<T: FromStr>(matches: &ArgMatches, name: &str) -> Result<Option<T>, ConfError> {
+    match matches.value_of(name) {
+        Some(value) => value
+            .parse()
+            .map(Some)
+            .map_err(|_| ConfError::Invalid(format!("invalid value '{}' for --{}", value, name))),
+        None => Ok(None),
+    }
+}
+
+fn resolve(matches: &ArgMatches) -> Result<TaxiConf, ConfError> {
+    let mut conf = match matches.value_of("config") {
+        Some(path) => TaxiConf::from_file(path)?,
+        None => TaxiConf::default(),
+    };
+
+    if let Some(kind) = parse(matches, "source")? {
+        conf.source.kind = kind;
+    }
+    if let Some(input) = matches.value_of("input") {
+        conf.source.path = input.into();
+    }
+    if let Some(format) = parse(matches, "input-format")? {
+        conf.source.format = format;
+    }
+    if let Some(max_delay) = parse(matches, "max-delay")? {
+        conf.source.max_delay = max_delay;
+    }
+    if let Some(speedup) = parse(matches, "replay-speedup")? {
+        conf.source.replay_speedup = Some(speedup);
+    }
+    if let Some(shuffle) = parse(matches, "replay-shuffle")? {
+        conf.source.replay_shuffle = shuffle;
+    }
+    if let Some(seed) = parse(matches, "replay-seed")? {
+        conf.source.replay_seed = seed;
+    }
+    if let Some(brokers) = matches.value_of("kafka-brokers") {
+        conf.kafka.brokers = brokers.to_owned();
+    }
+    if let Some(topic) = matches.value_of("kafka-topic") {
+        conf.kafka.topic = topic.to_owned();
+    }
+    if let Some(group_id) = matches.value_of("kafka-group") {
+        conf.kafka.group_id = group_id.to_owned();
+    }
+    if let Some(payload) = parse(matches, "kafka-payload")? {
+        conf.kafka.payload = payload;
+    }
+    if let Some(batch_size) = parse(matches, "batch-size")? {
+        conf.source.batch_size = batch_size;
+    }
+    if let Some(dead_letter) = matches.value_of("dead-letter") {
+        conf.source.dead_letter = dead_letter.into();
+    }
+    if let Some(kind) = parse(matches, "window-kind")? {
+        conf.window.kind = kind;
+    }
+    if let Some(length) = parse(matches, "window-length")? {
+        conf.window.length = length;
+    }
+    if let Some(slide) = parse(matches, "window-slide")? {
+        conf.window.slide = slide;
+    }
+    if let Some(gap) = parse(matches, "session-gap")? {
+        conf.window.gap = gap;
+    }
+    if let Some(late_arrival) = parse(matches, "late-arrival")? {
+        conf.window.late_arrival = late_arrival;
+    }
+    if matches.is_present("late-updates") {
+        conf.window.late_updates = true;
+    }
+    if let Some(late_data) = matches.value_of("late-data") {
+        conf.window.late_data = Some(late_data.into());
+    }
+    if let Some(aggregation) = parse(matches, "aggregation")? {
+        conf.window.aggregation = aggregation;
+    }
+    if let Some(action) = parse(matches, "quality")? {
+        conf.quality.action = action;
+    }
+    if let Some(max_duration) = parse(matches, "max-duration")? {
+        conf.quality.max_duration = max_duration;
+    }
+    if let Some(min_speed) = parse(matches, "min-speed")? {
+        conf.quality.min_speed = min_speed;
+    }
+    if let Some(max_speed) = parse(matches, "max-speed")? {
+        conf.quality.max_speed = max_speed;
+    }
+    if let Some(min_passengers) = parse(matches, "min-passengers")? {
+        conf.quality.min_passengers = min_passengers;
+    }
+    if let Some(valid_from) = matches.value_of("valid-from") {
+        conf.quality.valid_from = Some(valid_from.to_owned());
+    }
+    if let Some(valid_until) = matches.value_of("valid-until") {
+        conf.quality.valid_until = Some(valid_until.to_owned());
+    }
+    if let Some(min_fare) = parse(matches, "min-fare")? {
+        conf.quality.min_fare = min_fare;
+    }
+    if let Some(max_fare) = parse(matches, "max-fare")? {
+        conf.quality.max_fare = max_fare;
+    }
+    if let Some(directions) = matches.values_of("directions") {
+        conf.aggregate.directions = directions
+            .map(|d| d.parse().map_err(ConfError::Invalid))
+            .collect::<Result<_, _>>()?;
+    }
+    if matches.is_present("od") {
+        conf.aggregate.od = true;
+    }
+    if let Some(top_k) = parse(matches, "top-k")? {
+        conf.aggregate.top_k = top_k;
+    }
+    if let Some(zones) = matches.value_of("zones") {
+        conf.zones.path = zones.into();
+    }
+    if let Some(url) = matches.value_of("es-url") {
+        conf.sink.url = url.to_owned();
+    }
+    if let Some(username) = matches.value_of("es-username") {
+        conf.sink.username = Some(username.to_owned());
+    }
+    if let Some(password) = matches.value_of("es-password") {
+        conf.sink.password = Some(password.to_owned());
+    }
+    if let Some(ca_cert) = matches.value_of("es-ca-cert") {
+        conf.sink.ca_cert = Some(ca_cert.into());
+    }
+    if matches.is_present("es-tls-insecure") {
+        conf.sink.tls_insecure = true;
+    }
+    if let Some(index) = matches.value_of("index") {
+        conf.sink.index = index.to_owned();
+    }
+    if let Some(od_index) = matches.value_of("od-index") {
+        conf.sink.od_index = od_index.to_owned();
+    }
+    if let Some(id_template) = matches.value_of("id-template") {
+        conf.sink.id_template = id_template.to_owned();
+    }
+    if let Some(od_id_template) = matches.value_of("od-id-template") {
+        conf.sink.od_id_template = od_id_template.to_owned();
+    }
+    if let Some(suffix) = matches.value_of("index-date-suffix") {
+        conf.sink.index_date_suffix = Some(suffix.to_owned());
+    }
+    if matches.is_present("data-stream") {
+        conf.sink.data_stream = true;
+    }
+    if matches.is_present("no-index-templates") {
+        conf.sink.index_templates = false;
+    }
+    if let Some(max_retries) = parse(matches, "max-retries")? {
+        conf.sink.max_retries = max_retries;
+    }
+    if let Some(retry_backoff) = parse(matches, "retry-backoff")? {
+        conf.sink.retry_backoff = retry_backoff;
+    }
+    if let Some(dead_letter) = matches.value_of("sink-dead-letter") {
+        conf.sink.dead_letter = dead_letter.into();
+    }
+    if let Some(bulk_size) = parse(matches, "bulk-size")? {
+        conf.sink.bulk_size = bulk_size;
+    }
+    if let Some(flush_interval) = parse(matches, "flush-interval")? {
+        conf.sink.flush_interval = flush_interval;
+    }
+    if let Some(epoch_interval) = parse(matches, "epoch-interval")? {
+        conf.arcon.epoch_interval = epoch_interval;
+    }
+    if let Some(watermark_interval) = parse(matches, "watermark-interval")? {
+        conf.arcon.watermark_interval = watermark_interval;
+    }
+    if let Some(allocator_capacity) = parse(matches, "allocator-capacity")? {
+        conf.arcon.allocator_capacity = allocator_capacity;
+    }
+    if let Some(state_dir) = matches.value_of("state-dir") {
+        conf.arcon.state_dir = Some(state_dir.into());
+    }
+    if let Some(listen) = matches.value_of("query-listen") {
+        conf.query.listen = Some(listen.to_owned());
+    }
+    if matches.is_present("no-query") {
+        conf.query.listen = None;
+    }
+    if let Some(retention) = parse(matches, "query-retention")? {
+        conf.query.retention = retention;
+    }
+
+    conf.validate()?;
+    Ok(conf)
+}
+
+/// Parses the command line into a command with a fully resolved config.
+pub fn parse_args() -> Result<Command, ConfError> {
+    let matches = app().get_matches();
+    match matches.subcommand() {
+        ("run", Some(sub)) => resolve(sub).map(Command::Run),
+        ("config", Some(sub)) => resolve(sub).map(Command::Config),
+        ("mapping", Some(sub)) => resolve(sub).map(Command::Mapping),
+        _ => unreachable!("clap requires a subcommand"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::window::WindowKind;
+    use std::path::PathBuf;
+
+    fn config_file(name: &str, contents: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("{}_{}", std::process::id(), name));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn resolve_args(args: &[&str]) -> Result<TaxiConf, ConfError> {
+        let matches = app()
+            .get_matches_from_safe(std::iter::once("arcon_taxi").chain(args.iter().copied()))
+            .unwrap();
+        resolve(matches.subcommand_matches("config").unwrap())
+    }
+
+    #[test]
+    fn command_line_overrides_the_config_file() {
+        let toml = config_file(
+            "precedence.toml",
+            "[window]\nkind = \"sliding\"\nlength = 3600\nslide = 300\n\n\
+             [source]\nmax_delay = 600\n\n[sink]\nbulk_size = 100\n",
+        );
+        let conf = resolve_args(&[
+            "config",
+            "--config",
+            toml.to_str().unwrap(),
+            "--window-slide",
+            "900",
+            "--bulk-size",
+            "50",
+        ])
+        .unwrap();
+        // From the file
+        assert_eq!(conf.window.kind, WindowKind::Sliding);
+        assert_eq!(conf.window.length, 3600);
+        assert_eq!(conf.source.max_delay, 600);
+        // From the command line
+        assert_eq!(conf.window.slide, 900);
+        assert_eq!(conf.sink.bulk_size, 50);
+        // Defaults
+        assert_eq!(conf.window.late_arrival, 86400);
+        assert_eq!(conf.source.batch_size, 4000);
+
+        let yaml = config_file(
+            "precedence.yaml",
+            "window:\n  kind: tumbling\n  length: 900\n  slide: 900\n",
+        );
+        let conf = resolve_args(&[
+            "config",
+            "-c",
+            yaml.to_str().unwrap(),
+            "--window-length",
+            "1800",
+            "--window-slide",
+            "1800",
+        ])
+        .unwrap();
+        assert_eq!(conf.window.kind, WindowKind::Tumbling);
+        assert_eq!(conf.window.length, 1800);
+        assert_eq!(conf.window.slide, 1800);
+
+        std::fs::remove_file(toml).unwrap();
+        std::fs::remove_file(yaml).unwrap();
+    }
+
+    #[test]
+    fn invalid_configs_are_rejected() {
+        // Validated after the overrides, so the command line can fix or break the file
+        let toml = config_file(
+            "invalid.toml",
+            "[window]\nkind = \"sliding\"\nlength = 3600\nslide = 300\n",
+        );
+        let path = toml.to_str().unwrap();
+        match resolve_args(&["config", "--config", path, "--window-length", "60"]) {
+            Err(ConfError::Invalid(msg)) => {
+                assert_eq!(msg, "window.slide must be > 0 and <= window.length")
+            }
+            other => panic!("expected an invalid window, got {:?}", other),
+        }
+        assert!(resolve_args(&["config", "--config", path, "--window-slide", "60"]).is_ok());
+
+        match resolve_args(&["config", "--window-length", "an hour"]) {
+            Err(ConfError::Invalid(msg)) => {
+                assert_eq!(msg, "invalid value 'an hour' for --window-length")
+            }
+            other => panic!("expected an invalid value, got {:?}", other),
+        }
+
+        let unknown_field = config_file("unknown_field.toml", "[window]\nlenght = 3600\n");
+        match resolve_args(&["config", "--config", unknown_field.to_str().unwrap()]) {
+            Err(ConfError::Toml(..)) => {}
+            other => panic!("expected a TOML error, got {:?}", other),
+        }
+
+        let unknown_format = config_file("unknown_format.ini", "length = 3600\n");
+        match resolve_args(&["config", "--config", unknown_format.to_str().unwrap()]) {
+            Err(ConfError::UnknownFormat(_)) => {}
+            other => panic!("expected an unknown format, got {:?}", other),
+        }
+
+        for path in &[toml, unknown_field, unknown_format] {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+}
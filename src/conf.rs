@@ -0,0 +1,634 @@
+use arcon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::data::{self, Direction};
+use crate::kafka::Payload;
+use crate::ops;
+use crate::quality::{QualityAction, QualityRules};
+use crate::source::{InputFormat, SourceKind};
+use crate::window::{Aggregation, WindowKind};
+
+const DAY: u64 = 24 * 60 * 60;
+
+/// Resolved configuration of the taxi pipeline.
+///
+/// Values are taken from the defaults below, then from an optional
+/// TOML/YAML config file and finally from command-line overrides.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TaxiConf {
+    pub arcon: ArconSection,
+    pub source: SourceSection,
+    pub kafka: KafkaSection,
+    pub window: WindowSection,
+    pub quality: QualitySection,
+    pub aggregate: AggregateSection,
+    pub zones: ZoneSection,
+    pub sink: SinkSection,
+    pub query: QuerySection,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ArconSection {
+    /// Interval in milliseconds between epochs
+    pub epoch_interval: u64,
+    /// Interval in milliseconds between watermarks
+    pub watermark_interval: u64,
+    /// Host of the control system used by arcon_shell
+    pub ctrl_system_host: Option<String>,
+    /// Capacity in bytes of the arcon allocator, shared by the pipelines
+    pub allocator_capacity: usize,
+    /// State directory, defaults to the OS tempdir if not set
+    pub state_dir: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SourceSection {
+    /// Whether rides are read from files or from Kafka
+    pub kind: SourceKind,
+    /// TLC trip data file, directory of files, or glob pattern of files,
+    /// which are merged by pickup time
+    pub path: PathBuf,
+    /// Whether the input files are CSV or Parquet
+    pub format: InputFormat,
+    /// Replay speed of the input files as a multiple of real time, or as
+    /// fast as they can be read if unset
+    pub replay_speedup: Option<f64>,
+    /// Seconds of event time by which replayed rides are shuffled at most
+    pub replay_shuffle: u64,
+    /// Seed of the shuffle, so that a restarted pipeline replays rides in
+    /// the same order
+    pub replay_seed: u64,
+    /// Seconds of event time that rides may arrive out of order before
+    /// they are late
+    pub max_delay: u64,
+    /// Number of records the file source emits per batch
+    pub batch_size: usize,
+    /// File that rejected rows are appended to
+    pub dead_letter: PathBuf,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KafkaSection {
+    /// Comma-separated `host:port` list of brokers
+    pub brokers: String,
+    /// Topic of the trip records
+    pub topic: String,
+    /// Prefix of the consumer group of each pipeline
+    pub group_id: String,
+    /// Whether messages are CSV rows or protobuf `TaxiRideData`
+    pub payload: Payload,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WindowSection {
+    /// Tumbling, sliding or session windows
+    pub kind: WindowKind,
+    /// Window length in seconds, unused for session windows
+    pub length: u64,
+    /// Slide in seconds of sliding windows
+    pub slide: u64,
+    /// Inactivity gap in seconds that closes a session window
+    pub gap: u64,
+    /// Allowed lateness in seconds, after the end of a window or after a
+    /// session closed
+    pub late_arrival: u64,
+    /// Whether late rides within the allowed lateness emit their windows
+    /// again, for incremental tumbling and sliding windows per zone
+    pub late_updates: bool,
+    /// File that rides too late for all their windows are appended to
+    pub late_data: Option<PathBuf>,
+    /// Whether tumbling and sliding windows aggregate incrementally or
+    /// buffer their rides
+    pub aggregation: Aggregation,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct QualitySection {
+    /// Whether rides breaking a rule are dropped, tagged or not checked
+    pub action: QualityAction,
+    /// Longest trip in seconds
+    pub max_duration: u64,
+    /// Lowest average speed in miles per hour
+    pub min_speed: f32,
+    /// Highest average speed in miles per hour
+    pub max_speed: f32,
+    /// Fewest passengers
+    pub min_passengers: u64,
+    /// Earliest pickup, as New York local `%Y-%m-%d %H:%M:%S`
+    pub valid_from: Option<String>,
+    /// Pickup at which rides are no longer valid, as New York local
+    /// `%Y-%m-%d %H:%M:%S`
+    pub valid_until: Option<String>,
+    /// Lowest fare in cents
+    pub min_fare: i64,
+    /// Highest fare in cents
+    pub max_fare: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AggregateSection {
+    /// Trip ends to aggregate by, all in the same pipeline
+    pub directions: Vec<Direction>,
+    /// Whether to also run the origin-destination pipeline
+    pub od: bool,
+    /// Number of destinations kept per origin and window by the
+    /// origin-destination pipeline, 0 keeps all of them
+    pub top_k: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ZoneSection {
+    /// TLC `taxi_zone_lookup.csv` or taxi zone GeoJSON file
+    pub path: PathBuf,
+}
+
+/// Elasticsearch sink settings. `Debug` is written by hand so that the
+/// password is never printed.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SinkSection {
+    /// URL of the Elasticsearch node
+    pub url: String,
+    /// Basic authentication user name
+    pub username: Option<String>,
+    /// Basic authentication password, never printed with the config
+    #[serde(skip_serializing)]
+    pub password: Option<String>,
+    /// PEM file of the CA certificate that signed the node's certificate
+    pub ca_cert: Option<PathBuf>,
+    /// Accept any TLS certificate, for development clusters only
+    pub tls_insecure: bool,
+    /// Index of the per-zone documents
+    pub index: String,
+    /// Index of the origin-destination documents
+    pub od_index: String,
+    /// Id of the per-zone documents, with `{field}` placeholders
+    pub id_template: String,
+    /// Id of the origin-destination documents, with `{field}` placeholders
+    pub od_id_template: String,
+    /// chrono format of the New York local window start appended to the
+    /// index names, for time-based indices like `arcon_data_stream-2020.01`
+    pub index_date_suffix: Option<String>,
+    /// Whether the indices are Elasticsearch data streams
+    pub data_stream: bool,
+    /// Whether to create or verify the index templates at startup
+    pub index_templates: bool,
+    /// Number of documents sent per `_bulk` request
+    pub bulk_size: usize,
+    /// Seconds of event time a document is buffered at most before it is
+    /// sent
+    pub flush_interval: u64,
+    /// Retries of a failed request or document before it is given up on
+    pub max_retries: u32,
+    /// Wait in milliseconds before the first retry, doubled on every
+    /// following one
+    pub retry_backoff: u64,
+    /// File that documents which could not be indexed are appended to
+    pub dead_letter: PathBuf,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct QuerySection {
+    /// Address of the HTTP query API, or none to not serve it
+    pub listen: Option<String>,
+    /// Seconds of event time that windows can be queried after the latest
+    /// window of their direction ended
+    pub retention: u64,
+}
+
+impl Default for ArconSection {
+    fn default() -> Self {
+        Self {
+            epoch_interval: 20_000,
+            watermark_interval: 1000,
+            ctrl_system_host: Some("127.0.0.1:2000".to_string()),
+            allocator_capacity: 2147483648,
+            state_dir: None,
+        }
+    }
+}
+
+impl Default for SourceSection {
+    fn default() -> Self {
+        Self {
+            kind: SourceKind::File,
+            path: PathBuf::from("data/yellow_tripdata_2020-*.csv"),
+            format: InputFormat::Csv,
+            replay_speedup: None,
+            replay_shuffle: 0,
+            replay_seed: 0,
+            max_delay: 3600,
+            batch_size: 4000,
+            dead_letter: PathBuf::from("rejected_rows.tsv"),
+        }
+    }
+}
+
+impl Default for KafkaSection {
+    fn default() -> Self {
+        Self {
+            brokers: "localhost:9092".to_string(),
+            topic: "taxi_rides".to_string(),
+            group_id: "arcon_taxi".to_string(),
+            payload: Payload::Csv,
+        }
+    }
+}
+
+impl Default for QuerySection {
+    fn default() -> Self {
+        Self {
+            listen: Some("127.0.0.1:8080".to_string()),
+            retention: 30 * DAY,
+        }
+    }
+}
+
+impl Default for WindowSection {
+    fn default() -> Self {
+        Self {
+            kind: WindowKind::Tumbling,
+            length: DAY,
+            slide: DAY,
+            gap: 30 * 60,
+            late_arrival: DAY,
+            late_updates: false,
+            late_data: None,
+            aggregation: Aggregation::Incremental,
+        }
+    }
+}
+
+impl Default for QualitySection {
+    fn default() -> Self {
+        Self {
+            action: QualityAction::Drop,
+            max_duration: 6 * 60 * 60,
+            min_speed: 0.0,
+            max_speed: 100.0,
+            min_passengers: 1,
+            valid_from: None,
+            valid_until: None,
+            min_fare: 0,
+            max_fare: 100_000,
+        }
+    }
+}
+
+impl Default for AggregateSection {
+    fn default() -> Self {
+        Self {
+            directions: vec![Direction::Pickup, Direction::Dropoff],
+            od: false,
+            top_k: 10,
+        }
+    }
+}
+
+impl Default for ZoneSection {
+    fn default() -> Self {
+        Self {
+            path: PathBuf::from("visualization/nyu-geojson.json"),
+        }
+    }
+}
+
+impl fmt::Debug for SinkSection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SinkSection")
+            .field("url", &self.url)
+            .field("username", &self.username)
+            .field("password", &self.password.as_ref().map(|_| "<redacted>"))
+            .field("ca_cert", &self.ca_cert)
+            .field("tls_insecure", &self.tls_insecure)
+            .field("index", &self.index)
+            .field("od_index", &self.od_index)
+            .field("id_template", &self.id_template)
+            .field("od_id_template", &self.od_id_template)
+            .field("index_date_suffix", &self.index_date_suffix)
+            .field("data_stream", &self.data_stream)
+            .field("index_templates", &self.index_templates)
+            .field("bulk_size", &self.bulk_size)
+            .field("flush_interval", &self.flush_interval)
+            .field("max_retries", &self.max_retries)
+            .field("retry_backoff", &self.retry_backoff)
+            .field("dead_letter", &self.dead_letter)
+            .finish()
+    }
+}
+
+impl Default for SinkSection {
+    fn default() -> Self {
+        Self {
+            url: "http://localhost:9200".to_string(),
+            username: None,
+            password: None,
+            ca_cert: None,
+            tls_insecure: false,
+            index: "arcon_data_stream".to_string(),
+            od_index: "arcon_od_stream".to_string(),
+            id_template: "{direction}_location_{location_id}_{window_start}".to_string(),
+            od_id_template: "od_{pu_location_id}_{du_location_id}_{window_start}".to_string(),
+            index_date_suffix: None,
+            data_stream: false,
+            index_templates: true,
+            bulk_size: 500,
+            flush_interval: 60 * 60,
+            max_retries: 5,
+            retry_backoff: 500,
+            dead_letter: PathBuf::from("failed_documents.tsv"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfError {
+    Io(PathBuf, std::io::Error),
+    Toml(PathBuf, toml::de::Error),
+    Yaml(PathBuf, serde_yaml::Error),
+    UnknownFormat(PathBuf),
+    Invalid(String),
+}
+
+impl fmt::Display for ConfError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfError::Io(path, err) => write!(f, "failed to read {}: {}", path.display(), err),
+            ConfError::Toml(path, err) => write!(f, "invalid TOML in {}: {}", path.display(), err),
+            ConfError::Yaml(path, err) => write!(f, "invalid YAML in {}: {}", path.display(), err),
+            ConfError::UnknownFormat(path) => write!(
+                f,
+                "unknown config format for {}, expected .toml, .yaml or .yml",
+                path.display()
+            ),
+            ConfError::Invalid(msg) => write!(f, "invalid configuration: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for ConfError {}
+
+impl TaxiConf {
+    /// Loads a config file, picking the format from its extension.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, ConfError> {
+        let path = path.as_ref();
+        let contents =
+            std::fs::read_to_string(path).map_err(|e| ConfError::Io(path.to_owned(), e))?;
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => {
+                toml::from_str(&contents).map_err(|e| ConfError::Toml(path.to_owned(), e))
+            }
+            Some("yaml") | Some("yml") => {
+                serde_yaml::from_str(&contents).map_err(|e| ConfError::Yaml(path.to_owned(), e))
+            }
+            _ => Err(ConfError::UnknownFormat(path.to_owned())),
+        }
+    }
+
+    /// Checks values that would otherwise make the pipeline misbehave at runtime.
+    pub fn validate(&self) -> Result<(), ConfError> {
+        if self.source.batch_size == 0 {
+            return Err(ConfError::Invalid("source.batch_size must be > 0".into()));
+        }
+        if let Some(speedup) = self.source.replay_speedup {
+            if !speedup.is_finite() || speedup <= 0.0 {
+                return Err(ConfError::Invalid(
+                    "source.replay_speedup must be > 0".into(),
+                ));
+            }
+        }
+        if self.source.kind == SourceKind::Kafka
+            && (self.kafka.brokers.is_empty() || self.kafka.topic.is_empty())
+        {
+            return Err(ConfError::Invalid(
+                "kafka.brokers and kafka.topic must not be empty".into(),
+            ));
+        }
+        if self.aggregate.directions.is_empty() && !self.aggregate.od {
+            return Err(ConfError::Invalid(
+                "aggregate.directions must not be empty unless aggregate.od is set".into(),
+            ));
+        }
+        if self.aggregate.od && self.window.kind == WindowKind::Session {
+            return Err(ConfError::Invalid(
+                "aggregate.od requires tumbling or sliding windows".into(),
+            ));
+        }
+        match self.window.kind {
+            WindowKind::Tumbling if self.window.length == 0 => {
+                return Err(ConfError::Invalid("window.length must be > 0".into()));
+            }
+            WindowKind::Sliding
+                if self.window.slide == 0 || self.window.slide > self.window.length =>
+            {
+                return Err(ConfError::Invalid(
+                    "window.slide must be > 0 and <= window.length".into(),
+                ));
+            }
+            WindowKind::Session if self.window.gap == 0 => {
+                return Err(ConfError::Invalid("window.gap must be > 0".into()));
+            }
+            _ => (),
+        }
+        if self.sink.bulk_size == 0 {
+            return Err(ConfError::Invalid("sink.bulk_size must be > 0".into()));
+        }
+        if self.sink.flush_interval == 0 {
+            return Err(ConfError::Invalid("sink.flush_interval must be > 0".into()));
+        }
+        if self.sink.index.is_empty() || self.sink.od_index.is_empty() {
+            return Err(ConfError::Invalid(
+                "sink.index and sink.od_index must not be empty".into(),
+            ));
+        }
+        if self.sink.data_stream && self.sink.index_date_suffix.is_some() {
+            return Err(ConfError::Invalid(
+                "sink.index_date_suffix cannot be used with sink.data_stream".into(),
+            ));
+        }
+        if self.window.late_updates
+            && (self.window.kind == WindowKind::Session
+                || self.window.aggregation != Aggregation::Incremental)
+        {
+            return Err(ConfError::Invalid(
+                "window.late_updates requires incremental tumbling or sliding windows".into(),
+            ));
+        }
+        if self.window.late_updates && self.sink.data_stream {
+            return Err(ConfError::Invalid(
+                "window.late_updates cannot be used with sink.data_stream, \
+                 which cannot replace documents"
+                    .into(),
+            ));
+        }
+        self.quality_rules()?;
+        if self.quality.min_speed > self.quality.max_speed
+            || self.quality.min_fare > self.quality.max_fare
+        {
+            return Err(ConfError::Invalid(
+                "quality minimums must not be above their maximums".into(),
+            ));
+        }
+        if self.arcon.epoch_interval == 0 || self.arcon.watermark_interval == 0 {
+            return Err(ConfError::Invalid(
+                "arcon.epoch_interval and arcon.watermark_interval must be > 0".into(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Names of the pipelines to run: `zones` for the per-zone windows of
+    /// every direction, and `od` for the origin-destination windows.
+    pub fn pipelines(&self) -> Vec<&'static str> {
+        let mut pipelines = Vec::new();
+        if !self.aggregate.directions.is_empty() {
+            pipelines.push("zones");
+        }
+        if self.aggregate.od {
+            pipelines.push("od");
+        }
+        pipelines
+    }
+
+    /// Side-output file of the pipeline with the given name.
+    ///
+    /// Every pipeline reads the input on its own and has its own watermark,
+    /// so each writes its own rejected rows, late rides and failed
+    /// documents. The first pipeline writes to `path` itself, and the others
+    /// to `path` with their name added to the file stem, like
+    /// `rejected_rows-od.tsv`.
+    pub fn side_output(&self, path: &Path, name: &str) -> PathBuf {
+        if self.pipelines().first() == Some(&name) {
+            return path.to_owned();
+        }
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        let file = match path.extension() {
+            Some(ext) => format!("{}-{}.{}", stem, name, ext.to_string_lossy()),
+            None => format!("{}-{}", stem, name),
+        };
+        path.with_file_name(file)
+    }
+
+    /// Builds the `ArconConf` of the pipeline with the given name.
+    ///
+    /// Every pipeline gets its own state directory below the configured one,
+    /// and an equal share of the allocator capacity. Only the first pipeline
+    /// binds the control system host, as pipelines in the same process cannot
+    /// share its port.
+    pub fn arcon_conf(&self, name: &str) -> ArconConf {
+        let pipelines = self.pipelines();
+        let first = pipelines.first() == Some(&name);
+        let state_dir = self
+            .arcon
+            .state_dir
+            .clone()
+            .unwrap_or_else(|| std::env::temp_dir().join("arcon"));
+        ArconConf {
+            epoch_interval: self.arcon.epoch_interval,
+            watermark_interval: self.arcon.watermark_interval,
+            ctrl_system_host: self.arcon.ctrl_system_host.clone().filter(|_| first),
+            allocator_capacity: self.arcon.allocator_capacity / pipelines.len().max(1),
+            state_dir: state_dir.join(name),
+            ..Default::default()
+        }
+    }
+
+    /// Data-quality rules with the date range resolved to event times.
+    pub fn quality_rules(&self) -> Result<QualityRules, ConfError> {
+        let time = |name: &str, value: &Option<String>| match value {
+            Some(value) => data::datetime_to_u64(value).map(Some).map_err(|_| {
+                ConfError::Invalid(format!(
+                    "quality.{} must be a %Y-%m-%d %H:%M:%S datetime, got '{}'",
+                    name, value
+                ))
+            }),
+            None => Ok(None),
+        };
+        Ok(QualityRules {
+            max_duration: self.quality.max_duration,
+            min_speed: self.quality.min_speed,
+            max_speed: self.quality.max_speed,
+            min_passengers: self.quality.min_passengers,
+            valid_from: time("valid_from", &self.quality.valid_from)?,
+            valid_until: time("valid_until", &self.quality.valid_until)?,
+            min_fare: self.quality.min_fare,
+            max_fare: self.quality.max_fare,
+        })
+    }
+
+    pub fn window_length(&self) -> Time {
+        Time::seconds(self.window.length)
+    }
+
+    pub fn window_slide(&self) -> Time {
+        Time::seconds(self.window.slide)
+    }
+
+    pub fn session_gap(&self) -> Time {
+        Time::seconds(self.window.gap)
+    }
+
+    pub fn late_arrival(&self) -> Time {
+        Time::seconds(self.window.late_arrival)
+    }
+
+    /// Windows that late rides are checked against, see `ops::LateRides`.
+    pub fn lateness(&self) -> ops::Lateness {
+        // The last session a ride can belong to closes `gap` seconds after it
+        let (length, slide) = match self.window.kind {
+            WindowKind::Tumbling => (self.window.length, self.window.length),
+            WindowKind::Sliding => (self.window.length, self.window.slide),
+            WindowKind::Session => (self.window.gap, 1),
+        };
+        ops::Lateness {
+            length,
+            slide,
+            late_arrival: self.window.late_arrival,
+        }
+    }
+}
+
+impl fmt::Display for TaxiConf {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match toml::to_string_pretty(self) {
+            Ok(s) => write!(f, "{}", s),
+            Err(_) => write!(f, "{:?}", self),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn password_is_not_printed() {
+        let conf = TaxiConf {
+            sink: SinkSection {
+                username: Some("elastic".to_owned()),
+                password: Some("hunter2".to_owned()),
+                ..SinkSection::default()
+            },
+            ..TaxiConf::default()
+        };
+        for printed in &[
+            format!("{:?}", conf),
+            format!("{:#?}", conf),
+            conf.to_string(),
+        ] {
+            assert!(!printed.contains("hunter2"), "{}", printed);
+        }
+        assert!(format!("{:?}", conf.sink).contains("<redacted>"));
+    }
+}
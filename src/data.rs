@@ -1,13 +1,66 @@
 use arcon::arcon_decoder;
 use arcon::prelude::*;
-use chrono::NaiveDateTime;
+use chrono::{DateTime, Duration, LocalResult, NaiveDateTime, TimeZone};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use std::convert::{Infallible, TryFrom};
 use std::fmt;
+use std::str::FromStr;
+
+use crate::parse::{ParseError, ParseErrorKind};
+use crate::sketch::Distribution;
 
 #[derive(Arcon, Arrow, Clone, prost::Message)]
 #[arcon(unsafe_ser_id = 12, reliable_ser_id = 13, version = 1)]
 pub struct Unit {}
 
-#[arcon_decoder(,)]
+/// A single unparsed line of a TLC trip data file.
+///
+/// The file source emits raw lines so that malformed rows can be rejected
+/// by `ops::ParseRides` instead of failing the source.
+#[macros::proto]
+#[derive(Arcon, Arrow, Clone)]
+#[arcon(unsafe_ser_id = 12, reliable_ser_id = 13, version = 1)]
+pub struct RawLine {
+    pub line: String,
+    /// File the line was read from
+    pub path: String,
+    /// Line number within the file, starting at 1
+    pub line_number: u64,
+}
+
+impl RawLine {
+    /// Event time of the line, or 0 if the pickup datetime is malformed.
+    ///
+    /// Event time is New York wall-clock time, see [`local_time`].
+    pub fn pickup_time(&self) -> u64 {
+        self.line
+            .split(',')
+            .nth(1)
+            .map_or(0, |datetime| event_time(datetime.trim()))
+    }
+}
+
+impl FromStr for RawLine {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self {
+            line: s.to_owned(),
+            ..Self::default()
+        })
+    }
+}
+
+impl fmt::Display for RawLine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.line)
+    }
+}
+
+/// A TLC yellow taxi trip record.
+///
+/// Monetary amounts are stored in cents and are negative for refunds.
 #[macros::proto]
 #[derive(Arcon, Arrow, Clone)]
 #[arcon(
@@ -58,25 +111,57 @@ pub struct TaxiRideData {
     /// * 6 = Voided trip
     pub payment_type: u64,
     /// The time-and-distance fare calculated by the meter.
-    pub fare_amount: u64,
+    pub fare_amount: i64,
     /// Miscellaneous extras and surcharges. Currently, this only includes
     /// the $0.50 and $1 rush hour and overnight charges
-    pub extra: u64,
+    pub extra: i64,
     /// $0.50 MTA tax that is automatically triggered based on the metered
     /// rate in use.
-    pub mta_tax: f32,
+    pub mta_tax: i64,
     /// Tip amount – This field is automatically populated for credit card
     /// tips. Cash tips are not included.
-    pub tip_amount: f32,
+    pub tip_amount: i64,
     /// Total amount of all tolls paid in trip.
-    pub tolls_amount: u64,
+    pub tolls_amount: i64,
     /// $0.30 improvement surcharge assessed trips at the flag drop. The
     /// improvement surcharge began being levied in 2015
-    pub improvement_surcharge: f32,
+    pub improvement_surcharge: i64,
     /// The total amount charged to passengers. Does not include cash tips.
-    pub total_amount: f32,
+    pub total_amount: i64,
     /// This field is not documented.
-    pub congestion_surcharge: f32,
+    pub congestion_surcharge: i64,
+}
+
+impl TaxiRideData {
+    /// Event time of the record, or 0 if the pickup datetime is malformed.
+    pub fn pickup_time(&self) -> u64 {
+        event_time(&self.tpep_pickup_datetime)
+    }
+}
+
+/// A row of a TLC Parquet file, or why it could not be read.
+///
+/// Like `RawLine`, the Parquet source emits rows that cannot be read so that
+/// they are rejected by `ops::ParseRides` instead of failing the source.
+#[macros::proto]
+#[derive(Arcon, Clone)]
+#[arcon(unsafe_ser_id = 12, reliable_ser_id = 13, version = 1)]
+pub struct ParquetRow {
+    pub ride: TaxiRideData,
+    /// File the row was read from
+    pub path: String,
+    /// Index of the row within the file, starting at 0
+    pub row: u64,
+    /// Why the row could not be read, empty if it was
+    pub error: String,
+}
+
+impl ParquetRow {
+    /// Event time of the row, or 0 if it could not be read or its pickup
+    /// datetime is malformed.
+    pub fn pickup_time(&self) -> u64 {
+        self.ride.pickup_time()
+    }
 }
 
 impl fmt::Display for TaxiRideData {
@@ -85,92 +170,594 @@ impl fmt::Display for TaxiRideData {
     }
 }
 
+/// Which end of a trip rides are aggregated by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    /// Departures, keyed on the pickup zone
+    Pickup,
+    /// Arrivals, keyed on the dropoff zone
+    Dropoff,
+}
+
+impl Direction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Direction::Pickup => "pickup",
+            Direction::Dropoff => "dropoff",
+        }
+    }
+}
+
+impl FromStr for Direction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pickup" => Ok(Direction::Pickup),
+            "dropoff" => Ok(Direction::Dropoff),
+            _ => Err(format!("unknown direction '{}'", s)),
+        }
+    }
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// TPEP provider that provided a trip record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Vendor {
+    /// 1 = Creative Mobile Technologies, LLC
+    CreativeMobile,
+    /// 2 = VeriFone Inc.
+    VeriFone,
+    /// Any other code
+    Unknown,
+}
+
+impl From<u64> for Vendor {
+    fn from(code: u64) -> Self {
+        match code {
+            1 => Vendor::CreativeMobile,
+            2 => Vendor::VeriFone,
+            _ => Vendor::Unknown,
+        }
+    }
+}
+
+/// Final rate code in effect at the end of a trip.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RateCode {
+    /// 1 = Standard rate
+    Standard,
+    /// 2 = JFK
+    Jfk,
+    /// 3 = Newark
+    Newark,
+    /// 4 = Nassau or Westchester
+    NassauWestchester,
+    /// 5 = Negotiated fare
+    Negotiated,
+    /// 6 = Group ride
+    GroupRide,
+    /// Any other code, including the undocumented 99
+    Unknown,
+}
+
+impl From<u64> for RateCode {
+    fn from(code: u64) -> Self {
+        match code {
+            1 => RateCode::Standard,
+            2 => RateCode::Jfk,
+            3 => RateCode::Newark,
+            4 => RateCode::NassauWestchester,
+            5 => RateCode::Negotiated,
+            6 => RateCode::GroupRide,
+            _ => RateCode::Unknown,
+        }
+    }
+}
+
+/// How the passenger paid for a trip.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PaymentType {
+    /// 1 = Credit card
+    CreditCard,
+    /// 2 = Cash
+    Cash,
+    /// 3 = No charge
+    NoCharge,
+    /// 4 = Dispute
+    Dispute,
+    /// 5 = Unknown, and any code that is not documented
+    Unknown,
+    /// 6 = Voided trip
+    Voided,
+}
+
+impl From<u64> for PaymentType {
+    fn from(code: u64) -> Self {
+        match code {
+            1 => PaymentType::CreditCard,
+            2 => PaymentType::Cash,
+            3 => PaymentType::NoCharge,
+            4 => PaymentType::Dispute,
+            6 => PaymentType::Voided,
+            _ => PaymentType::Unknown,
+        }
+    }
+}
+
 /// A cleaned up version of TaxiRideData.
 #[arcon_decoder(,)]
 #[macros::proto]
-#[derive(Arcon, Arrow, Clone)]
+#[derive(Arcon, Arrow, Clone, Serialize)]
 #[arcon(
     unsafe_ser_id = 12,
     reliable_ser_id = 13,
     version = 1,
-    keys = "pu_location_id"
+    keys = "location_id"
 )]
 pub struct RideData {
     /// Keys
+    pub location_id: u64,
     pub pu_location_id: u64,
+    pub du_location_id: u64,
+    /// Pickup and dropoff in seconds since the epoch
     pub pu_time: u64,
     pub do_time: u64,
-    /// Values
-    pub fare_amount: u64,
-    pub tip_amount: f32,
+    /// Values, monetary amounts in cents
+    pub fare_amount: i64,
+    pub tip_amount: i64,
+    pub total_amount: i64,
+    pub tolls_amount: i64,
+    pub extra: i64,
+    pub mta_tax: i64,
+    pub improvement_surcharge: i64,
+    pub congestion_surcharge: i64,
     pub trip_distance: f32,
     pub passenger_count: u64,
+    /// Codes, see `vendor`, `rate_code` and `payment`
+    pub vendor_id: u64,
+    pub rate_code_id: u64,
+    pub payment_type: u64,
+    /// Data-quality rules the ride breaks, see `quality::Rule::bit`
+    pub quality_flags: u64,
 }
 
-impl From<TaxiRideData> for RideData {
-    fn from(t: TaxiRideData) -> Self {
-        Self {
+impl TryFrom<TaxiRideData> for RideData {
+    type Error = ParseError;
+
+    fn try_from(t: TaxiRideData) -> Result<Self, Self::Error> {
+        Ok(Self {
+            location_id: t.pu_location_id,
             pu_location_id: t.pu_location_id,
-            pu_time: datetime_to_u64(&t.tpep_pickup_datetime),
-            do_time: datetime_to_u64(&t.tpep_dropoff_datetime),
+            du_location_id: t.du_location_id,
+            pu_time: datetime_to_u64(&t.tpep_pickup_datetime)?,
+            do_time: datetime_to_u64(&t.tpep_dropoff_datetime)?,
             fare_amount: t.fare_amount,
             tip_amount: t.tip_amount,
+            total_amount: t.total_amount,
+            tolls_amount: t.tolls_amount,
+            extra: t.extra,
+            mta_tax: t.mta_tax,
+            improvement_surcharge: t.improvement_surcharge,
+            congestion_surcharge: t.congestion_surcharge,
             trip_distance: t.trip_distance,
             passenger_count: t.passenger_count,
-        }
+            vendor_id: t.vendor_id,
+            rate_code_id: t.rate_code_id,
+            payment_type: t.payment_type,
+            quality_flags: 0,
+        })
+    }
+}
+
+impl RideData {
+    pub fn vendor(&self) -> Vendor {
+        Vendor::from(self.vendor_id)
+    }
+
+    pub fn rate_code(&self) -> RateCode {
+        RateCode::from(self.rate_code_id)
+    }
+
+    pub fn payment(&self) -> PaymentType {
+        PaymentType::from(self.payment_type)
+    }
+
+    /// Keys the ride on the zone of the given end of the trip.
+    ///
+    /// The direction is kept in the upper half of the key, so that the
+    /// windows of both directions can share an operator, see `split_key`.
+    pub fn keyed_by(mut self, direction: Direction) -> Self {
+        let (tag, zone) = match direction {
+            Direction::Pickup => (0, self.pu_location_id),
+            Direction::Dropoff => (1, self.du_location_id),
+        };
+        self.location_id = (tag << 32) | zone;
+        self
+    }
+
+    /// Keys the ride on its (pickup, dropoff) zone pair.
+    pub fn keyed_by_pair(mut self) -> Self {
+        self.location_id = (self.pu_location_id << 32) | self.du_location_id;
+        self
     }
 }
 
+/// Direction and zone of a key made by `RideData::keyed_by`.
+pub fn split_key(key: u64) -> (Direction, u64) {
+    let direction = match key >> 32 {
+        0 => Direction::Pickup,
+        _ => Direction::Dropoff,
+    };
+    (direction, key & 0xffff_ffff)
+}
+
 /// Aggregate of TaxiRideData, produced by window_sum
+///
+/// Monetary aggregates are in cents.
 #[macros::proto]
-#[derive(Arcon, Arrow, Clone)]
+#[derive(Arcon, Arrow, Clone, Serialize, macros::EsMapping)]
 #[arcon(
     unsafe_ser_id = 12,
     reliable_ser_id = 13,
     version = 1,
-    keys = "pu_location_id, pu_time"
+    keys = "location_id, pu_time"
 )]
 pub struct RideWindowedData {
     /// Keys
-    pub pu_location_id: u64,
+    pub location_id: u64,
     pub pu_time: u64,
+    /// Window
+    pub window_start: u64,
+    pub window_end: u64,
+    /// Number of times late rides updated the window after it was emitted
+    pub revision: u64,
     /// Other
-    pub pu_location_name: String,
+    pub direction: String,
+    pub location_name: String,
+    pub borough: String,
+    pub zone: String,
+    /// Aggregates
+    pub count: u64,
+    /// Rides tagged by the data-quality check
+    pub flagged_count: u64,
+
+    pub sum_fare_amount: i64,
+    pub max_fare_amount: i64,
+    pub avg_fare_amount: f64,
+    pub min_fare_amount: i64,
+
+    pub sum_tip_amount: i64,
+    pub max_tip_amount: i64,
+    pub avg_tip_amount: f64,
+    pub min_tip_amount: i64,
+
+    pub sum_total_amount: i64,
+    pub max_total_amount: i64,
+    pub avg_total_amount: f64,
+    pub min_total_amount: i64,
+
+    pub sum_tolls_amount: i64,
+    pub max_tolls_amount: i64,
+    pub avg_tolls_amount: f64,
+    pub min_tolls_amount: i64,
+
+    pub sum_extra: i64,
+    pub max_extra: i64,
+    pub avg_extra: f64,
+    pub min_extra: i64,
+
+    pub sum_mta_tax: i64,
+    pub max_mta_tax: i64,
+    pub avg_mta_tax: f64,
+    pub min_mta_tax: i64,
+
+    pub sum_improvement_surcharge: i64,
+    pub max_improvement_surcharge: i64,
+    pub avg_improvement_surcharge: f64,
+    pub min_improvement_surcharge: i64,
+
+    pub sum_congestion_surcharge: i64,
+    pub max_congestion_surcharge: i64,
+    pub avg_congestion_surcharge: f64,
+    pub min_congestion_surcharge: i64,
+
+    pub sum_trip_distance: f32,
+    pub avg_trip_distance: f64,
+
+    pub sum_passenger_count: u64,
+    pub max_passenger_count: u64,
+    pub avg_passenger_count: f64,
+    pub min_passenger_count: u64,
+
+    pub sum_duration: u64,
+    pub max_duration: u64,
+    pub avg_duration: f64,
+    pub min_duration: u64,
+
+    /// Percentiles and standard deviations, see `Distribution`
+    pub p50_fare_amount: i64,
+    pub p90_fare_amount: i64,
+    pub p99_fare_amount: i64,
+    pub stddev_fare_amount: f32,
+
+    pub p50_duration: u64,
+    pub p90_duration: u64,
+    pub p99_duration: u64,
+    pub stddev_duration: f32,
+
+    pub p50_trip_distance: f32,
+    pub p90_trip_distance: f32,
+    pub p99_trip_distance: f32,
+    pub stddev_trip_distance: f32,
+
+    /// Tips as a percentage of the fare
+    pub tip_percentage: f32,
+    /// Share of the total amount that is congestion surcharge, from 0 to 1
+    pub congestion_surcharge_share: f32,
+
+    /// Breakdowns, see `Breakdown`
+    pub payment_credit_card_count: u64,
+    pub payment_credit_card_revenue: i64,
+
+    pub payment_cash_count: u64,
+    pub payment_cash_revenue: i64,
+
+    pub payment_no_charge_count: u64,
+    pub payment_no_charge_revenue: i64,
+
+    pub payment_dispute_count: u64,
+    pub payment_dispute_revenue: i64,
+
+    pub payment_unknown_count: u64,
+    pub payment_unknown_revenue: i64,
+
+    pub payment_voided_count: u64,
+    pub payment_voided_revenue: i64,
+
+    pub rate_standard_count: u64,
+    pub rate_jfk_count: u64,
+    pub rate_newark_count: u64,
+    pub rate_nassau_westchester_count: u64,
+    pub rate_negotiated_count: u64,
+    pub rate_group_ride_count: u64,
+    pub rate_unknown_count: u64,
+
+    pub vendor_creative_mobile_count: u64,
+    pub vendor_verifone_count: u64,
+    pub vendor_unknown_count: u64,
+}
+
+/// Ride counts per payment type, rate code and vendor, with the revenue
+/// (total amount in cents) of each payment type.
+#[macros::proto]
+#[derive(Arcon, Clone)]
+#[arcon(unsafe_ser_id = 12, reliable_ser_id = 13, version = 1)]
+pub struct Breakdown {
+    pub payment_credit_card_count: u64,
+    pub payment_credit_card_revenue: i64,
+
+    pub payment_cash_count: u64,
+    pub payment_cash_revenue: i64,
+
+    pub payment_no_charge_count: u64,
+    pub payment_no_charge_revenue: i64,
+
+    pub payment_dispute_count: u64,
+    pub payment_dispute_revenue: i64,
+
+    pub payment_unknown_count: u64,
+    pub payment_unknown_revenue: i64,
+
+    pub payment_voided_count: u64,
+    pub payment_voided_revenue: i64,
+
+    pub rate_standard_count: u64,
+    pub rate_jfk_count: u64,
+    pub rate_newark_count: u64,
+    pub rate_nassau_westchester_count: u64,
+    pub rate_negotiated_count: u64,
+    pub rate_group_ride_count: u64,
+    pub rate_unknown_count: u64,
+
+    pub vendor_creative_mobile_count: u64,
+    pub vendor_verifone_count: u64,
+    pub vendor_unknown_count: u64,
+}
+
+/// Mergeable partial aggregate of RideData, kept per window instead of
+/// buffering every ride.
+#[macros::proto]
+#[derive(Arcon, Clone)]
+#[arcon(unsafe_ser_id = 12, reliable_ser_id = 13, version = 1)]
+pub struct RideAccumulator {
+    /// Keys
+    pub location_id: u64,
+    pub pu_time: u64,
     /// Aggregates
     pub count: u64,
+    /// Rides tagged by the data-quality check
+    pub flagged_count: u64,
+
+    pub sum_fare_amount: i64,
+    pub max_fare_amount: i64,
+    pub min_fare_amount: i64,
+
+    pub sum_tip_amount: i64,
+    pub max_tip_amount: i64,
+    pub min_tip_amount: i64,
+
+    pub sum_total_amount: i64,
+    pub max_total_amount: i64,
+    pub min_total_amount: i64,
 
-    pub sum_fare_amount: u64,
-    pub max_fare_amount: u64,
-    pub avg_fare_amount: u64,
-    pub min_fare_amount: u64,
+    pub sum_tolls_amount: i64,
+    pub max_tolls_amount: i64,
+    pub min_tolls_amount: i64,
+
+    pub sum_extra: i64,
+    pub max_extra: i64,
+    pub min_extra: i64,
+
+    pub sum_mta_tax: i64,
+    pub max_mta_tax: i64,
+    pub min_mta_tax: i64,
+
+    pub sum_improvement_surcharge: i64,
+    pub max_improvement_surcharge: i64,
+    pub min_improvement_surcharge: i64,
+
+    pub sum_congestion_surcharge: i64,
+    pub max_congestion_surcharge: i64,
+    pub min_congestion_surcharge: i64,
 
     pub sum_trip_distance: f32,
-    pub avg_trip_distance: f32,
 
     pub sum_passenger_count: u64,
     pub max_passenger_count: u64,
-    pub avg_passenger_count: u64,
     pub min_passenger_count: u64,
 
     pub sum_duration: u64,
     pub max_duration: u64,
-    pub avg_duration: u64,
     pub min_duration: u64,
+
+    pub dist_fare_amount: Distribution,
+    pub dist_duration: Distribution,
+    pub dist_trip_distance: Distribution,
+
+    pub breakdown: Breakdown,
 }
 
-pub fn datetime_to_u64(datetime: &str) -> u64 {
-    let s = NaiveDateTime::parse_from_str(datetime, "%Y-%m-%d %H:%M:%S").unwrap();
-    s.timestamp() as u64
+/// Mergeable partial aggregate of the rides between a pair of zones.
+#[macros::proto]
+#[derive(Arcon, Clone)]
+#[arcon(unsafe_ser_id = 12, reliable_ser_id = 13, version = 1)]
+pub struct OdAccumulator {
+    /// Keys
+    pub pu_location_id: u64,
+    pub du_location_id: u64,
+    pub pu_time: u64,
+    /// Aggregates
+    pub count: u64,
+    pub sum_fare_amount: i64,
+    pub sum_trip_distance: f32,
+    pub sum_duration: u64,
 }
 
-pub fn u64_to_datetime(time: u64) -> NaiveDateTime {
-    chrono::NaiveDateTime::from_timestamp(time as i64, 0)
+/// Aggregate of the rides between a pair of zones within a window
+#[macros::proto]
+#[derive(Arcon, Arrow, Clone, Serialize, macros::EsMapping)]
+#[arcon(
+    unsafe_ser_id = 12,
+    reliable_ser_id = 13,
+    version = 1,
+    keys = "pu_location_id, du_location_id, pu_time"
+)]
+pub struct OdWindowedData {
+    /// Keys
+    pub pu_location_id: u64,
+    pub du_location_id: u64,
+    pub pu_time: u64,
+    /// Window
+    pub window_start: u64,
+    pub window_end: u64,
+    /// Other
+    pub pu_location_name: String,
+    pub du_location_name: String,
+    /// Position among the pairs of the same origin ordered by count, starting
+    /// at 1, or 0 if the pair is not ranked
+    pub rank: u64,
+    /// Aggregates
+    pub count: u64,
+    pub avg_fare_amount: f64,
+    pub avg_trip_distance: f64,
+    pub avg_duration: f64,
+}
+
+/// Time zone of the datetimes in the TLC trip records.
+pub const TIME_ZONE: Tz = chrono_tz::America::New_York;
+
+/// Resolves a New York wall-clock datetime.
+///
+/// A datetime in the hour repeated when daylight saving time ends is taken
+/// as the first of the two, and one in the hour skipped when it starts as
+/// standard time, as a meter that has not been set forward yet would record.
+fn from_local(datetime: &NaiveDateTime) -> Option<DateTime<Tz>> {
+    match TIME_ZONE.from_local_datetime(datetime) {
+        LocalResult::Single(time) => Some(time),
+        LocalResult::Ambiguous(earliest, _) => Some(earliest),
+        LocalResult::None => TIME_ZONE
+            .from_local_datetime(&(*datetime + Duration::hours(1)))
+            .earliest(),
+    }
+}
+
+/// Parses a TLC datetime, which is New York local time, into seconds since
+/// the epoch.
+pub fn datetime_to_u64(datetime: &str) -> Result<u64, ParseError> {
+    let invalid = || ParseError::new(ParseErrorKind::DateTime, "datetime", datetime);
+    let s = NaiveDateTime::parse_from_str(datetime, "%Y-%m-%d %H:%M:%S").map_err(|_| invalid())?;
+    let s = from_local(&s).ok_or_else(invalid)?;
+    u64::try_from(s.timestamp()).map_err(|_| invalid())
+}
+
+/// New York local time of seconds since the epoch.
+pub fn u64_to_datetime(time: u64) -> DateTime<Tz> {
+    TIME_ZONE.timestamp(time as i64, 0)
+}
+
+/// Event time of seconds since the epoch: the New York wall-clock time
+/// counted as if it were UTC.
+///
+/// Windows are assigned on event time, so fixed-length windows are cut at
+/// New York midnight, and a day window spans 23 or 25 hours when the clocks
+/// change.
+pub fn local_time(time: u64) -> u64 {
+    u64::try_from(u64_to_datetime(time).naive_local().timestamp()).unwrap_or(0)
+}
+
+/// Event time of a TLC datetime, or 0 if it is malformed.
+fn event_time(datetime: &str) -> u64 {
+    datetime_to_u64(datetime).map_or(0, local_time)
+}
+
+/// Seconds since the epoch of an event time, the inverse of `local_time`.
+pub fn utc_time(local: u64) -> u64 {
+    from_local(&NaiveDateTime::from_timestamp(local as i64, 0))
+        .and_then(|time| u64::try_from(time.timestamp()).ok())
+        .unwrap_or(0)
+}
+
+/// Key of a zone's aggregate in `RideState`.
+#[macros::proto]
+#[derive(Clone, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct RideKey {
+    pub direction: String,
+    pub location_id: u64,
+    pub window_start: u64,
+}
+
+impl RideKey {
+    pub fn of(data: &RideWindowedData) -> Self {
+        Self {
+            direction: data.direction.clone(),
+            location_id: data.location_id,
+            window_start: data.window_start,
+        }
+    }
 }
 
+/// The latest aggregate of every zone and window, see `query::QueryStore`.
 #[derive(ArconState)]
 pub struct RideState<B: Backend> {
     #[table = "rides"]
-    rides: EagerValue<RideWindowedData, B>,
+    rides: EagerHashTable<RideKey, RideWindowedData, B>,
 }
 
 impl<B: Backend> StateConstructor for RideState<B> {
@@ -178,7 +765,98 @@ impl<B: Backend> StateConstructor for RideState<B> {
 
     fn new(backend: Arc<Self::BackendType>) -> Self {
         Self {
-            rides: EagerValue::new("_rides", backend),
+            rides: EagerHashTable::new("_rides", backend),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{Map, Value};
+
+    /// Seconds since the epoch of a UTC datetime.
+    fn utc(datetime: &str) -> u64 {
+        NaiveDateTime::parse_from_str(datetime, "%Y-%m-%d %H:%M:%S")
+            .unwrap()
+            .timestamp() as u64
+    }
+
+    #[test]
+    fn repeated_hour_is_taken_as_daylight_time() {
+        // 01:30 happens at 05:30 UTC in daylight time and at 06:30 in
+        // standard time on 2020-11-01
+        let time = datetime_to_u64("2020-11-01 01:30:00").unwrap();
+        assert_eq!(time, utc("2020-11-01 05:30:00"));
+        assert_eq!(local_time(time), utc("2020-11-01 01:30:00"));
+        assert_eq!(local_time(utc("2020-11-01 06:30:00")), local_time(time));
+    }
+
+    #[test]
+    fn skipped_hour_is_taken_as_standard_time() {
+        // 02:30 does not happen on 2020-03-08, the clocks go from 02:00 EST
+        // to 03:00 EDT
+        let time = datetime_to_u64("2020-03-08 02:30:00").unwrap();
+        assert_eq!(time, utc("2020-03-08 07:30:00"));
+        assert_eq!(local_time(time), utc("2020-03-08 03:30:00"));
+        assert_eq!(utc_time(utc("2020-03-08 02:30:00")), time);
+    }
+
+    #[test]
+    fn event_times_round_trip_outside_the_repeated_hour() {
+        let repeated = utc("2020-11-01 06:00:00")..utc("2020-11-01 07:00:00");
+        for start in &["2020-03-07 12:00:00", "2020-10-31 12:00:00"] {
+            let start = utc(start);
+            for time in (start..start + 2 * 86400).step_by(15 * 60) {
+                let local = local_time(time);
+                if repeated.contains(&time) {
+                    // The second 01:xx maps back to the first one
+                    assert_eq!(utc_time(local), time - 3600);
+                } else {
+                    assert_eq!(utc_time(local), time, "{}", u64_to_datetime(time));
+                }
+            }
         }
     }
+
+    /// Asserts that every serialized field of a document is mapped, with a
+    /// type that fits its JSON value, and that every mapped field is
+    /// serialized.
+    fn assert_mapped(document: impl Serialize, properties: Map<String, Value>) {
+        let document = match serde_json::to_value(document).unwrap() {
+            Value::Object(fields) => fields,
+            other => panic!("document is not an object: {}", other),
+        };
+        let fields: Vec<_> = document.keys().collect();
+        let mapped: Vec<_> = properties.keys().collect();
+        assert_eq!(fields, mapped);
+
+        for (field, value) in &document {
+            let ty = properties[field]["type"].as_str().unwrap();
+            let fits = match value {
+                Value::String(_) => ty == "keyword",
+                Value::Bool(_) => ty == "boolean",
+                Value::Number(_) => ["integer", "long", "float", "double"].contains(&ty),
+                _ => false,
+            };
+            assert!(
+                fits,
+                "{} is mapped as {} but serialized as {}",
+                field, ty, value
+            );
+        }
+    }
+
+    #[test]
+    fn ride_windowed_data_matches_its_mapping() {
+        assert_mapped(
+            RideWindowedData::default(),
+            RideWindowedData::es_properties(),
+        );
+    }
+
+    #[test]
+    fn od_windowed_data_matches_its_mapping() {
+        assert_mapped(OdWindowedData::default(), OdWindowedData::es_properties());
+    }
 }
@@ -0,0 +1,500 @@
+use arcon::prelude::*;
+use prost::Message;
+use rdkafka::config::ClientConfig;
+use rdkafka::consumer::{BaseConsumer, CommitMode, Consumer};
+use rdkafka::error::{KafkaError, RDKafkaErrorCode};
+use rdkafka::message::Message as _;
+use rdkafka::{Offset, TopicPartitionList};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::conf::KafkaSection;
+use crate::data::TaxiRideData;
+use crate::ops::RideRecord;
+use crate::parse::{self, ParseError, ParseErrorKind};
+
+/// Encoding of the trip records in Kafka messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Payload {
+    /// A TLC CSV row
+    Csv,
+    /// A protobuf-encoded `TaxiRideData`
+    Protobuf,
+}
+
+impl std::str::FromStr for Payload {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(Payload::Csv),
+            "protobuf" => Ok(Payload::Protobuf),
+            _ => Err(format!("unknown Kafka payload '{}'", s)),
+        }
+    }
+}
+
+/// Creates a consumer of the trip topic that does not commit offsets by
+/// itself, see [`EpochOffsets`].
+///
+/// Every pipeline reads the whole topic, so each gets its own consumer group
+/// named after the configured group and the pipeline.
+pub fn consumer(conf: &KafkaSection, name: &str) -> Result<BaseConsumer, KafkaError> {
+    let consumer: BaseConsumer = ClientConfig::new()
+        .set("bootstrap.servers", &conf.brokers)
+        .set("group.id", &format!("{}-{}", conf.group_id, name))
+        .set("enable.auto.commit", "false")
+        .set("auto.offset.reset", "earliest")
+        .create()?;
+    consumer.subscribe(&[&conf.topic])?;
+    Ok(consumer)
+}
+
+/// Checks that the brokers can be reached and that the topic exists.
+pub fn check_topic(conf: &KafkaSection) -> Result<(), KafkaError> {
+    let consumer: BaseConsumer = ClientConfig::new()
+        .set("bootstrap.servers", &conf.brokers)
+        .create()?;
+    let metadata = consumer.fetch_metadata(Some(&conf.topic), Duration::from_secs(10))?;
+    match metadata
+        .topics()
+        .iter()
+        .find(|topic| topic.name() == conf.topic)
+    {
+        Some(topic) if topic.error().is_none() && !topic.partitions().is_empty() => Ok(()),
+        _ => Err(KafkaError::MetadataFetch(
+            RDKafkaErrorCode::UnknownTopicOrPartition,
+        )),
+    }
+}
+
+/// A Kafka message, with the watermark of the source when it was read.
+#[macros::proto]
+#[derive(Arcon, Clone)]
+#[arcon(unsafe_ser_id = 12, reliable_ser_id = 13, version = 1)]
+pub struct KafkaRecord {
+    pub partition: i32,
+    pub offset: i64,
+    pub payload: Vec<u8>,
+    /// Whether the payload is a protobuf `TaxiRideData` instead of a CSV row
+    pub protobuf: bool,
+    /// Lowest event time that every partition has reached, used as the
+    /// timestamp of the record so that the watermark of the source follows
+    /// the slowest partition
+    pub watermark: u64,
+}
+
+impl KafkaRecord {
+    /// Event time of the record, or 0 if the payload is malformed.
+    pub fn pickup_time(&self) -> u64 {
+        self.parse().map_or(0, |ride| ride.pickup_time())
+    }
+}
+
+impl RideRecord for KafkaRecord {
+    fn parse(&self) -> Result<TaxiRideData, ParseError> {
+        if self.protobuf {
+            TaxiRideData::decode(self.payload.as_slice())
+                .map_err(|err| ParseError::new(ParseErrorKind::Protobuf, "", err.to_string()))
+        } else {
+            parse::parse_taxi_ride(&String::from_utf8_lossy(&self.payload))
+        }
+    }
+
+    fn raw(&self) -> String {
+        if self.protobuf {
+            format!("{}:{}", self.partition, self.offset)
+        } else {
+            String::from_utf8_lossy(&self.payload).into_owned()
+        }
+    }
+
+    fn origin(&self) -> (String, u64) {
+        (format!("partition {}", self.partition), self.offset as u64)
+    }
+}
+
+/// Source of the messages of a Kafka topic.
+///
+/// The source tracks the highest event time of every partition assigned to
+/// it, and stamps every record with the lowest of them. A partition that is
+/// behind does not see its rides dropped as late because another partition
+/// is ahead. Partitions that have not delivered a valid ride yet hold the
+/// watermark back, and records that cannot be parsed or whose event time is
+/// not plausible do not move it.
+pub struct KafkaSource {
+    consumer: Arc<BaseConsumer>,
+    payload: Payload,
+    /// Event times that move the watermark, see `QualityRules::plausible_times`
+    plausible: Range<u64>,
+    /// Highest event time of every assigned partition, or 0 if it has not
+    /// delivered a valid ride yet
+    partitions: BTreeMap<i32, u64>,
+    watermark: u64,
+}
+
+impl KafkaSource {
+    pub fn new(consumer: Arc<BaseConsumer>, payload: Payload, plausible: Range<u64>) -> Self {
+        Self {
+            consumer,
+            payload,
+            plausible,
+            partitions: BTreeMap::new(),
+            watermark: 0,
+        }
+    }
+
+    /// Starts tracking newly assigned partitions and stops tracking revoked
+    /// ones.
+    fn refresh_partitions(&mut self) {
+        let assignment = match self.consumer.assignment() {
+            Ok(assignment) => assignment,
+            Err(err) => {
+                eprintln!("Failed to read the Kafka assignment: {}", err);
+                return;
+            }
+        };
+        let assigned: BTreeSet<i32> = assignment
+            .elements()
+            .iter()
+            .map(|elem| elem.partition())
+            .collect();
+        self.partitions
+            .retain(|partition, _| assigned.contains(partition));
+        for partition in assigned {
+            self.partitions.entry(partition).or_insert(0);
+        }
+    }
+}
+
+impl Source for KafkaSource {
+    type Item = KafkaRecord;
+
+    fn poll_next(&mut self) -> SourceResult<Poll<Self::Item>> {
+        let message = match self.consumer.poll(Duration::from_millis(0)) {
+            Some(Ok(message)) => message,
+            Some(Err(err)) => {
+                eprintln!("Failed to read from Kafka: {}", err);
+                return Ok(Poll::Pending);
+            }
+            None => {
+                self.refresh_partitions();
+                return Ok(Poll::Pending);
+            }
+        };
+
+        let mut record = KafkaRecord {
+            partition: message.partition(),
+            offset: message.offset(),
+            payload: message.payload().unwrap_or_default().to_vec(),
+            protobuf: self.payload == Payload::Protobuf,
+            watermark: 0,
+        };
+        if !self.partitions.contains_key(&record.partition) {
+            self.refresh_partitions();
+        }
+        let time = record.pickup_time();
+        if self.plausible.contains(&time) {
+            let partition = self.partitions.entry(record.partition).or_insert(0);
+            *partition = (*partition).max(time);
+        }
+        if let Some(&slowest) = self.partitions.values().min() {
+            self.watermark = self.watermark.max(slowest);
+        }
+        record.watermark = self.watermark;
+        Ok(Poll::Ready(record))
+    }
+
+    /// Kafka resumes from the offsets committed by [`EpochOffsets`].
+    fn set_offset(&mut self, _offset: usize) {}
+}
+
+/// Offsets of the Kafka records that an epoch covers, committed once the
+/// whole pipeline has persisted the epoch.
+///
+/// [`TrackOffsets`] snapshots the offsets when it persists an epoch, right
+/// after the source. The sink commits the oldest snapshot when it persists
+/// the same epoch, after every operator in between has persisted it too. A
+/// pipeline that crashes before that restores its state and its offsets from
+/// the same earlier epoch, so a restarted pipeline reads every ride at least
+/// once.
+pub struct EpochOffsets {
+    consumer: Arc<BaseConsumer>,
+    topic: String,
+    /// Next offset to read of every partition, of every epoch that the sink
+    /// has not persisted yet
+    epochs: Mutex<VecDeque<BTreeMap<i32, i64>>>,
+}
+
+impl EpochOffsets {
+    pub fn new(consumer: Arc<BaseConsumer>, topic: String) -> Self {
+        Self {
+            consumer,
+            topic,
+            epochs: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Records the offsets of an epoch that `TrackOffsets` has persisted.
+    fn snapshot(&self, offsets: BTreeMap<i32, i64>) {
+        self.epochs
+            .lock()
+            .expect("epoch offsets lock poisoned")
+            .push_back(offsets);
+    }
+
+    /// Commits the offsets of the oldest epoch that is not committed yet.
+    pub fn commit(&self) {
+        let offsets = match self
+            .epochs
+            .lock()
+            .expect("epoch offsets lock poisoned")
+            .pop_front()
+        {
+            Some(offsets) if !offsets.is_empty() => offsets,
+            _ => return,
+        };
+        let mut list = TopicPartitionList::new();
+        for (&partition, &next) in &offsets {
+            if let Err(err) =
+                list.add_partition_offset(&self.topic, partition, Offset::Offset(next))
+            {
+                eprintln!("Failed to commit Kafka offsets: {}", err);
+                return;
+            }
+        }
+        if let Err(err) = self.consumer.commit(&list, CommitMode::Sync) {
+            eprintln!("Failed to commit Kafka offsets: {}", err);
+        }
+    }
+}
+
+/// Passes Kafka records on, and snapshots the offsets of the records it has
+/// seen on every epoch, see [`EpochOffsets`].
+pub struct TrackOffsets {
+    state: (),
+    epochs: Arc<EpochOffsets>,
+    /// Next offset to read of every partition
+    offsets: BTreeMap<i32, i64>,
+}
+
+impl TrackOffsets {
+    pub fn new(epochs: Arc<EpochOffsets>) -> Self {
+        Self {
+            state: (),
+            epochs,
+            offsets: BTreeMap::new(),
+        }
+    }
+}
+
+impl Operator for TrackOffsets {
+    type IN = KafkaRecord;
+    type OUT = KafkaRecord;
+    type TimerState = ArconNever;
+    type OperatorState = ();
+
+    fn handle_element(
+        &mut self,
+        element: ArconElement<Self::IN>,
+        mut ctx: OperatorContext<Self, impl Backend, impl ComponentDefinition>,
+    ) -> ArconResult<()> {
+        let next = self.offsets.entry(element.data.partition).or_insert(0);
+        *next = (*next).max(element.data.offset + 1);
+        ctx.output(element);
+        Ok(())
+    }
+
+    arcon::ignore_timeout!();
+
+    fn persist(&mut self) -> Result<(), ArconStateError> {
+        self.epochs.snapshot(self.offsets.clone());
+        Ok(())
+    }
+
+    fn state(&mut self) -> &mut Self::OperatorState {
+        &mut self.state
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rdkafka::mocking::MockCluster;
+    use rdkafka::producer::{BaseProducer, BaseRecord, DefaultProducerContext, Producer};
+    use std::time::Instant;
+
+    const TOPIC: &str = "taxi_rides";
+
+    fn row(pickup: &str) -> String {
+        format!(
+            "1,{},2020-01-01 23:59:00,1,1.5,1,N,161,236,1,8.5,0.5,0.5,2,0,0.3,14.3,2.5",
+            pickup
+        )
+    }
+
+    fn event_time(pickup: &str) -> u64 {
+        crate::data::local_time(crate::data::datetime_to_u64(pickup).unwrap())
+    }
+
+    /// Starts a mock cluster with a two-partition topic holding `rows`.
+    fn cluster(
+        rows: &[(i32, String)],
+    ) -> (MockCluster<'static, DefaultProducerContext>, KafkaSection) {
+        let cluster = MockCluster::new(1).unwrap();
+        cluster.create_topic(TOPIC, 2, 1).unwrap();
+        let producer: BaseProducer = ClientConfig::new()
+            .set("bootstrap.servers", &cluster.bootstrap_servers())
+            .create()
+            .unwrap();
+        for (partition, row) in rows {
+            producer
+                .send(
+                    BaseRecord::<(), _>::to(TOPIC)
+                        .partition(*partition)
+                        .payload(row),
+                )
+                .map_err(|(err, _)| err)
+                .unwrap();
+        }
+        producer.flush(Duration::from_secs(10));
+        let conf = KafkaSection {
+            brokers: cluster.bootstrap_servers(),
+            topic: TOPIC.to_owned(),
+            group_id: "test".to_owned(),
+            payload: Payload::Csv,
+        };
+        (cluster, conf)
+    }
+
+    fn poll(source: &mut KafkaSource, count: usize) -> Vec<KafkaRecord> {
+        let deadline = Instant::now() + Duration::from_secs(30);
+        let mut records = Vec::new();
+        while records.len() < count && Instant::now() < deadline {
+            match source.poll_next() {
+                Ok(Poll::Ready(record)) => records.push(record),
+                _ => std::thread::sleep(Duration::from_millis(10)),
+            }
+        }
+        assert_eq!(
+            records.len(),
+            count,
+            "timed out reading from the mock cluster"
+        );
+        records
+    }
+
+    #[test]
+    fn watermark_follows_the_slowest_partition() {
+        let (_cluster, conf) = cluster(&[
+            (0, row("2020-01-01 10:00:00")),
+            (1, row("2020-01-01 09:00:00")),
+            (1, row("2020-01-01 11:00:00")),
+        ]);
+        let consumer = Arc::new(consumer(&conf, "zones").unwrap());
+        let mut source = KafkaSource::new(consumer, Payload::Csv, 1..u64::MAX);
+        let records = poll(&mut source, 3);
+
+        let slowest = event_time("2020-01-01 10:00:00");
+        for record in &records {
+            assert!(record.watermark <= slowest);
+        }
+        assert_eq!(records.last().unwrap().watermark, slowest);
+    }
+
+    #[test]
+    fn unseen_and_malformed_partitions_hold_the_watermark_back() {
+        let (_cluster, conf) = cluster(&[
+            (0, row("2020-01-01 10:00:00")),
+            (1, "not a ride".to_owned()),
+            (1, row("2020-01-01 09:30:00")),
+        ]);
+        let consumer = Arc::new(consumer(&conf, "zones").unwrap());
+        let mut source = KafkaSource::new(consumer, Payload::Csv, 1..u64::MAX);
+        let records = poll(&mut source, 3);
+
+        // Until partition 1 delivers a valid ride, it holds the watermark at 0
+        let valid = records
+            .iter()
+            .position(|record| record.partition == 1 && record.offset == 1)
+            .unwrap();
+        for record in &records[..valid] {
+            assert_eq!(record.watermark, 0);
+        }
+        assert_eq!(
+            records.last().unwrap().watermark,
+            event_time("2020-01-01 09:30:00")
+        );
+    }
+
+    #[test]
+    fn future_dated_rides_do_not_move_the_watermark() {
+        let (_cluster, conf) = cluster(&[
+            (0, row("2020-01-01 10:00:00")),
+            (0, row("2099-01-01 10:00:00")),
+            (0, row("2020-01-01 10:05:00")),
+            (1, row("2020-01-01 11:00:00")),
+        ]);
+        let consumer = Arc::new(consumer(&conf, "zones").unwrap());
+        let plausible = 1..event_time("2021-01-01 00:00:00");
+        let mut source = KafkaSource::new(consumer, Payload::Csv, plausible);
+        let records = poll(&mut source, 4);
+
+        // Partition 0 is the slowest, and the ride of 2099 does not count
+        let slowest = event_time("2020-01-01 10:05:00");
+        for record in &records {
+            assert!(record.watermark <= slowest);
+        }
+        assert_eq!(records.last().unwrap().watermark, slowest);
+    }
+
+    #[test]
+    fn offsets_are_committed_per_epoch_by_the_sink() {
+        let (_cluster, conf) = cluster(&[
+            (0, row("2020-01-01 10:00:00")),
+            (0, row("2020-01-01 10:05:00")),
+            (1, row("2020-01-01 10:00:00")),
+        ]);
+        let consumer = Arc::new(consumer(&conf, "zones").unwrap());
+        let mut source = KafkaSource::new(consumer.clone(), Payload::Csv, 1..u64::MAX);
+        let records = poll(&mut source, 3);
+
+        let mut first = BTreeMap::new();
+        let mut second = BTreeMap::new();
+        for record in &records {
+            let next = record.offset + 1;
+            if record.offset == 0 {
+                first.insert(record.partition, next);
+            }
+            let offset = second.entry(record.partition).or_insert(0);
+            *offset = next.max(*offset);
+        }
+
+        let epochs = EpochOffsets::new(consumer.clone(), TOPIC.to_owned());
+        epochs.snapshot(first.clone());
+        epochs.snapshot(second.clone());
+
+        let committed = |partition: i32| {
+            consumer
+                .committed(Duration::from_secs(10))
+                .unwrap()
+                .find_partition(TOPIC, partition)
+                .map(|elem| elem.offset())
+        };
+        assert_eq!(committed(0), Some(Offset::Invalid));
+
+        // The sink persists the first epoch
+        epochs.commit();
+        assert_eq!(committed(0), Some(Offset::Offset(first[&0])));
+        assert_eq!(committed(1), Some(Offset::Offset(first[&1])));
+
+        // And then the second one
+        epochs.commit();
+        assert_eq!(committed(0), Some(Offset::Offset(second[&0])));
+        assert_eq!(committed(1), Some(Offset::Offset(second[&1])));
+    }
+}
@@ -1,63 +1,625 @@
 use arcon::prelude::*;
 
 pub mod agg;
+pub mod cli;
+pub mod conf;
 pub mod data;
+pub mod kafka;
+pub mod mapping;
 pub mod ops;
+pub mod parse;
+pub mod quality;
+pub mod query;
+pub mod sketch;
+pub mod source;
+pub mod window;
+pub mod zone;
 
-use agg::window_sum;
-use data::datetime_to_u64;
+use cli::Command;
+use conf::TaxiConf;
+use data::Direction;
+use data::OdWindowedData;
+use data::ParquetRow;
+use data::RawLine;
 use data::RideData;
+use data::RideKey;
 use data::RideState;
 use data::RideWindowedData;
-use data::TaxiRideData;
+use elasticsearch::Elasticsearch;
+use kafka::KafkaRecord;
+use query::QueryStore;
+use source::InputFormat;
+use source::SourceKind;
+use window::Aggregation;
+use window::SessionWindow;
+use window::UpdatingWindow;
+use window::WindowBounds;
+use window::WindowKind;
+use zone::ZoneTable;
 
 fn main() {
-    let conf = ArconConf {
-        epoch_interval: 20_000,
-        watermark_interval: 1000,
-        ctrl_system_host: Some("127.0.0.1:2000".to_string()),
-        allocator_capacity: 2147483648,
-        ..Default::default()
+    let command = match cli::parse_args() {
+        Ok(command) => command,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            std::process::exit(2);
+        }
     };
 
-    let mut pipeline = Pipeline::with_conf(conf)
-        .file("data/sorted_yellow_tripdata_2020.csv", |cfg| {
-            cfg.set_timestamp_extractor(|x: &TaxiRideData| {
-                datetime_to_u64(&x.tpep_pickup_datetime)
+    match command {
+        Command::Config(conf) => print!("{}", conf),
+        Command::Mapping(conf) => {
+            let templates: serde_json::Map<_, _> = index_properties(&conf)
+                .into_iter()
+                .map(|(index, properties)| {
+                    let template =
+                        mapping::index_template(&index, &properties, conf.sink.data_stream);
+                    (index, template)
+                })
+                .collect();
+            println!("{:#}", serde_json::Value::Object(templates));
+        }
+        Command::Run(conf) => {
+            println!("Resolved configuration:\n{}", conf);
+            run(conf);
+        }
+    }
+}
+
+fn run(conf: TaxiConf) {
+    for name in conf.pipelines() {
+        let dead_letter = conf.side_output(&conf.source.dead_letter, name);
+        if let Err(err) = ops::DeadLetter::open(&dead_letter) {
+            eprintln!(
+                "error: cannot open dead-letter file {}: {}",
+                dead_letter.display(),
+                err
+            );
+            std::process::exit(1);
+        }
+
+        let dead_letter = conf.side_output(&conf.sink.dead_letter, name);
+        if let Err(err) = ops::DeadLetter::open(&dead_letter) {
+            eprintln!(
+                "error: cannot open sink dead-letter file {}: {}",
+                dead_letter.display(),
+                err
+            );
+            std::process::exit(1);
+        }
+
+        if let Some(late_data) = &conf.window.late_data {
+            let late_data = conf.side_output(late_data, name);
+            if let Err(err) = ops::DeadLetter::open(&late_data) {
+                eprintln!(
+                    "error: cannot open late-data file {}: {}",
+                    late_data.display(),
+                    err
+                );
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let templates = [
+        (
+            &conf.sink.id_template,
+            serde_json::to_value(RideWindowedData::default())
+                .expect("output types serialize to JSON"),
+        ),
+        (
+            &conf.sink.od_id_template,
+            serde_json::to_value(OdWindowedData::default())
+                .expect("output types serialize to JSON"),
+        ),
+    ];
+    for (template, document) in templates.iter() {
+        if let Err(err) = ops::render_id(template, document) {
+            eprintln!("error: {}", err);
+            std::process::exit(1);
+        }
+    }
+
+    let client = match ops::elasticsearch_client(&conf.sink) {
+        Ok(client) => client,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            std::process::exit(1);
+        }
+    };
+
+    if conf.sink.index_templates {
+        let runtime = tokio::runtime::Runtime::new().expect("failed to start tokio runtime");
+        for (index, properties) in index_properties(&conf) {
+            let ensured = runtime.block_on(mapping::ensure_index_template(
+                &client,
+                &index,
+                &properties,
+                conf.sink.data_stream,
+            ));
+            if let Err(err) = ensured {
+                eprintln!("error: {}", err);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    match conf.source.kind {
+        SourceKind::File => {
+            let format = conf.source.format;
+            let plausible = 1..u64::MAX;
+            let files = source::input_files(&conf.source.path, format).and_then(|files| {
+                match format {
+                    InputFormat::Csv => source::csv_files(&files, plausible).map(|_| ()),
+                    InputFormat::Parquet => source::parquet_files(&files, plausible).map(|_| ()),
+                }?;
+                Ok(files)
             });
-            cfg.set_arcon_time(ArconTime::Event);
-            cfg.set_batch_size(4000);
+            match files {
+                Ok(files) => println!("Reading {} input files", files.len()),
+                Err(err) => {
+                    eprintln!("error: {}", err);
+                    std::process::exit(1);
+                }
+            }
+        }
+        SourceKind::Kafka => {
+            if let Err(err) = kafka::check_topic(&conf.kafka) {
+                eprintln!(
+                    "error: cannot read Kafka topic {} from {}: {}",
+                    conf.kafka.topic, conf.kafka.brokers, err
+                );
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let zones = match ZoneTable::load(&conf.zones.path) {
+        Ok(zones) if zones.is_empty() => {
+            eprintln!("error: no zones found in {}", conf.zones.path.display());
+            std::process::exit(1);
+        }
+        Ok(zones) => Arc::new(zones),
+        Err(err) => {
+            eprintln!("error: {}", err);
+            std::process::exit(1);
+        }
+    };
+    println!("Loaded {} taxi zones", zones.len());
+
+    let store = Arc::new(QueryStore::new(conf.query.retention));
+    if let Some(listen) = &conf.query.listen {
+        if let Err(err) = query::serve(store.clone(), listen) {
+            eprintln!("error: cannot serve queries on {}: {}", listen, err);
+            std::process::exit(1);
+        }
+        println!("Serving queries on http://{}", listen);
+    }
+
+    let mut handles = Vec::new();
+    if !conf.aggregate.directions.is_empty() {
+        let conf = conf.clone();
+        let zones = zones.clone();
+        let client = client.clone();
+        let store = store.clone();
+        handles.push(std::thread::spawn(move || {
+            run_pipeline(conf, zones, client, store)
+        }));
+    }
+    if conf.aggregate.od {
+        let conf = conf.clone();
+        let zones = zones.clone();
+        let client = client.clone();
+        handles.push(std::thread::spawn(move || {
+            run_od_pipeline(conf, zones, client)
+        }));
+    }
+
+    for handle in handles {
+        handle.join().expect("pipeline thread panicked");
+    }
+}
+
+/// Mapping of every index the configured pipelines write to.
+fn index_properties(conf: &TaxiConf) -> Vec<(String, serde_json::Map<String, serde_json::Value>)> {
+    let data_stream = conf.sink.data_stream;
+    let mut indices = Vec::new();
+    if !conf.aggregate.directions.is_empty() {
+        indices.push((
+            conf.sink.index.clone(),
+            mapping::document_properties(RideWindowedData::es_properties(), data_stream),
+        ));
+    }
+    if conf.aggregate.od {
+        indices.push((
+            conf.sink.od_index.clone(),
+            mapping::document_properties(OdWindowedData::es_properties(), data_stream),
+        ));
+    }
+    indices
+}
+
+/// Reads and parses the input into rides, checks them against the
+/// data-quality rules, counts the late ones and emits every ride once per
+/// key function.
+///
+/// With a Kafka source, also returns the offsets that the sink commits on
+/// every epoch.
+fn rides(
+    conf: &TaxiConf,
+    name: &'static str,
+    keys: Vec<fn(RideData) -> RideData>,
+) -> (Stream<RideData>, Option<Arc<kafka::EpochOffsets>>) {
+    let files = || {
+        source::input_files(&conf.source.path, conf.source.format)
+            .expect("input files were found at startup")
+    };
+    let speedup = conf.source.replay_speedup;
+    let shuffle = conf.source.replay_shuffle;
+    let seed = conf.source.replay_seed;
+    let lateness = conf.lateness();
+    let late_data = conf
+        .window
+        .late_data
+        .as_ref()
+        .map(|late_data| conf.side_output(late_data, name));
+    let quality_action = conf.quality.action;
+    let quality_rules = conf
+        .quality_rules()
+        .expect("quality rules were validated with the config");
+
+    let pipeline = Pipeline::with_conf(conf.arcon_conf(name));
+    let mut offsets = None;
+    let rides = match (conf.source.kind, conf.source.format) {
+        (SourceKind::Kafka, _) => {
+            let consumer = kafka::consumer(&conf.kafka, name)
+                .map(Arc::new)
+                .expect("Kafka topic was checked at startup");
+            let epochs = Arc::new(kafka::EpochOffsets::new(
+                consumer.clone(),
+                conf.kafka.topic.clone(),
+            ));
+            offsets = Some(epochs.clone());
+            let payload = conf.kafka.payload;
+            let plausible = quality_rules.plausible_times();
+            let records = pipeline
+                .source(SourceBuilder {
+                    constructor: Arc::new(move |_| {
+                        kafka::KafkaSource::new(consumer.clone(), payload, plausible.clone())
+                    }),
+                    conf: source_conf(conf, |record: &KafkaRecord| record.watermark),
+                })
+                .operator(OperatorBuilder {
+                    constructor: Arc::new(move |_| kafka::TrackOffsets::new(epochs.clone())),
+                    conf: OperatorConf {
+                        parallelism_strategy: ParallelismStrategy::Static(1),
+                        ..Default::default()
+                    },
+                });
+            parse_rides(records, conf, name)
+        }
+        (SourceKind::File, InputFormat::Csv) => {
+            let files = files();
+            let plausible = quality_rules.plausible_times();
+            let lines = pipeline.source(SourceBuilder {
+                constructor: Arc::new(move |_| {
+                    let files = source::csv_files(&files, plausible.clone())
+                        .expect("input files were opened at startup");
+                    source::Replay::new(files, speedup, shuffle, seed)
+                }),
+                conf: source_conf(conf, RawLine::pickup_time),
+            });
+            parse_rides(lines, conf, name)
+        }
+        (SourceKind::File, InputFormat::Parquet) => {
+            let files = files();
+            let plausible = quality_rules.plausible_times();
+            let records = pipeline.source(SourceBuilder {
+                constructor: Arc::new(move |_| {
+                    let files = source::parquet_files(&files, plausible.clone())
+                        .expect("input files were opened at startup");
+                    source::Replay::new(files, speedup, shuffle, seed)
+                }),
+                conf: source_conf(conf, ParquetRow::pickup_time),
+            });
+            parse_rides(records, conf, name)
+        }
+    };
+
+    let rides = rides
+        .operator(OperatorBuilder {
+            constructor: Arc::new(move |_| {
+                ops::QualityFilter::new(name, quality_rules.clone(), quality_action)
+            }),
+            conf: OperatorConf {
+                parallelism_strategy: ParallelismStrategy::Static(1),
+                ..Default::default()
+            },
         })
         .operator(OperatorBuilder {
-            constructor: Arc::new(|_| Map::new(|x: TaxiRideData| RideData::from(x))),
+            constructor: Arc::new(move |_| {
+                let late_data = late_data.as_ref().map(|late_data| {
+                    ops::DeadLetter::open(late_data).expect("late-data file was opened at startup")
+                });
+                ops::LateRides::new(name, lateness, late_data)
+            }),
+            conf: OperatorConf {
+                parallelism_strategy: ParallelismStrategy::Static(1),
+                ..Default::default()
+            },
+        })
+        .operator(OperatorBuilder {
+            constructor: Arc::new(move |_| ops::KeyRides::new(keys.clone())),
+            conf: OperatorConf {
+                parallelism_strategy: ParallelismStrategy::Static(1),
+                ..Default::default()
+            },
+        });
+    (rides, offsets)
+}
+
+fn source_conf<A: ArconType>(conf: &TaxiConf, time: fn(&A) -> u64) -> SourceConf<A> {
+    let mut source_conf = SourceConf::default();
+    let plausible = conf
+        .quality_rules()
+        .expect("quality rules were validated with the config")
+        .plausible_times();
+    source_conf.set_timestamp_extractor(source::bounded_out_of_orderness(
+        time,
+        conf.source.max_delay,
+        plausible,
+    ));
+    source_conf.set_arcon_time(ArconTime::Event);
+    source_conf.set_batch_size(conf.source.batch_size);
+    source_conf
+}
+
+/// Parses input records into rides.
+fn parse_rides<IN: ops::RideRecord>(
+    records: Stream<IN>,
+    conf: &TaxiConf,
+    name: &'static str,
+) -> Stream<RideData> {
+    let dead_letter = conf.side_output(&conf.source.dead_letter, name);
+    records.operator(OperatorBuilder {
+        constructor: Arc::new(move |_| {
+            let dead_letter = ops::DeadLetter::open(&dead_letter)
+                .expect("dead-letter file was opened at startup");
+            ops::ParseRides::<IN>::new(name, dead_letter)
+        }),
+        conf: OperatorConf {
+            parallelism_strategy: ParallelismStrategy::Static(1),
+            ..Default::default()
+        },
+    })
+}
+
+/// Runs the pipeline aggregating rides per zone, by every configured end of
+/// the trip.
+fn run_pipeline(
+    conf: TaxiConf,
+    zones: Arc<ZoneTable>,
+    client: Elasticsearch,
+    store: Arc<QueryStore>,
+) {
+    let window_kind = conf.window.kind;
+    let aggregation = conf.window.aggregation;
+    let window_length = conf.window_length();
+    let window_slide = conf.window_slide();
+    let session_gap = conf.session_gap();
+    let late_arrival = conf.late_arrival();
+    let late_updates = conf.window.late_updates;
+    let mut sink = conf.sink.clone();
+    sink.dead_letter = conf.side_output(&sink.dead_letter, "zones");
+
+    let keys = conf
+        .aggregate
+        .directions
+        .iter()
+        .map(|direction| -> fn(RideData) -> RideData {
+            match direction {
+                Direction::Pickup => |ride| ride.keyed_by(Direction::Pickup),
+                Direction::Dropoff => |ride| ride.keyed_by(Direction::Dropoff),
+            }
+        })
+        .collect();
+    let (rides, offsets) = rides(&conf, "zones", keys);
+
+    let window_conf = OperatorConf {
+        parallelism_strategy: ParallelismStrategy::Static(1),
+        ..Default::default()
+    };
+
+    let windowed = match (window_kind, aggregation) {
+        (WindowKind::Session, _) => rides.operator(OperatorBuilder {
+            constructor: Arc::new(move |backend| {
+                SessionWindow::new(backend, session_gap, late_arrival)
+            }),
+            conf: window_conf,
+        }),
+        (_, Aggregation::Incremental) if late_updates => rides.operator(OperatorBuilder {
+            constructor: Arc::new(move |backend| {
+                UpdatingWindow::new(
+                    backend,
+                    window_kind,
+                    window_length,
+                    window_slide,
+                    late_arrival,
+                )
+            }),
+            conf: window_conf,
+        }),
+        (_, Aggregation::Incremental) => rides
+            .operator(OperatorBuilder {
+                constructor: Arc::new(move |backend| {
+                    window::incremental_window(
+                        backend,
+                        window_kind,
+                        window_length,
+                        window_slide,
+                        late_arrival,
+                    )
+                }),
+                conf: window_conf,
+            })
+            .operator(OperatorBuilder {
+                constructor: Arc::new(|_| Map::new(agg::window_finish)),
+                conf: Default::default(),
+            })
+            .operator(OperatorBuilder {
+                constructor: Arc::new(move |_| WindowBounds::new(window_length)),
+                conf: Default::default(),
+            }),
+        (_, Aggregation::Appender) => rides
+            .operator(OperatorBuilder {
+                constructor: Arc::new(move |backend| {
+                    window::appender_window(
+                        backend,
+                        window_kind,
+                        window_length,
+                        window_slide,
+                        late_arrival,
+                    )
+                }),
+                conf: window_conf,
+            })
+            .operator(OperatorBuilder {
+                constructor: Arc::new(move |_| WindowBounds::new(window_length)),
+                conf: Default::default(),
+            }),
+    };
+
+    let mut pipeline = windowed
+        .operator(OperatorBuilder {
+            constructor: Arc::new(move |_| {
+                let zones = zones.clone();
+                Map::new(move |mut data: RideWindowedData| {
+                    let (direction, location_id) = data::split_key(data.location_id);
+                    data.location_id = location_id;
+                    data.direction = direction.to_string();
+                    zones.annotate(data)
+                })
+            }),
+            conf: Default::default(),
+        })
+        .operator(OperatorBuilder {
+            constructor: Arc::new(move |backend| {
+                let store = store.clone();
+                // The query API starts from the windows restored from the
+                // last snapshot
+                let mut state = RideState::new(backend);
+                let restored: Vec<RideWindowedData> = state
+                    .rides()
+                    .iter()
+                    .expect("failed to read the restored ride state")
+                    .map(|(_, data)| data.clone())
+                    .collect();
+                for key in store.restore(restored) {
+                    state
+                        .rides()
+                        .remove(&key)
+                        .expect("failed to evict a restored window");
+                }
+                Map::stateful(state, move |ride_per_location: RideWindowedData, state| {
+                    for key in store.insert(ride_per_location.clone()) {
+                        state.rides().remove(&key)?;
+                    }
+                    state
+                        .rides()
+                        .put(RideKey::of(&ride_per_location), ride_per_location.clone())?;
+                    Ok(ride_per_location)
+                })
+            }),
             conf: Default::default(),
         })
         .operator(OperatorBuilder {
-            constructor: Arc::new(|backend| {
-                let function = AppenderWindow::new(backend.clone(), &window_sum);
-                let window_length = Time::days(1);
-                let late_arrival = Time::days(1);
-                WindowAssigner::tumbling(function, backend, window_length, late_arrival, true)
+            constructor: Arc::new(move |_| {
+                ops::Kibana::new(
+                    sink.index.clone(),
+                    sink.id_template.clone(),
+                    client.clone(),
+                    &sink,
+                )
+                .with_offsets(offsets.clone())
             }),
             conf: OperatorConf {
                 parallelism_strategy: ParallelismStrategy::Static(1),
                 ..Default::default()
             },
         })
+        .to_console()
+        .build();
+    pipeline.start();
+    pipeline.await_termination();
+}
+
+/// Runs the pipeline aggregating rides per (pickup, dropoff) zone pair.
+fn run_od_pipeline(conf: TaxiConf, zones: Arc<ZoneTable>, client: Elasticsearch) {
+    let window_kind = conf.window.kind;
+    let window_length = conf.window_length();
+    let window_slide = conf.window_slide();
+    let late_arrival = conf.late_arrival();
+    let top_k = conf.aggregate.top_k;
+    let mut sink = conf.sink.clone();
+    sink.dead_letter = conf.side_output(&sink.dead_letter, "od");
+
+    let keys = vec![RideData::keyed_by_pair as fn(RideData) -> RideData];
+    let (rides, offsets) = rides(&conf, "od", keys);
+    let windowed = rides
         .operator(OperatorBuilder {
-            constructor: Arc::new(|backend| {
-                Map::stateful(
-                    RideState::new(backend),
-                    |ride_per_location: RideWindowedData, state| {
-                        state.rides().put(ride_per_location.clone())?;
-                        Ok(ride_per_location)
-                    },
+            constructor: Arc::new(move |backend| {
+                window::od_window(
+                    backend,
+                    window_kind,
+                    window_length,
+                    window_slide,
+                    late_arrival,
                 )
             }),
+            conf: OperatorConf {
+                parallelism_strategy: ParallelismStrategy::Static(1),
+                ..Default::default()
+            },
+        })
+        .operator(OperatorBuilder {
+            constructor: Arc::new(|_| Map::new(agg::od_finish)),
+            conf: Default::default(),
+        })
+        .operator(OperatorBuilder {
+            constructor: Arc::new(move |_| WindowBounds::new(window_length)),
+            conf: Default::default(),
+        });
+
+    let ranked = if top_k > 0 {
+        windowed.operator(OperatorBuilder {
+            constructor: Arc::new(move |backend| ops::TopKPerOrigin::new(backend, top_k)),
+            conf: OperatorConf {
+                parallelism_strategy: ParallelismStrategy::Static(1),
+                ..Default::default()
+            },
+        })
+    } else {
+        windowed
+    };
+
+    let mut pipeline = ranked
+        .operator(OperatorBuilder {
+            constructor: Arc::new(move |_| {
+                let zones = zones.clone();
+                Map::new(move |data: OdWindowedData| zones.annotate_pair(data))
+            }),
             conf: Default::default(),
         })
         .operator(OperatorBuilder {
-            constructor: Arc::new(|_| ops::Kibana::default()),
+            constructor: Arc::new(move |_| {
+                ops::Kibana::new(
+                    sink.od_index.clone(),
+                    sink.od_id_template.clone(),
+                    client.clone(),
+                    &sink,
+                )
+                .with_offsets(offsets.clone())
+            }),
             conf: OperatorConf {
                 parallelism_strategy: ParallelismStrategy::Static(1),
                 ..Default::default()
@@ -0,0 +1,166 @@
+use elasticsearch::indices::{
+    IndicesGetIndexTemplateParts, IndicesGetMappingParts, IndicesPutIndexTemplateParts,
+};
+use elasticsearch::Elasticsearch;
+use serde_json::{json, Map, Value};
+use std::fmt;
+
+#[derive(Debug)]
+pub enum MappingError {
+    Request(String, elasticsearch::Error),
+    Status(String, u16, String),
+    /// An existing template or index maps fields differently than the output
+    /// type
+    Mismatch(String, Vec<String>),
+}
+
+impl fmt::Display for MappingError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MappingError::Request(index, err) => {
+                write!(f, "failed to check the mapping of {}: {}", index, err)
+            }
+            MappingError::Status(index, status, body) => write!(
+                f,
+                "unexpected HTTP {} while checking the mapping of {}: {}",
+                status, index, body
+            ),
+            MappingError::Mismatch(name, fields) => write!(
+                f,
+                "the mapping of {} does not match the output type ({}), \
+                 delete or migrate it, or use another index name",
+                name,
+                fields.join(", ")
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MappingError {}
+
+/// Mapping of the documents written by a sink: the fields of the output type
+/// and the time fields the sink adds.
+pub fn document_properties(
+    mut properties: Map<String, Value>,
+    data_stream: bool,
+) -> Map<String, Value> {
+    properties.insert("time".to_owned(), json!({ "type": "date" }));
+    if data_stream {
+        properties.insert("@timestamp".to_owned(), json!({ "type": "date" }));
+    }
+    properties
+}
+
+/// Index template applying `properties` to `index` and its time-based
+/// indices, or to the data stream `index`.
+pub fn index_template(index: &str, properties: &Map<String, Value>, data_stream: bool) -> Value {
+    let mut template = json!({
+        "index_patterns": [index, format!("{}-*", index)],
+        "template": { "mappings": { "properties": properties } },
+    });
+    if data_stream {
+        template["index_patterns"] = json!([index]);
+        template["data_stream"] = json!({});
+    }
+    template
+}
+
+/// Fields of `expected` that are missing or of another type in `actual`.
+fn mismatches(expected: &Map<String, Value>, actual: &Value) -> Vec<String> {
+    expected
+        .iter()
+        .filter_map(|(field, mapping)| {
+            let expected_type = &mapping["type"];
+            match &actual[field]["type"] {
+                Value::Null => Some(format!("{} is missing", field)),
+                actual_type if actual_type != expected_type => Some(format!(
+                    "{} is {} instead of {}",
+                    field, actual_type, expected_type
+                )),
+                _ if actual[field]["format"] != mapping["format"] => {
+                    Some(format!("{} has format {}", field, actual[field]["format"]))
+                }
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// Creates the index template of `index` if there is none, and verifies
+/// that the template and the existing indices map every field like
+/// `properties`.
+pub async fn ensure_index_template(
+    client: &Elasticsearch,
+    index: &str,
+    properties: &Map<String, Value>,
+    data_stream: bool,
+) -> Result<(), MappingError> {
+    let request_error = |err| MappingError::Request(index.to_owned(), err);
+
+    let response = client
+        .indices()
+        .get_index_template(IndicesGetIndexTemplateParts::Name(index))
+        .send()
+        .await
+        .map_err(request_error)?;
+    match response.status_code().as_u16() {
+        404 => {
+            let response = client
+                .indices()
+                .put_index_template(IndicesPutIndexTemplateParts::Name(index))
+                .body(index_template(index, properties, data_stream))
+                .send()
+                .await
+                .map_err(request_error)?;
+            let status = response.status_code().as_u16();
+            if !response.status_code().is_success() {
+                let body = response.text().await.unwrap_or_default();
+                return Err(MappingError::Status(index.to_owned(), status, body));
+            }
+            println!("Created index template {}", index);
+        }
+        status if (200..300).contains(&status) => {
+            let body: Value = response.json().await.map_err(request_error)?;
+            let actual =
+                &body["index_templates"][0]["index_template"]["template"]["mappings"]["properties"];
+            let fields = mismatches(properties, actual);
+            if !fields.is_empty() {
+                return Err(MappingError::Mismatch(
+                    format!("index template {}", index),
+                    fields,
+                ));
+            }
+        }
+        status => {
+            let body = response.text().await.unwrap_or_default();
+            return Err(MappingError::Status(index.to_owned(), status, body));
+        }
+    }
+
+    // Templates only apply to new indices, so check the existing ones too
+    let time_based = format!("{}-*", index);
+    let patterns = [index, time_based.as_str()];
+    let response = client
+        .indices()
+        .get_mapping(IndicesGetMappingParts::Index(&patterns))
+        .allow_no_indices(true)
+        .ignore_unavailable(true)
+        .send()
+        .await
+        .map_err(request_error)?;
+    let status = response.status_code().as_u16();
+    if !response.status_code().is_success() {
+        let body = response.text().await.unwrap_or_default();
+        return Err(MappingError::Status(index.to_owned(), status, body));
+    }
+    let body: Value = response.json().await.map_err(request_error)?;
+    if let Value::Object(indices) = body {
+        for (name, mapping) in indices {
+            let fields = mismatches(properties, &mapping["mappings"]["properties"]);
+            if !fields.is_empty() {
+                return Err(MappingError::Mismatch(format!("index {}", name), fields));
+            }
+        }
+    }
+    Ok(())
+}
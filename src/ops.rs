@@ -1,94 +1,1426 @@
 use arcon::prelude::*;
+use elasticsearch::auth::Credentials;
+use elasticsearch::cert::{Certificate, CertificateValidation};
+use elasticsearch::http::request::JsonBody;
+use elasticsearch::http::transport::{SingleNodeConnectionPool, TransportBuilder};
+use elasticsearch::http::Url;
+use elasticsearch::BulkParts;
 use elasticsearch::Elasticsearch;
+use serde::Serialize;
 use serde_json::json;
+use std::convert::TryFrom;
+use std::fmt;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io::{BufWriter, Write};
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
 use tokio::runtime::Runtime;
 
+use crate::conf::SinkSection;
 use crate::data;
+use crate::data::u64_to_datetime;
+use crate::kafka::EpochOffsets;
+use crate::parse::{self, ParseCounters};
+use crate::quality::{QualityAction, QualityCounters, QualityRules};
 
-pub struct Kibana {
+/// Append-only file of rejected input, one `origin<TAB>line<TAB>reason<TAB>raw`
+/// record per row, or of documents that could not be indexed.
+pub struct DeadLetter {
+    writer: BufWriter<File>,
+}
+
+impl DeadLetter {
+    pub fn open(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            writer: BufWriter::new(file),
+        })
+    }
+
+    pub fn write(&mut self, origin: &str, line: u64, reason: &str, raw: &str) {
+        if let Err(err) = writeln!(self.writer, "{}\t{}\t{}\t{}", origin, line, reason, raw) {
+            eprintln!("Failed to write to dead-letter file: {}", err);
+        }
+    }
+
+    /// Writes a document that could not be indexed as an
+    /// `index<TAB>id<TAB>reason<TAB>document` record.
+    pub fn write_document(
+        &mut self,
+        index: &str,
+        id: &str,
+        reason: &str,
+        document: &serde_json::Value,
+    ) {
+        let reason = reason.replace(|c| c == '\t' || c == '\n', " ");
+        if let Err(err) = writeln!(self.writer, "{}\t{}\t{}\t{}", index, id, reason, document) {
+            eprintln!("Failed to write to dead-letter file: {}", err);
+        }
+    }
+
+    /// Writes a record as a line of JSON.
+    pub fn write_json(&mut self, record: &impl Serialize) {
+        let written = serde_json::to_writer(&mut self.writer, record)
+            .map_err(std::io::Error::from)
+            .and_then(|_| writeln!(self.writer));
+        if let Err(err) = written {
+            eprintln!("Failed to write to dead-letter file: {}", err);
+        }
+    }
+
+    pub fn flush(&mut self) {
+        if let Err(err) = self.writer.flush() {
+            eprintln!("Failed to flush dead-letter file: {}", err);
+        }
+    }
+}
+
+/// Input record that a ride is parsed from.
+pub trait RideRecord: ArconType {
+    fn parse(&self) -> Result<data::TaxiRideData, parse::ParseError>;
+
+    /// The record as written to the dead-letter file.
+    fn raw(&self) -> String;
+
+    /// File and line number, or Kafka partition and offset, that the record
+    /// was read from.
+    fn origin(&self) -> (String, u64);
+}
+
+impl RideRecord for data::RawLine {
+    fn parse(&self) -> Result<data::TaxiRideData, parse::ParseError> {
+        parse::parse_taxi_ride(&self.line)
+    }
+
+    fn raw(&self) -> String {
+        self.line.clone()
+    }
+
+    fn origin(&self) -> (String, u64) {
+        (self.path.clone(), self.line_number)
+    }
+}
+
+impl RideRecord for data::ParquetRow {
+    fn parse(&self) -> Result<data::TaxiRideData, parse::ParseError> {
+        if self.error.is_empty() {
+            Ok(self.ride.clone())
+        } else {
+            Err(parse::ParseError::new(
+                parse::ParseErrorKind::Parquet,
+                "",
+                self.error.clone(),
+            ))
+        }
+    }
+
+    fn raw(&self) -> String {
+        format!("{:?}", self.ride)
+    }
+
+    /// File and index of the row, starting at 0.
+    fn origin(&self) -> (String, u64) {
+        (self.path.clone(), self.row)
+    }
+}
+
+/// Parses raw CSV lines or trip records into `RideData`.
+///
+/// Malformed rows are written to a dead-letter file together with where
+/// they were read from and the reason they were rejected, and are counted
+/// per error kind. The counters are printed on every epoch.
+pub struct ParseRides<IN> {
+    state: (),
+    name: &'static str,
+    dead_letter: DeadLetter,
+    counters: ParseCounters,
+    _marker: PhantomData<IN>,
+}
+
+impl<IN: RideRecord> ParseRides<IN> {
+    pub fn new(name: &'static str, dead_letter: DeadLetter) -> Self {
+        Self {
+            state: (),
+            name,
+            dead_letter,
+            counters: ParseCounters::default(),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Parses a record into a ride, or writes it to the dead-letter file.
+    fn parse(&mut self, record: &IN) -> Option<data::RideData> {
+        let parsed = record.parse().and_then(data::RideData::try_from);
+        self.counters.count(&parsed);
+        match parsed {
+            Ok(ride) => Some(ride),
+            Err(err) => {
+                let (origin, line) = record.origin();
+                self.dead_letter
+                    .write(&origin, line, &err.to_string(), &record.raw());
+                None
+            }
+        }
+    }
+}
+
+impl<IN: RideRecord> Operator for ParseRides<IN> {
+    type IN = IN;
+    type OUT = data::RideData;
+    type TimerState = ArconNever;
+    type OperatorState = ();
+
+    fn handle_element(
+        &mut self,
+        element: ArconElement<Self::IN>,
+        mut ctx: OperatorContext<Self, impl Backend, impl ComponentDefinition>,
+    ) -> ArconResult<()> {
+        if let Some(ride) = self.parse(&element.data) {
+            // Sources stamp records with their watermark, the latest
+            // plausible pickup time minus `source.max_delay`, see
+            // `source::bounded_out_of_orderness`. Windows need the pickup
+            // time of the ride itself.
+            let timestamp = data::local_time(ride.pu_time);
+            ctx.output(ArconElement::with_timestamp(ride, timestamp));
+        }
+        Ok(())
+    }
+
+    arcon::ignore_timeout!();
+
+    fn persist(&mut self) -> Result<(), ArconStateError> {
+        self.dead_letter.flush();
+        if !self.counters.rejected.is_empty() {
+            println!("Parsed rows ({}): {}", self.name, self.counters);
+        }
+        Ok(())
+    }
+
+    fn state(&mut self) -> &mut Self::OperatorState {
+        &mut self.state
+    }
+}
+
+/// Checks rides against data-quality rules before they are aggregated.
+///
+/// Rides breaking a rule are dropped or tagged in `RideData::quality_flags`,
+/// depending on the action. Broken rules are counted per rule, and the
+/// counters are printed on every epoch.
+pub struct QualityFilter {
     state: (),
+    name: &'static str,
+    rules: QualityRules,
+    action: QualityAction,
+    counters: QualityCounters,
+}
+
+impl QualityFilter {
+    pub fn new(name: &'static str, rules: QualityRules, action: QualityAction) -> Self {
+        Self {
+            state: (),
+            name,
+            rules,
+            action,
+            counters: QualityCounters::default(),
+        }
+    }
+
+    /// Checks a ride, and returns it with its `quality_flags` set unless it
+    /// is dropped.
+    fn filter(&mut self, mut ride: data::RideData) -> Option<data::RideData> {
+        if self.action == QualityAction::Off {
+            return Some(ride);
+        }
+        ride.quality_flags = self.rules.check(&ride);
+        self.counters.count(ride.quality_flags);
+        if self.action == QualityAction::Drop && ride.quality_flags != 0 {
+            return None;
+        }
+        Some(ride)
+    }
+}
+
+impl Operator for QualityFilter {
+    type IN = data::RideData;
+    type OUT = data::RideData;
+    type TimerState = ArconNever;
+    type OperatorState = ();
+
+    fn handle_element(
+        &mut self,
+        element: ArconElement<Self::IN>,
+        mut ctx: OperatorContext<Self, impl Backend, impl ComponentDefinition>,
+    ) -> ArconResult<()> {
+        if let Some(ride) = self.filter(element.data) {
+            ctx.output(ArconElement {
+                data: ride,
+                timestamp: element.timestamp,
+            });
+        }
+        Ok(())
+    }
+
+    arcon::ignore_timeout!();
+
+    fn persist(&mut self) -> Result<(), ArconStateError> {
+        if self.counters.flagged > 0 {
+            println!(
+                "Broken data-quality rules ({}, {}): {}",
+                self.name,
+                match self.action {
+                    QualityAction::Tag => "tagged",
+                    _ => "dropped",
+                },
+                self.counters
+            );
+        }
+        Ok(())
+    }
+
+    fn state(&mut self) -> &mut Self::OperatorState {
+        &mut self.state
+    }
+}
+
+/// Emits every ride once per key function.
+///
+/// The windows of several directions are fed by a single pipeline this way,
+/// which reads and parses the input only once.
+pub struct KeyRides {
+    state: (),
+    keys: Vec<fn(data::RideData) -> data::RideData>,
+}
+
+impl KeyRides {
+    pub fn new(keys: Vec<fn(data::RideData) -> data::RideData>) -> Self {
+        Self { state: (), keys }
+    }
+}
+
+impl Operator for KeyRides {
+    type IN = data::RideData;
+    type OUT = data::RideData;
+    type TimerState = ArconNever;
+    type OperatorState = ();
+
+    fn handle_element(
+        &mut self,
+        element: ArconElement<Self::IN>,
+        mut ctx: OperatorContext<Self, impl Backend, impl ComponentDefinition>,
+    ) -> ArconResult<()> {
+        for key in &self.keys {
+            ctx.output(ArconElement {
+                data: key(element.data.clone()),
+                timestamp: element.timestamp,
+            });
+        }
+        Ok(())
+    }
+
+    arcon::ignore_timeout!();
+    arcon::ignore_persist!();
+
+    fn state(&mut self) -> &mut Self::OperatorState {
+        &mut self.state
+    }
+}
+
+/// Windows that rides are assigned to. A session window is seen as sliding
+/// windows of `gap` seconds that slide by every second, since the last
+/// session a ride can belong to closes `gap` seconds after it.
+#[derive(Debug, Clone, Copy)]
+pub struct Lateness {
+    pub length: u64,
+    pub slide: u64,
+    pub late_arrival: u64,
+}
+
+impl Lateness {
+    /// Whether every window of a ride is closed, in which case the window
+    /// drops it.
+    fn too_late(&self, time: u64, watermark: u64) -> bool {
+        let last_window_end = time / self.slide * self.slide + self.length;
+        last_window_end + self.late_arrival <= watermark
+    }
+}
+
+/// Counts rides that arrive after the watermark, and removes those that are
+/// too late for all their windows.
+///
+/// Rides that are too late are written to the late-data file, if any, and
+/// are otherwise dropped, as the window would drop them. The counters are
+/// printed on every epoch.
+pub struct LateRides {
+    state: (),
+    name: &'static str,
+    lateness: Lateness,
+    late_data: Option<DeadLetter>,
+    after_watermark: u64,
+    dropped: u64,
+    routed: u64,
+}
+
+impl LateRides {
+    pub fn new(name: &'static str, lateness: Lateness, late_data: Option<DeadLetter>) -> Self {
+        Self {
+            state: (),
+            name,
+            lateness,
+            late_data,
+            after_watermark: 0,
+            dropped: 0,
+            routed: 0,
+        }
+    }
+
+    /// Counts a ride at `time` if it is after the watermark, and returns
+    /// false if it is too late for all its windows.
+    fn admit(&mut self, time: Option<u64>, watermark: u64, ride: &data::RideData) -> bool {
+        let time = match time.filter(|&time| time < watermark) {
+            Some(time) => time,
+            None => return true,
+        };
+        self.after_watermark += 1;
+        if !self.lateness.too_late(time, watermark) {
+            return true;
+        }
+        match &mut self.late_data {
+            Some(late_data) => {
+                late_data.write_json(ride);
+                self.routed += 1;
+            }
+            None => self.dropped += 1,
+        }
+        false
+    }
+}
+
+impl Operator for LateRides {
+    type IN = data::RideData;
+    type OUT = data::RideData;
+    type TimerState = ArconNever;
+    type OperatorState = ();
+
+    fn handle_element(
+        &mut self,
+        element: ArconElement<Self::IN>,
+        mut ctx: OperatorContext<Self, impl Backend, impl ComponentDefinition>,
+    ) -> ArconResult<()> {
+        let watermark = ctx.current_time()?;
+        if self.admit(element.timestamp, watermark, &element.data) {
+            ctx.output(element);
+        }
+        Ok(())
+    }
+
+    arcon::ignore_timeout!();
+
+    fn persist(&mut self) -> Result<(), ArconStateError> {
+        if let Some(late_data) = &mut self.late_data {
+            late_data.flush();
+        }
+        if self.after_watermark > 0 {
+            println!(
+                "Late rides ({}): after_watermark={} dropped={} routed={}",
+                self.name, self.after_watermark, self.dropped, self.routed
+            );
+        }
+        Ok(())
+    }
+
+    fn state(&mut self) -> &mut Self::OperatorState {
+        &mut self.state
+    }
+}
+
+/// Pairs of an origin within one window, ordered by rank.
+#[macros::proto]
+#[derive(Arcon, Clone)]
+#[arcon(unsafe_ser_id = 12, reliable_ser_id = 13, version = 1)]
+pub struct TopKWindow {
+    /// Event time of the window end, see `data::local_time`
+    pub end: u64,
+    pub pairs: Vec<data::OdWindowedData>,
+}
+
+/// Windows of an origin whose top-K is not complete yet, sorted by end.
+#[macros::proto]
+#[derive(Arcon, Clone)]
+#[arcon(unsafe_ser_id = 12, reliable_ser_id = 13, version = 1)]
+pub struct PendingTopK {
+    pub windows: Vec<TopKWindow>,
+}
+
+/// Timer registered for an origin at the end of its earliest pending window.
+#[macros::proto]
+#[derive(Arcon, Clone)]
+#[arcon(unsafe_ser_id = 12, reliable_ser_id = 13, version = 1)]
+pub struct TopKTimeout {
+    pub pu_location_id: u64,
+}
+
+#[derive(ArconState)]
+pub struct TopKState<B: Backend> {
+    #[table = "top_k"]
+    pending: EagerHashTable<u64, PendingTopK, B>,
+}
+
+impl<B: Backend> StateConstructor for TopKState<B> {
+    type BackendType = B;
+
+    fn new(backend: Arc<Self::BackendType>) -> Self {
+        Self {
+            pending: EagerHashTable::new("_top_k", backend),
+        }
+    }
+}
+
+/// Adds a pair to the window ending at `end`, keeping the `k` pairs with the
+/// most rides. Ties are broken by destination.
+fn add_to_top_k(windows: &mut Vec<TopKWindow>, data: data::OdWindowedData, end: u64, k: usize) {
+    let i = match windows.binary_search_by_key(&end, |window| window.end) {
+        Ok(i) => i,
+        Err(i) => {
+            let window = TopKWindow {
+                end,
+                pairs: Vec::new(),
+            };
+            windows.insert(i, window);
+            i
+        }
+    };
+    let top = &mut windows[i].pairs;
+    top.push(data);
+    top.sort_by(|a, b| {
+        b.count
+            .cmp(&a.count)
+            .then(a.du_location_id.cmp(&b.du_location_id))
+    });
+    top.truncate(k);
+}
+
+/// Removes the windows that ended by `now`, with their pairs ranked.
+fn take_top_k(windows: &mut Vec<TopKWindow>, now: u64) -> Vec<TopKWindow> {
+    let due = windows
+        .iter()
+        .take_while(|window| window.end <= now)
+        .count();
+    windows
+        .drain(..due)
+        .map(|mut window| {
+            for (i, data) in window.pairs.iter_mut().enumerate() {
+                data.rank = i as u64 + 1;
+            }
+            window
+        })
+        .collect()
+}
+
+/// Keeps the `k` zone pairs with the most rides per origin and window.
+///
+/// A window emits all of its pairs before the watermark that closed it, so
+/// the top-K of a window is complete once the watermark reaches its end.
+/// Every origin has a single timer, at the end of its earliest pending
+/// window, and the pending pairs are kept in operator state.
+pub struct TopKPerOrigin<B: Backend> {
+    state: TopKState<B>,
+    k: usize,
+}
+
+impl<B: Backend> TopKPerOrigin<B> {
+    pub fn new(backend: Arc<B>, k: usize) -> Self {
+        Self {
+            state: TopKState::new(backend),
+            k,
+        }
+    }
+
+    /// Emits the windows of an origin that ended by `now`, and arms the
+    /// timer of the origin at the end of the earliest remaining one.
+    fn advance(
+        &self,
+        origin: u64,
+        pending: &mut PendingTopK,
+        mut now: u64,
+        ctx: &mut OperatorContext<Self, impl Backend, impl ComponentDefinition>,
+    ) {
+        loop {
+            for window in take_top_k(&mut pending.windows, now) {
+                for data in window.pairs {
+                    ctx.output(ArconElement::with_timestamp(data, window.end));
+                }
+            }
+            let deadline = match pending.windows.first() {
+                Some(window) => window.end,
+                None => return,
+            };
+            let timeout = TopKTimeout {
+                pu_location_id: origin,
+            };
+            // Fails if the watermark already passed the deadline, in which
+            // case the window is emitted right away
+            match ctx.schedule_at(origin, deadline, timeout) {
+                Ok(_) => return,
+                Err(_) => now = deadline,
+            }
+        }
+    }
+}
+
+impl<B: Backend> Operator for TopKPerOrigin<B> {
+    type IN = data::OdWindowedData;
+    type OUT = data::OdWindowedData;
+    type TimerState = TopKTimeout;
+    type OperatorState = TopKState<B>;
+
+    fn handle_element(
+        &mut self,
+        element: ArconElement<Self::IN>,
+        mut ctx: OperatorContext<Self, impl Backend, impl ComponentDefinition>,
+    ) -> ArconResult<()> {
+        let data = element.data;
+        let origin = data.pu_location_id;
+        let end = element
+            .timestamp
+            .unwrap_or_else(|| data::local_time(data.window_end));
+        let watermark = ctx.current_time()?;
+
+        let mut pending = self.state.pending.remove(&origin)?.unwrap_or_default();
+        add_to_top_k(&mut pending.windows, data, end, self.k);
+        self.advance(origin, &mut pending, watermark, &mut ctx);
+        if !pending.windows.is_empty() {
+            self.state.pending.put(origin, pending)?;
+        }
+        Ok(())
+    }
+
+    fn handle_timeout(
+        &mut self,
+        timeout: Self::TimerState,
+        mut ctx: OperatorContext<Self, impl Backend, impl ComponentDefinition>,
+    ) -> ArconResult<()> {
+        let origin = timeout.pu_location_id;
+        let now = ctx.current_time()?;
+        let mut pending = match self.state.pending.remove(&origin)? {
+            Some(pending) => pending,
+            None => return Ok(()),
+        };
+        self.advance(origin, &mut pending, now, &mut ctx);
+        if !pending.windows.is_empty() {
+            self.state.pending.put(origin, pending)?;
+        }
+        Ok(())
+    }
+
+    fn persist(&mut self) -> Result<(), ArconStateError> {
+        self.state.persist()
+    }
+
+    fn state(&mut self) -> &mut Self::OperatorState {
+        &mut self.state
+    }
+}
+
+/// Renders a document id from a template, replacing every `{field}` with
+/// the value of that top-level field of the document.
+pub fn render_id(template: &str, document: &serde_json::Value) -> Result<String, String> {
+    let mut id = String::new();
+    let mut rest = template;
+    while let Some(open) = rest.find('{') {
+        id.push_str(&rest[..open]);
+        let close = rest[open..]
+            .find('}')
+            .ok_or_else(|| format!("unclosed '{{' in id template '{}'", template))?;
+        let field = &rest[open + 1..open + close];
+        match &document[field] {
+            serde_json::Value::Null => {
+                return Err(format!(
+                    "unknown field '{}' in id template '{}'",
+                    field, template
+                ))
+            }
+            serde_json::Value::String(value) => id.push_str(value),
+            value => id.push_str(&value.to_string()),
+        }
+        rest = &rest[open + close + 1..];
+    }
+    id.push_str(rest);
+    Ok(id)
+}
+
+/// Timer flushing the documents buffered by a `Kibana` sink.
+#[macros::proto]
+#[derive(Arcon, Clone)]
+#[arcon(unsafe_ser_id = 12, reliable_ser_id = 13, version = 1)]
+pub struct FlushTimeout {
+    pub deadline: u64,
+}
+
+#[derive(Debug)]
+pub enum SinkError {
+    Url(String, String),
+    Io(PathBuf, std::io::Error),
+    Elasticsearch(elasticsearch::Error),
+}
+
+impl fmt::Display for SinkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SinkError::Url(url, err) => write!(f, "invalid Elasticsearch URL '{}': {}", url, err),
+            SinkError::Io(path, err) => write!(f, "failed to read {}: {}", path.display(), err),
+            SinkError::Elasticsearch(err) => write!(f, "invalid Elasticsearch client: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for SinkError {}
+
+/// Builds an Elasticsearch client from the sink configuration.
+pub fn elasticsearch_client(conf: &SinkSection) -> Result<Elasticsearch, SinkError> {
+    let url = Url::parse(&conf.url).map_err(|e| SinkError::Url(conf.url.clone(), e.to_string()))?;
+    let mut builder = TransportBuilder::new(SingleNodeConnectionPool::new(url));
+
+    if let Some(username) = &conf.username {
+        let password = conf.password.clone().unwrap_or_default();
+        builder = builder.auth(Credentials::Basic(username.clone(), password));
+    }
+    if let Some(path) = &conf.ca_cert {
+        let pem = std::fs::read(path).map_err(|e| SinkError::Io(path.clone(), e))?;
+        let cert = Certificate::from_pem(&pem).map_err(SinkError::Elasticsearch)?;
+        builder = builder.cert_validation(CertificateValidation::Full(cert));
+    } else if conf.tls_insecure {
+        builder = builder.cert_validation(CertificateValidation::None);
+    }
+
+    let transport = builder.build().map_err(SinkError::Elasticsearch)?;
+    Ok(Elasticsearch::new(transport))
+}
+
+/// Indexes documents into Elasticsearch with the `_bulk` API.
+///
+/// The body of a document is the serde serialization of the element, with
+/// its event time added as `time`. Document ids are rendered from the body
+/// with an id template, see `render_id`.
+///
+/// Documents are buffered and flushed when `bulk_size` of them are pending,
+/// `flush_interval` seconds of event time after the first pending one, and
+/// on every epoch.
+///
+/// Documents go to `index`, or to `index-<date>` with a date suffix
+/// formatted from the window start, and are created rather than indexed when
+/// `index` is a data stream.
+///
+/// Requests that fail with a connection error, 429 or 5xx are retried with
+/// exponential backoff, and so are single documents rejected with those
+/// statuses. Documents that keep failing, or that Elasticsearch rejects for
+/// another reason, are written to the sink's dead-letter file instead of
+/// stopping the pipeline.
+///
+/// With a Kafka source, the sink commits the offsets of every epoch once it
+/// has flushed the documents of the epoch, see `kafka::EpochOffsets`.
+pub struct Kibana<T> {
+    state: (),
+    index: String,
+    id_template: String,
+    index_date_suffix: Option<String>,
+    data_stream: bool,
     client: Elasticsearch,
     runtime: Runtime,
+    bulk_size: usize,
+    flush_interval: u64,
+    retry: Retry,
+    dead_letter: DeadLetter,
+    pending: Vec<BulkDocument>,
+    flush_scheduled: bool,
+    offsets: Option<Arc<EpochOffsets>>,
+    _marker: PhantomData<T>,
 }
 
-impl Default for Kibana {
-    fn default() -> Self {
+impl<T> Kibana<T> {
+    pub fn new(
+        index: impl Into<String>,
+        id_template: impl Into<String>,
+        client: Elasticsearch,
+        conf: &SinkSection,
+    ) -> Self {
         Self {
             state: (),
-            client: Elasticsearch::default(),
+            index: index.into(),
+            id_template: id_template.into(),
+            index_date_suffix: conf.index_date_suffix.clone(),
+            data_stream: conf.data_stream,
+            client,
             runtime: Runtime::new().unwrap(),
+            bulk_size: conf.bulk_size,
+            flush_interval: conf.flush_interval,
+            retry: Retry {
+                max_retries: conf.max_retries,
+                backoff: Duration::from_millis(conf.retry_backoff),
+            },
+            dead_letter: DeadLetter::open(&conf.dead_letter)
+                .expect("sink dead-letter file was opened at startup"),
+            pending: Vec::with_capacity(conf.bulk_size),
+            flush_scheduled: false,
+            offsets: None,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Commits the Kafka offsets of every epoch the sink persists.
+    pub fn with_offsets(mut self, offsets: Option<Arc<EpochOffsets>>) -> Self {
+        self.offsets = offsets;
+        self
+    }
+
+    fn flush(&mut self) {
+        if self.pending.is_empty() {
+            return;
+        }
+        let documents = std::mem::take(&mut self.pending);
+        let count = documents.len();
+        let op = if self.data_stream { "create" } else { "index" };
+        let failed = self
+            .runtime
+            .block_on(send_bulk(&self.client, op, documents, &self.retry));
+
+        if failed.is_empty() {
+            println!("Indexed {} documents into {}", count, self.index);
+            return;
+        }
+        eprintln!(
+            "Failed to index {} of {} documents into {}, see the sink dead-letter file",
+            failed.len(),
+            count,
+            self.index
+        );
+        for (document, reason) in failed {
+            self.dead_letter
+                .write_document(&document.index, &document.id, &reason, &document.body);
+        }
+        self.dead_letter.flush();
+    }
+
+    /// Buffers the document of an element, and sends the buffered documents
+    /// once `bulk_size` of them are pending.
+    fn push(&mut self, data: &T, timestamp: Option<u64>)
+    where
+        T: Serialize,
+    {
+        let timestamp = data::utc_time(timestamp.unwrap_or(0));
+        let time = u64_to_datetime(timestamp).to_rfc3339();
+        let mut body = match serde_json::to_value(data) {
+            Ok(body) => body,
+            Err(err) => {
+                eprintln!("Failed to serialize a document for {}: {}", self.index, err);
+                return;
+            }
+        };
+        if self.data_stream {
+            body["@timestamp"] = json!(time);
+        }
+        body["time"] = json!(time);
+
+        let window_start = body["window_start"].as_u64().unwrap_or(timestamp);
+        let index = self.index_of(window_start);
+        match render_id(&self.id_template, &body) {
+            Ok(id) => self.pending.push(BulkDocument { index, id, body }),
+            Err(reason) => {
+                self.dead_letter.write_document(&index, "", &reason, &body);
+                self.dead_letter.flush();
+            }
+        }
+
+        if self.pending.len() >= self.bulk_size {
+            self.flush();
+        }
+    }
+
+    /// Index of a document with the given window start.
+    fn index_of(&self, window_start: u64) -> String {
+        match &self.index_date_suffix {
+            Some(format) => format!(
+                "{}-{}",
+                self.index,
+                u64_to_datetime(window_start).format(format)
+            ),
+            None => self.index.clone(),
         }
     }
 }
 
-impl Operator for Kibana {
-    type IN = data::RideWindowedData;
+impl<T: ArconType + Serialize> Operator for Kibana<T> {
+    type IN = T;
     type OUT = ArconNever;
-    type TimerState = ArconNever;
+    type TimerState = FlushTimeout;
     type OperatorState = ();
 
     fn handle_element(
         &mut self,
         element: ArconElement<Self::IN>,
+        mut ctx: OperatorContext<Self, impl Backend, impl ComponentDefinition>,
+    ) -> ArconResult<()> {
+        self.push(&element.data, element.timestamp);
+
+        if !self.pending.is_empty() && !self.flush_scheduled {
+            let deadline = ctx.current_time()? + self.flush_interval;
+            // Fails if the watermark already passed the deadline, in which
+            // case the documents are sent right away
+            match ctx.schedule_at(0, deadline, FlushTimeout { deadline }) {
+                Ok(_) => self.flush_scheduled = true,
+                Err(_) => self.flush(),
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_timeout(
+        &mut self,
+        _timeout: Self::TimerState,
         _ctx: OperatorContext<Self, impl Backend, impl ComponentDefinition>,
     ) -> ArconResult<()> {
-        self.runtime.block_on(send(
-            &mut self.client,
-            element.data,
-            element.timestamp.unwrap(),
-        ));
+        self.flush_scheduled = false;
+        self.flush();
         Ok(())
     }
 
-    arcon::ignore_timeout!();
-    arcon::ignore_persist!();
+    fn persist(&mut self) -> Result<(), ArconStateError> {
+        self.flush();
+        if let Some(offsets) = &self.offsets {
+            offsets.commit();
+        }
+        Ok(())
+    }
 
     fn state(&mut self) -> &mut Self::OperatorState {
         &mut self.state
     }
 }
 
-async fn send(client: &mut elasticsearch::Elasticsearch, data: data::RideWindowedData, time: u64) {
-    // Data to be posted to Kibana
-    // Send data and block
-    let time = chrono::NaiveDateTime::from_timestamp(time as i64, 0).to_string();
-    println!("Time: {}", time);
-    client
-        .index(elasticsearch::IndexParts::IndexTypeId(
-            "arcon_data_stream",
-            "_doc",
-            &format!("location_{}", data.pu_location_id),
-        ))
-        .body(json!({
-            "time": time,
-            // Keys
-            "pu_location_id": data.pu_location_id,
-            "pu_location_name": data.pu_location_name,
-            // Aggregates
-            "count": data.count,
-
-            "sum_fare_amount": data.sum_fare_amount,
-            "max_fare_amount": data.max_fare_amount,
-            "avg_fare_amount": data.avg_fare_amount,
-            "min_fare_amount": data.min_fare_amount,
-
-            "sum_trip_distance": data.sum_trip_distance,
-            "avg_trip_distance": data.avg_trip_distance,
-
-            "sum_passenger_count": data.sum_passenger_count,
-            "max_passenger_count": data.max_passenger_count,
-            "avg_passenger_count": data.avg_passenger_count,
-            "min_passenger_count": data.min_passenger_count,
-
-            "sum_duration": data.sum_duration,
-            "max_duration": data.max_duration,
-            "avg_duration": data.avg_duration,
-            "min_duration": data.min_duration,
-        }))
+/// How often and how long to wait before a failed request is retried.
+struct Retry {
+    max_retries: u32,
+    /// Wait before the first retry, doubled on every following one
+    backoff: Duration,
+}
+
+/// A document with the index and id it is written to.
+struct BulkDocument {
+    index: String,
+    id: String,
+    body: serde_json::Value,
+}
+
+/// Why a `_bulk` request failed as a whole.
+struct BulkError {
+    retryable: bool,
+    reason: String,
+}
+
+fn retryable(status: u16) -> bool {
+    status == 429 || status >= 500
+}
+
+/// Sends documents with the bulk operation `op`, retrying the request and
+/// single documents that failed with a retryable error.
+///
+/// Returns the documents that could not be written, with the reason.
+async fn send_bulk(
+    client: &Elasticsearch,
+    op: &str,
+    documents: Vec<BulkDocument>,
+    retry: &Retry,
+) -> Vec<(BulkDocument, String)> {
+    let mut pending = documents;
+    let mut failed = Vec::new();
+    let mut attempt = 0;
+    loop {
+        let mut retries = Vec::new();
+        match bulk_request(client, op, &pending).await {
+            Ok(errors) => {
+                for (document, error) in pending.into_iter().zip(errors) {
+                    match error {
+                        None => (),
+                        Some((status, reason)) if retryable(status) => {
+                            retries.push((document, reason))
+                        }
+                        Some((_, reason)) => failed.push((document, reason)),
+                    }
+                }
+            }
+            Err(err) if err.retryable => {
+                retries = pending
+                    .into_iter()
+                    .map(|document| (document, err.reason.clone()))
+                    .collect()
+            }
+            Err(err) => {
+                failed.extend(
+                    pending
+                        .into_iter()
+                        .map(|document| (document, err.reason.clone())),
+                );
+            }
+        }
+
+        if retries.is_empty() {
+            return failed;
+        }
+        if attempt >= retry.max_retries {
+            failed.extend(retries);
+            return failed;
+        }
+        let backoff = retry.backoff.saturating_mul(2u32.saturating_pow(attempt));
+        eprintln!(
+            "Retrying {} documents in {:?}: {}",
+            retries.len(),
+            backoff,
+            retries[0].1
+        );
+        tokio::time::sleep(backoff).await;
+        attempt += 1;
+        pending = retries.into_iter().map(|(document, _)| document).collect();
+    }
+}
+
+/// Sends documents in a single `_bulk` request with the operation `op`.
+///
+/// Returns the status and reason of every document that was not written, in
+/// the order of `documents`. With `create`, a document that already exists
+/// counts as written, since ids are deterministic.
+async fn bulk_request(
+    client: &Elasticsearch,
+    op: &str,
+    documents: &[BulkDocument],
+) -> Result<Vec<Option<(u16, String)>>, BulkError> {
+    let mut body: Vec<JsonBody<serde_json::Value>> = Vec::with_capacity(2 * documents.len());
+    for document in documents {
+        body.push(json!({ op: { "_index": document.index, "_id": document.id } }).into());
+        body.push(document.body.clone().into());
+    }
+
+    let response = client
+        .bulk(BulkParts::None)
+        .body(body)
         .send()
         .await
-        .unwrap()
-        .text()
+        .map_err(|err| BulkError {
+            retryable: true,
+            reason: err.to_string(),
+        })?;
+
+    let status = response.status_code().as_u16();
+    if !response.status_code().is_success() {
+        let text = response.text().await.unwrap_or_default();
+        return Err(BulkError {
+            retryable: retryable(status),
+            reason: format!("HTTP {}: {}", status, text),
+        });
+    }
+
+    let result = response
+        .json::<serde_json::Value>()
         .await
-        .unwrap();
+        .map_err(|err| BulkError {
+            retryable: false,
+            reason: format!("invalid bulk response: {}", err),
+        })?;
+    let items = result["items"].as_array().cloned().unwrap_or_default();
+
+    let errors = (0..documents.len())
+        .map(|i| {
+            let item = match items.get(i) {
+                Some(item) => &item[op],
+                None => return Some((0, "missing from the bulk response".to_owned())),
+            };
+            let status = item["status"].as_u64().unwrap_or(0) as u16;
+            if (200..300).contains(&status) || (op == "create" && status == 409) {
+                None
+            } else {
+                Some((status, item["error"].to_string()))
+            }
+        })
+        .collect();
+    Ok(errors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::RideWindowedData;
+    use crate::quality::Rule;
+    use std::io::Read;
+    use std::sync::mpsc::{self, Receiver};
+    use tiny_http::{Header, Response, Server};
+
+    /// Starts an Elasticsearch mock that answers requests with `responses`
+    /// in order, and returns its URL and the bodies of the requests.
+    fn mock(responses: Vec<(u16, serde_json::Value)>) -> (String, Receiver<String>) {
+        let server = Server::http("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", server.server_addr());
+        let (sender, bodies) = mpsc::channel();
+        std::thread::spawn(move || {
+            for (status, body) in responses {
+                let mut request = server.recv().unwrap();
+                let mut text = String::new();
+                request.as_reader().read_to_string(&mut text).unwrap();
+                sender.send(text).unwrap();
+                let content_type = Header::from_bytes("Content-Type", "application/json").unwrap();
+                let response = Response::from_string(body.to_string())
+                    .with_status_code(status)
+                    .with_header(content_type);
+                request.respond(response).unwrap();
+            }
+        });
+        (url, bodies)
+    }
+
+    /// Bulk response with an item of the given status for every document.
+    fn bulk_response(statuses: &[u16]) -> (u16, serde_json::Value) {
+        let items: Vec<_> = statuses
+            .iter()
+            .map(|&status| match status {
+                200..=299 => json!({ "index": { "status": status } }),
+                _ => json!({ "index": { "status": status, "error": { "type": "error" } } }),
+            })
+            .collect();
+        let errors = statuses.iter().any(|status| !(200..300).contains(status));
+        (200, json!({ "took": 1, "errors": errors, "items": items }))
+    }
+
+    fn sink(url: String, bulk_size: usize, test: &str) -> (Kibana<RideWindowedData>, PathBuf) {
+        let dead_letter =
+            std::env::temp_dir().join(format!("arcon_taxi-{}-{}.tsv", test, std::process::id()));
+        let _ = std::fs::remove_file(&dead_letter);
+        let conf = SinkSection {
+            url,
+            bulk_size,
+            max_retries: 2,
+            retry_backoff: 1,
+            dead_letter: dead_letter.clone(),
+            ..SinkSection::default()
+        };
+        let client = elasticsearch_client(&conf).unwrap();
+        let sink = Kibana::new("taxi", "{location_id}", client, &conf);
+        (sink, dead_letter)
+    }
+
+    fn window(location_id: u64) -> RideWindowedData {
+        RideWindowedData {
+            location_id,
+            ..RideWindowedData::default()
+        }
+    }
+
+    /// Ids of the documents of a `_bulk` request body.
+    fn ids(body: &str) -> Vec<String> {
+        body.lines()
+            .step_by(2)
+            .map(|line| {
+                let action: serde_json::Value = serde_json::from_str(line).unwrap();
+                action["index"]["_id"].as_str().unwrap().to_owned()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn documents_are_sent_once_bulk_size_are_pending() {
+        let (url, bodies) = mock(vec![bulk_response(&[201, 201])]);
+        let (mut sink, _) = sink(url, 2, "bulk-size");
+
+        sink.push(&window(1), Some(0));
+        assert!(bodies.try_recv().is_err());
+        sink.push(&window(2), Some(0));
+        assert_eq!(ids(&bodies.recv().unwrap()), vec!["1", "2"]);
+        assert!(sink.pending.is_empty());
+    }
+
+    #[test]
+    fn pending_documents_are_sent_on_every_epoch() {
+        let (url, bodies) = mock(vec![bulk_response(&[201])]);
+        let (mut sink, _) = sink(url, 10, "epoch");
+
+        sink.push(&window(1), Some(0));
+        assert!(bodies.try_recv().is_err());
+        sink.persist().unwrap();
+        assert_eq!(ids(&bodies.recv().unwrap()), vec!["1"]);
+
+        // Nothing is sent when nothing is pending
+        sink.persist().unwrap();
+        assert!(bodies.try_recv().is_err());
+    }
+
+    #[test]
+    fn failed_documents_go_to_the_dead_letter() {
+        let (url, _bodies) = mock(vec![bulk_response(&[201, 400, 201])]);
+        let (mut sink, dead_letter) = sink(url, 3, "partial-failure");
+
+        for location_id in 1..=3 {
+            sink.push(&window(location_id), Some(0));
+        }
+        let failed = std::fs::read_to_string(&dead_letter).unwrap();
+        let records: Vec<Vec<&str>> = failed.lines().map(|l| l.split('\t').collect()).collect();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0][..2], ["taxi", "2"]);
+        std::fs::remove_file(dead_letter).unwrap();
+    }
+
+    #[test]
+    fn retryable_failures_are_retried() {
+        let (url, bodies) = mock(vec![
+            (429, json!({ "error": "too many requests" })),
+            bulk_response(&[201, 429]),
+            bulk_response(&[201]),
+        ]);
+        let (mut sink, dead_letter) = sink(url, 2, "retry");
+
+        sink.push(&window(1), Some(0));
+        sink.push(&window(2), Some(0));
+        assert_eq!(ids(&bodies.recv().unwrap()), vec!["1", "2"]);
+        assert_eq!(ids(&bodies.recv().unwrap()), vec!["1", "2"]);
+        assert_eq!(ids(&bodies.recv().unwrap()), vec!["2"]);
+        assert_eq!(std::fs::read_to_string(&dead_letter).unwrap(), "");
+        std::fs::remove_file(dead_letter).unwrap();
+    }
+
+    fn pair(du: u64, count: u64) -> data::OdWindowedData {
+        data::OdWindowedData {
+            pu_location_id: 1,
+            du_location_id: du,
+            count,
+            ..Default::default()
+        }
+    }
+
+    /// `(end, [(du, rank, count)])` of the given windows.
+    fn ranked(windows: &[TopKWindow]) -> Vec<(u64, Vec<(u64, u64, u64)>)> {
+        windows
+            .iter()
+            .map(|window| {
+                let pairs = window
+                    .pairs
+                    .iter()
+                    .map(|data| (data.du_location_id, data.rank, data.count))
+                    .collect();
+                (window.end, pairs)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn top_k_keeps_every_window_of_an_origin() {
+        // Sliding windows of 200 seconds every 100 seconds: a ride pair is
+        // counted in the two windows that cover it
+        let mut windows = Vec::new();
+        add_to_top_k(&mut windows, pair(5, 3), 200, 2);
+        add_to_top_k(&mut windows, pair(5, 4), 300, 2);
+        add_to_top_k(&mut windows, pair(7, 1), 200, 2);
+        add_to_top_k(&mut windows, pair(8, 9), 200, 2);
+        add_to_top_k(&mut windows, pair(7, 2), 300, 2);
+        add_to_top_k(&mut windows, pair(6, 1), 400, 2);
+
+        assert!(take_top_k(&mut windows, 199).is_empty());
+        let due = take_top_k(&mut windows, 300);
+        assert_eq!(
+            ranked(&due),
+            vec![
+                (200, vec![(8, 1, 9), (5, 2, 3)]),
+                (300, vec![(5, 1, 4), (7, 2, 2)]),
+            ]
+        );
+        assert_eq!(windows.len(), 1);
+        assert_eq!(windows[0].end, 400);
+    }
+
+    #[test]
+    fn top_k_breaks_ties_by_destination() {
+        let mut windows = Vec::new();
+        for du in &[9, 3, 7, 5] {
+            add_to_top_k(&mut windows, pair(*du, 2), 100, 3);
+        }
+        assert_eq!(
+            ranked(&take_top_k(&mut windows, 100)),
+            vec![(100, vec![(3, 1, 2), (5, 2, 2), (7, 3, 2)])]
+        );
+    }
+
+    #[test]
+    fn top_k_emits_fewer_pairs_than_k() {
+        let mut windows = Vec::new();
+        add_to_top_k(&mut windows, pair(4, 1), 100, 10);
+        add_to_top_k(&mut windows, pair(2, 5), 100, 10);
+        assert_eq!(
+            ranked(&take_top_k(&mut windows, 100)),
+            vec![(100, vec![(2, 1, 5), (4, 2, 1)])]
+        );
+        assert!(windows.is_empty());
+    }
+
+    #[test]
+    fn session_rides_are_dropped_after_the_allowed_lateness() {
+        let conf = crate::conf::TaxiConf {
+            window: crate::conf::WindowSection {
+                kind: crate::window::WindowKind::Session,
+                gap: 10,
+                late_arrival: 60,
+                ..crate::conf::TaxiConf::default().window
+            },
+            ..crate::conf::TaxiConf::default()
+        };
+        let mut late = LateRides::new("test", conf.lateness(), None);
+        let ride = data::RideData::default();
+
+        assert!(late.admit(Some(200), 100, &ride));
+        // Its session closed at 110 and is kept until 170
+        assert!(late.admit(Some(100), 169, &ride));
+        assert!(!late.admit(Some(100), 170, &ride));
+        assert_eq!((late.after_watermark, late.dropped), (2, 1));
+    }
+
+    #[test]
+    fn rides_after_the_allowed_lateness_are_counted_and_routed() {
+        // Sliding windows of 200 seconds every 100, kept 50 seconds
+        let lateness = Lateness {
+            length: 200,
+            slide: 100,
+            late_arrival: 50,
+        };
+        // The last window of a ride at 150 is [100, 300)
+        assert!(!lateness.too_late(150, 349));
+        assert!(lateness.too_late(150, 350));
+
+        let path =
+            std::env::temp_dir().join(format!("arcon_taxi-late-{}.json", std::process::id()));
+        let late_data = DeadLetter::open(&path).unwrap();
+        let mut late = LateRides::new("test", lateness, Some(late_data));
+        let ride = data::RideData {
+            pu_location_id: 42,
+            ..Default::default()
+        };
+        assert!(late.admit(Some(150), 349, &ride));
+        assert!(!late.admit(Some(150), 350, &ride));
+        late.late_data.as_mut().unwrap().flush();
+        let routed = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!((late.after_watermark, late.routed, late.dropped), (2, 1, 0));
+        assert_eq!(routed.lines().count(), 1);
+        assert!(routed.contains("\"pu_location_id\":42"));
+    }
+
+    #[test]
+    fn rejected_rows_land_in_the_dead_letter_file() {
+        let path = std::env::temp_dir().join(format!("arcon_taxi-dead-{}.tsv", std::process::id()));
+        let dead_letter = DeadLetter::open(&path).unwrap();
+        let mut parse = ParseRides::<data::RawLine>::new("test", dead_letter);
+        let line = |line: &str, line_number| data::RawLine {
+            line: line.to_owned(),
+            path: "rides/2020-01.csv".to_owned(),
+            line_number,
+        };
+        let valid = "1,2020-01-01 00:28:15,2020-01-01 00:33:03,1,1.20,1,N,238,239,1,6,3,0.5,1.47,0,0.3,11.27,2.5";
+        let invalid = valid.replace(",238,", ",x,");
+
+        assert!(parse.parse(&line(valid, 2)).is_some());
+        assert!(parse.parse(&line(&invalid, 3)).is_none());
+        assert!(parse.parse(&line("1,2", 5)).is_none());
+        parse.dead_letter.flush();
+        let records = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let expected = format!(
+            "rides/2020-01.csv\t3\tinvalid integer value 'x' for pu_location_id\t{}\n\
+             rides/2020-01.csv\t5\texpected 18 columns, found 2\t1,2\n",
+            invalid
+        );
+        assert_eq!(records, expected);
+        assert_eq!(parse.counters.parsed, 1);
+        assert_eq!(
+            parse.counters.to_string(),
+            "parsed=1 column_count=1 integer=1"
+        );
+    }
+
+    #[test]
+    fn quality_filter_drops_or_tags_broken_rides() {
+        let rules = crate::conf::TaxiConf::default().quality_rules().unwrap();
+        let valid = data::RideData {
+            do_time: 600,
+            trip_distance: 2.0,
+            passenger_count: 1,
+            fare_amount: 1000,
+            ..Default::default()
+        };
+        let empty = data::RideData {
+            passenger_count: 0,
+            ..valid.clone()
+        };
+
+        let mut drop = QualityFilter::new("test", rules.clone(), QualityAction::Drop);
+        assert_eq!(drop.filter(valid.clone()).unwrap().quality_flags, 0);
+        assert!(drop.filter(empty.clone()).is_none());
+        assert_eq!(
+            drop.counters.to_string(),
+            "checked=2 flagged=1 passengers=1"
+        );
+
+        let mut tag = QualityFilter::new("test", rules.clone(), QualityAction::Tag);
+        let tagged = tag.filter(empty.clone()).unwrap();
+        assert_eq!(tagged.quality_flags, Rule::Passengers.bit());
+        assert_eq!(tag.counters.flagged, 1);
+
+        let mut off = QualityFilter::new("test", rules, QualityAction::Off);
+        assert_eq!(off.filter(empty).unwrap().quality_flags, 0);
+        assert_eq!(off.counters.checked, 0);
+    }
+
+    #[test]
+    fn rejected_parquet_rows_keep_their_file_and_index() {
+        let path = std::env::temp_dir().join(format!(
+            "arcon_taxi-dead-parquet-{}.tsv",
+            std::process::id()
+        ));
+        let dead_letter = DeadLetter::open(&path).unwrap();
+        let mut parse = ParseRides::<data::ParquetRow>::new("test", dead_letter);
+        let unreadable = data::ParquetRow {
+            path: "rides/2020-01.parquet".to_owned(),
+            row: 7,
+            error: "corrupt page".to_owned(),
+            ..Default::default()
+        };
+        let no_pickup = data::ParquetRow {
+            path: "rides/2020-01.parquet".to_owned(),
+            row: 3,
+            ..Default::default()
+        };
+
+        assert!(parse.parse(&unreadable).is_none());
+        assert!(parse.parse(&no_pickup).is_none());
+        parse.dead_letter.flush();
+        let records = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let origins: Vec<Vec<&str>> = records
+            .lines()
+            .map(|record| record.splitn(4, '\t').take(3).collect())
+            .collect();
+        assert_eq!(
+            origins,
+            vec![
+                vec![
+                    "rides/2020-01.parquet",
+                    "7",
+                    "unreadable Parquet row: corrupt page"
+                ],
+                vec![
+                    "rides/2020-01.parquet",
+                    "3",
+                    "invalid datetime value '' for datetime"
+                ],
+            ]
+        );
+        assert_eq!(parse.counters.to_string(), "parsed=0 datetime=1 parquet=1");
+    }
 }
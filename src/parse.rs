@@ -0,0 +1,378 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::data::TaxiRideData;
+
+/// Number of columns in a TLC yellow taxi trip record.
+const COLUMNS: usize = 18;
+
+/// Why a row of a TLC trip data file was rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum ParseErrorKind {
+    /// The row does not have the expected number of columns
+    ColumnCount,
+    /// A required column is empty
+    Missing,
+    /// A column is not a valid integer
+    Integer,
+    /// A column is not a valid decimal number
+    Decimal,
+    /// A column is not a valid dollar amount
+    Money,
+    /// A column is not a valid `%Y-%m-%d %H:%M:%S` datetime
+    DateTime,
+    /// A message is not a valid protobuf `TaxiRideData`
+    Protobuf,
+    /// A row of a Parquet file could not be read
+    Parquet,
+}
+
+impl ParseErrorKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ParseErrorKind::ColumnCount => "column_count",
+            ParseErrorKind::Missing => "missing",
+            ParseErrorKind::Integer => "integer",
+            ParseErrorKind::Decimal => "decimal",
+            ParseErrorKind::Money => "money",
+            ParseErrorKind::DateTime => "datetime",
+            ParseErrorKind::Protobuf => "protobuf",
+            ParseErrorKind::Parquet => "parquet",
+        }
+    }
+}
+
+impl fmt::Display for ParseErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    /// Name of the offending column
+    pub column: &'static str,
+    /// The raw value of the column, or the column count
+    pub value: String,
+}
+
+impl ParseError {
+    pub fn new(kind: ParseErrorKind, column: &'static str, value: impl Into<String>) -> Self {
+        Self {
+            kind,
+            column,
+            value: value.into(),
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            ParseErrorKind::ColumnCount => {
+                write!(f, "expected {} columns, found {}", COLUMNS, self.value)
+            }
+            ParseErrorKind::Missing => write!(f, "missing value for {}", self.column),
+            ParseErrorKind::Protobuf => write!(f, "invalid protobuf message: {}", self.value),
+            ParseErrorKind::Parquet => write!(f, "unreadable Parquet row: {}", self.value),
+            _ => write!(
+                f,
+                "invalid {} value '{}' for {}",
+                self.kind, self.value, self.column
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Rows parsed and rejected per error kind by a `ParseRides` operator.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ParseCounters {
+    pub parsed: u64,
+    pub rejected: BTreeMap<ParseErrorKind, u64>,
+}
+
+impl ParseCounters {
+    pub fn count<T>(&mut self, result: &Result<T, ParseError>) {
+        match result {
+            Ok(_) => self.parsed += 1,
+            Err(err) => *self.rejected.entry(err.kind).or_insert(0) += 1,
+        }
+    }
+}
+
+impl fmt::Display for ParseCounters {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "parsed={}", self.parsed)?;
+        for (kind, count) in &self.rejected {
+            write!(f, " {}={}", kind, count)?;
+        }
+        Ok(())
+    }
+}
+
+/// Columns of a single CSV row, consumed in order.
+struct Columns<'a> {
+    columns: std::str::Split<'a, char>,
+}
+
+impl<'a> Columns<'a> {
+    fn next_str(&mut self, column: &'static str) -> Result<&'a str, ParseError> {
+        match self.columns.next().map(str::trim) {
+            Some("") | None => Err(ParseError::new(ParseErrorKind::Missing, column, "")),
+            Some(value) => Ok(value),
+        }
+    }
+
+    fn next<T: FromStr>(
+        &mut self,
+        column: &'static str,
+        kind: ParseErrorKind,
+    ) -> Result<T, ParseError> {
+        let value = self.next_str(column)?;
+        value
+            .parse()
+            .map_err(|_| ParseError::new(kind, column, value))
+    }
+
+    fn next_int<T: FromStr>(&mut self, column: &'static str) -> Result<T, ParseError> {
+        self.next(column, ParseErrorKind::Integer)
+    }
+
+    fn next_decimal<T: FromStr>(&mut self, column: &'static str) -> Result<T, ParseError> {
+        self.next(column, ParseErrorKind::Decimal)
+    }
+
+    fn next_cents(&mut self, column: &'static str) -> Result<i64, ParseError> {
+        let value = self.next_str(column)?;
+        parse_cents(value).ok_or_else(|| ParseError::new(ParseErrorKind::Money, column, value))
+    }
+
+    fn next_datetime(&mut self, column: &'static str) -> Result<String, ParseError> {
+        let value = self.next_str(column)?;
+        crate::data::datetime_to_u64(value).map_err(|mut err| {
+            err.column = column;
+            err
+        })?;
+        Ok(value.to_owned())
+    }
+}
+
+/// Parses a dollar amount such as `6.5`, `-0.50` or `.3` into cents.
+///
+/// Fractions of a cent are rounded half away from zero.
+pub fn parse_cents(value: &str) -> Option<i64> {
+    let (negative, digits) = match value.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, value.strip_prefix('+').unwrap_or(value)),
+    };
+    let (whole, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+    if whole.is_empty() && fraction.is_empty() {
+        return None;
+    }
+    if !whole
+        .bytes()
+        .chain(fraction.bytes())
+        .all(|b| b.is_ascii_digit())
+    {
+        return None;
+    }
+
+    let whole: i64 = if whole.is_empty() {
+        0
+    } else {
+        whole.parse().ok()?
+    };
+    let mut fraction = fraction.bytes().map(|b| i64::from(b - b'0'));
+    let tenths = fraction.next().unwrap_or(0);
+    let hundredths = fraction.next().unwrap_or(0);
+    let round = fraction.next().map_or(0, |d| if d >= 5 { 1 } else { 0 });
+
+    let cents = whole
+        .checked_mul(100)?
+        .checked_add(tenths * 10 + hundredths + round)?;
+    Some(if negative { -cents } else { cents })
+}
+
+/// Parses a CSV row of a TLC yellow taxi trip data file.
+pub fn parse_taxi_ride(line: &str) -> Result<TaxiRideData, ParseError> {
+    let count = line.split(',').count();
+    if count != COLUMNS {
+        return Err(ParseError::new(
+            ParseErrorKind::ColumnCount,
+            "",
+            count.to_string(),
+        ));
+    }
+
+    let mut c = Columns {
+        columns: line.split(','),
+    };
+
+    Ok(TaxiRideData {
+        vendor_id: c.next_int("vendor_id")?,
+        tpep_pickup_datetime: c.next_datetime("tpep_pickup_datetime")?,
+        tpep_dropoff_datetime: c.next_datetime("tpep_dropoff_datetime")?,
+        passenger_count: c.next_int("passenger_count")?,
+        trip_distance: c.next_decimal("trip_distance")?,
+        rate_code_id: c.next_int("rate_code_id")?,
+        store_and_fwd_flag: c.next_str("store_and_fwd_flag")?.to_owned(),
+        pu_location_id: c.next_int("pu_location_id")?,
+        du_location_id: c.next_int("du_location_id")?,
+        payment_type: c.next_int("payment_type")?,
+        fare_amount: c.next_cents("fare_amount")?,
+        extra: c.next_cents("extra")?,
+        mta_tax: c.next_cents("mta_tax")?,
+        tip_amount: c.next_cents("tip_amount")?,
+        tolls_amount: c.next_cents("tolls_amount")?,
+        improvement_surcharge: c.next_cents("improvement_surcharge")?,
+        total_amount: c.next_cents("total_amount")?,
+        congestion_surcharge: c.next_cents("congestion_surcharge")?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cents_of_whole_and_fractional_amounts() {
+        assert_eq!(parse_cents("6"), Some(600));
+        assert_eq!(parse_cents("6.5"), Some(650));
+        assert_eq!(parse_cents("6.05"), Some(605));
+        assert_eq!(parse_cents(".3"), Some(30));
+        assert_eq!(parse_cents("0.30"), Some(30));
+        assert_eq!(parse_cents("12."), Some(1200));
+        assert_eq!(parse_cents("+2.5"), Some(250));
+    }
+
+    #[test]
+    fn cents_round_on_the_third_decimal() {
+        assert_eq!(parse_cents("1.004"), Some(100));
+        assert_eq!(parse_cents("1.005"), Some(101));
+        assert_eq!(parse_cents("1.0049"), Some(100));
+        assert_eq!(parse_cents("0.999"), Some(100));
+        assert_eq!(parse_cents("-1.005"), Some(-101));
+        assert_eq!(parse_cents("-1.004"), Some(-100));
+    }
+
+    #[test]
+    fn cents_of_negative_amounts() {
+        assert_eq!(parse_cents("-0.50"), Some(-50));
+        assert_eq!(parse_cents("-.5"), Some(-50));
+        assert_eq!(parse_cents("-52"), Some(-5200));
+        assert_eq!(parse_cents("-0"), Some(0));
+    }
+
+    #[test]
+    fn malformed_amounts_are_rejected() {
+        for value in &[
+            "", ".", "-", "-.", "1.2.3", "1,5", "abc", "1e3", "--1", " 1",
+        ] {
+            assert_eq!(parse_cents(value), None, "{:?}", value);
+        }
+        assert_eq!(parse_cents("100000000000000000"), None);
+    }
+
+    const ROW: &str =
+        "1,2020-01-01 00:28:15,2020-01-01 00:33:03,1,1.20,1,N,238,239,1,6,3,0.5,1.47,0,0.3,11.27,2.5";
+
+    /// `ROW` with the column at `index` replaced by `value`.
+    fn row_with(index: usize, value: &str) -> String {
+        let mut columns: Vec<_> = ROW.split(',').collect();
+        columns[index] = value;
+        columns.join(",")
+    }
+
+    fn error(line: &str) -> ParseError {
+        parse_taxi_ride(line).unwrap_err()
+    }
+
+    #[test]
+    fn valid_row_is_parsed() {
+        let ride = parse_taxi_ride(ROW).unwrap();
+        assert_eq!(ride.tpep_pickup_datetime, "2020-01-01 00:28:15");
+        assert_eq!((ride.pu_location_id, ride.du_location_id), (238, 239));
+        assert_eq!((ride.tip_amount, ride.total_amount), (147, 1127));
+    }
+
+    #[test]
+    fn missing_values_are_rejected() {
+        assert_eq!(
+            error(&row_with(3, "")),
+            ParseError::new(ParseErrorKind::Missing, "passenger_count", "")
+        );
+        assert_eq!(
+            error(&row_with(17, " ")),
+            ParseError::new(ParseErrorKind::Missing, "congestion_surcharge", "")
+        );
+        assert_eq!(
+            error(&row_with(3, "")).to_string(),
+            "missing value for passenger_count"
+        );
+    }
+
+    #[test]
+    fn malformed_numbers_are_rejected() {
+        assert_eq!(
+            error(&row_with(7, "2x")),
+            ParseError::new(ParseErrorKind::Integer, "pu_location_id", "2x")
+        );
+        assert_eq!(
+            error(&row_with(4, "1..2")),
+            ParseError::new(ParseErrorKind::Decimal, "trip_distance", "1..2")
+        );
+        assert_eq!(
+            error(&row_with(10, "$6")),
+            ParseError::new(ParseErrorKind::Money, "fare_amount", "$6")
+        );
+        assert_eq!(
+            error(&row_with(10, "$6")).to_string(),
+            "invalid money value '$6' for fare_amount"
+        );
+    }
+
+    #[test]
+    fn malformed_datetimes_are_rejected() {
+        for value in &["2020-01-01", "2020-13-01 00:00:00", "01/01/2020 00:28:15"] {
+            assert_eq!(
+                error(&row_with(2, value)),
+                ParseError::new(ParseErrorKind::DateTime, "tpep_dropoff_datetime", *value)
+            );
+        }
+    }
+
+    #[test]
+    fn rows_with_another_column_count_are_rejected() {
+        let err = error(&format!("{},1", ROW));
+        assert_eq!(err.kind, ParseErrorKind::ColumnCount);
+        assert_eq!(err.to_string(), "expected 18 columns, found 19");
+    }
+
+    #[test]
+    fn counters_count_rows_per_error_kind() {
+        let mut counters = ParseCounters::default();
+        for line in &[
+            ROW.to_owned(),
+            row_with(3, ""),
+            row_with(7, "x"),
+            row_with(8, "y"),
+            ROW.to_owned(),
+        ] {
+            counters.count(&parse_taxi_ride(line));
+        }
+        assert_eq!(counters.parsed, 2);
+        let rejected: Vec<_> = counters.rejected.iter().collect();
+        assert_eq!(
+            rejected,
+            vec![
+                (&ParseErrorKind::Missing, &1),
+                (&ParseErrorKind::Integer, &2)
+            ]
+        );
+        assert_eq!(counters.to_string(), "parsed=2 missing=1 integer=2");
+    }
+}
@@ -0,0 +1,350 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::Range;
+use std::time::SystemTime;
+
+use crate::data;
+use crate::data::RideData;
+
+/// What happens to rides that break a data-quality rule.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QualityAction {
+    /// Drop the ride before it reaches the window
+    Drop,
+    /// Keep the ride, but set `RideData::quality_flags`
+    Tag,
+    /// Do not check rides
+    Off,
+}
+
+impl std::str::FromStr for QualityAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "drop" => Ok(QualityAction::Drop),
+            "tag" => Ok(QualityAction::Tag),
+            "off" => Ok(QualityAction::Off),
+            _ => Err(format!("unknown quality action '{}'", s)),
+        }
+    }
+}
+
+/// A data-quality rule that a ride can break.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Rule {
+    /// The dropoff is before the pickup
+    NegativeDuration,
+    /// The trip took longer than `max_duration`
+    MaxDuration,
+    /// The trip has no distance
+    ZeroDistance,
+    /// The average speed is outside `[min_speed, max_speed]`
+    Speed,
+    /// Fewer than `min_passengers` passengers
+    Passengers,
+    /// The pickup is outside `[valid_from, valid_until)`
+    DateRange,
+    /// The fare is outside `[min_fare, max_fare]`
+    Fare,
+}
+
+impl Rule {
+    pub const ALL: [Rule; 7] = [
+        Rule::NegativeDuration,
+        Rule::MaxDuration,
+        Rule::ZeroDistance,
+        Rule::Speed,
+        Rule::Passengers,
+        Rule::DateRange,
+        Rule::Fare,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Rule::NegativeDuration => "negative_duration",
+            Rule::MaxDuration => "max_duration",
+            Rule::ZeroDistance => "zero_distance",
+            Rule::Speed => "speed",
+            Rule::Passengers => "passengers",
+            Rule::DateRange => "date_range",
+            Rule::Fare => "fare",
+        }
+    }
+
+    /// Bit of the rule in `RideData::quality_flags`.
+    pub fn bit(&self) -> u64 {
+        1 << (*self as u64)
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// Rides checked and rules broken by a `QualityFilter` operator.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct QualityCounters {
+    pub checked: u64,
+    /// Rides that broke at least one rule
+    pub flagged: u64,
+    pub broken: BTreeMap<Rule, u64>,
+}
+
+impl QualityCounters {
+    /// Counts a checked ride with the bits of the rules it broke.
+    pub fn count(&mut self, flags: u64) {
+        self.checked += 1;
+        if flags != 0 {
+            self.flagged += 1;
+        }
+        for rule in Rule::ALL.iter().filter(|rule| flags & rule.bit() != 0) {
+            *self.broken.entry(*rule).or_insert(0) += 1;
+        }
+    }
+}
+
+impl fmt::Display for QualityCounters {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "checked={} flagged={}", self.checked, self.flagged)?;
+        for (rule, count) in &self.broken {
+            write!(f, " {}={}", rule, count)?;
+        }
+        Ok(())
+    }
+}
+
+/// Bounds that a ride must be within to pass the data-quality check.
+#[derive(Debug, Clone)]
+pub struct QualityRules {
+    /// Longest trip in seconds
+    pub max_duration: u64,
+    /// Average speed bounds in miles per hour
+    pub min_speed: f32,
+    pub max_speed: f32,
+    pub min_passengers: u64,
+    /// Pickup time bounds in seconds since the epoch
+    pub valid_from: Option<u64>,
+    pub valid_until: Option<u64>,
+    /// Fare bounds in cents
+    pub min_fare: i64,
+    pub max_fare: i64,
+}
+
+impl QualityRules {
+    /// Event times, see `data::local_time`, that a ride can plausibly have
+    /// been picked up at: within `[valid_from, valid_until)`, and never after
+    /// the current time. Sources only move their watermark on plausible
+    /// times, so that a single future-dated ride does not make every ride
+    /// after it late.
+    pub fn plausible_times(&self) -> Range<u64> {
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |now| now.as_secs());
+        let until = self.valid_until.map_or(now + 1, |until| until.min(now + 1));
+        // 0 is the event time of a malformed datetime
+        let from = self.valid_from.map_or(1, data::local_time).max(1);
+        from..data::local_time(until)
+    }
+
+    /// Returns the bits of every rule the ride breaks, 0 if it breaks none.
+    pub fn check(&self, ride: &RideData) -> u64 {
+        let mut flags = 0;
+        let mut flag = |rule: Rule| flags |= rule.bit();
+
+        let duration = ride.do_time as i64 - ride.pu_time as i64;
+        if duration < 0 {
+            flag(Rule::NegativeDuration);
+        } else if duration as u64 > self.max_duration {
+            flag(Rule::MaxDuration);
+        }
+
+        if ride.trip_distance <= 0.0 {
+            flag(Rule::ZeroDistance);
+        } else if duration > 0 {
+            let speed = ride.trip_distance / (duration as f32 / 3600.0);
+            if speed < self.min_speed || speed > self.max_speed {
+                flag(Rule::Speed);
+            }
+        }
+
+        if ride.passenger_count < self.min_passengers {
+            flag(Rule::Passengers);
+        }
+
+        let too_early = self.valid_from.map_or(false, |from| ride.pu_time < from);
+        let too_late = self
+            .valid_until
+            .map_or(false, |until| ride.pu_time >= until);
+        if too_early || too_late {
+            flag(Rule::DateRange);
+        }
+
+        if ride.fare_amount < self.min_fare || ride.fare_amount > self.max_fare {
+            flag(Rule::Fare);
+        }
+
+        flags
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(valid_from: Option<u64>, valid_until: Option<u64>) -> QualityRules {
+        QualityRules {
+            max_duration: 4 * 3600,
+            min_speed: 0.0,
+            max_speed: 100.0,
+            min_passengers: 1,
+            valid_from,
+            valid_until,
+            min_fare: 0,
+            max_fare: 100_000,
+        }
+    }
+
+    #[test]
+    fn plausible_times_end_at_the_current_time() {
+        let now = data::local_time(
+            SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+        );
+        let unbounded = rules(None, None).plausible_times();
+        assert_eq!(unbounded.start, 1);
+        assert!(unbounded.contains(&now));
+        assert!(!unbounded.contains(&(now + 24 * 3600)));
+
+        let from = data::datetime_to_u64("2020-01-01 00:00:00").unwrap();
+        let until = data::datetime_to_u64("2020-02-01 00:00:00").unwrap();
+        let bounded = rules(Some(from), Some(until)).plausible_times();
+        assert_eq!(bounded, data::local_time(from)..data::local_time(until));
+    }
+
+    fn time(datetime: &str) -> u64 {
+        data::datetime_to_u64(datetime).unwrap()
+    }
+
+    fn january() -> QualityRules {
+        rules(
+            Some(time("2020-01-01 00:00:00")),
+            Some(time("2020-02-01 00:00:00")),
+        )
+    }
+
+    /// A 10 minute ride of 2 miles with one passenger and a $10 fare.
+    fn ride() -> RideData {
+        let pu_time = time("2020-01-15 10:00:00");
+        RideData {
+            pu_time,
+            do_time: pu_time + 600,
+            trip_distance: 2.0,
+            passenger_count: 1,
+            fare_amount: 1000,
+            ..Default::default()
+        }
+    }
+
+    fn broken(ride: &RideData) -> Vec<Rule> {
+        let flags = january().check(ride);
+        Rule::ALL
+            .iter()
+            .copied()
+            .filter(|rule| flags & rule.bit() != 0)
+            .collect()
+    }
+
+    #[test]
+    fn valid_ride_breaks_no_rule() {
+        assert_eq!(january().check(&ride()), 0);
+    }
+
+    #[test]
+    fn durations_must_be_positive_and_bounded() {
+        let mut backwards = ride();
+        backwards.do_time = backwards.pu_time - 60;
+        assert_eq!(broken(&backwards), vec![Rule::NegativeDuration]);
+
+        let mut long = ride();
+        long.do_time = long.pu_time + 4 * 3600 + 1;
+        assert_eq!(broken(&long), vec![Rule::MaxDuration]);
+        long.do_time = long.pu_time + 4 * 3600;
+        assert_eq!(broken(&long), Vec::<Rule>::new());
+    }
+
+    #[test]
+    fn distance_and_speed_must_be_plausible() {
+        let mut parked = ride();
+        parked.trip_distance = 0.0;
+        assert_eq!(broken(&parked), vec![Rule::ZeroDistance]);
+
+        // 50 miles in 10 minutes is 300 miles per hour
+        let mut fast = ride();
+        fast.trip_distance = 50.0;
+        assert_eq!(broken(&fast), vec![Rule::Speed]);
+    }
+
+    #[test]
+    fn rides_need_a_passenger() {
+        let mut empty = ride();
+        empty.passenger_count = 0;
+        assert_eq!(broken(&empty), vec![Rule::Passengers]);
+    }
+
+    #[test]
+    fn pickups_must_be_within_the_date_range() {
+        let mut early = ride();
+        early.pu_time = time("2019-12-31 23:59:59");
+        early.do_time = early.pu_time + 600;
+        assert_eq!(broken(&early), vec![Rule::DateRange]);
+
+        let mut late = ride();
+        late.pu_time = time("2020-02-01 00:00:00");
+        late.do_time = late.pu_time + 600;
+        assert_eq!(broken(&late), vec![Rule::DateRange]);
+
+        late.pu_time -= 1;
+        assert_eq!(broken(&late), Vec::<Rule>::new());
+        assert_eq!(rules(None, None).check(&early), 0);
+    }
+
+    #[test]
+    fn fares_must_be_within_bounds() {
+        for &(fare, valid) in &[(-500, false), (0, true), (100_000, true), (100_001, false)] {
+            let mut ride = ride();
+            ride.fare_amount = fare;
+            let expected = if valid { vec![] } else { vec![Rule::Fare] };
+            assert_eq!(broken(&ride), expected, "{}", fare);
+        }
+    }
+
+    #[test]
+    fn every_broken_rule_is_flagged_and_counted() {
+        let mut refund = ride();
+        refund.fare_amount = -450;
+        refund.trip_distance = 0.0;
+        refund.passenger_count = 0;
+        let flags = january().check(&refund);
+        assert_eq!(
+            flags,
+            Rule::ZeroDistance.bit() | Rule::Passengers.bit() | Rule::Fare.bit()
+        );
+
+        let mut counters = QualityCounters::default();
+        counters.count(flags);
+        counters.count(0);
+        counters.count(Rule::Fare.bit());
+        assert_eq!((counters.checked, counters.flagged), (3, 2));
+        assert_eq!(
+            counters.to_string(),
+            "checked=3 flagged=2 zero_distance=1 passengers=1 fare=2"
+        );
+    }
+}
@@ -0,0 +1,309 @@
+use serde_json::json;
+use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::{Arc, RwLock};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::data::{self, Direction, RideKey, RideWindowedData};
+
+/// The latest aggregate of every zone and window of every pipeline, shared
+/// with the query server.
+///
+/// The store mirrors `RideState`, and is rebuilt from it when the pipeline
+/// restarts from a snapshot. Windows that end more than `retention` seconds
+/// before the latest window of their direction are evicted.
+pub struct QueryStore {
+    retention: u64,
+    /// Windows by the `direction` of their documents
+    directions: RwLock<HashMap<String, Rides>>,
+}
+
+#[derive(Default)]
+struct Rides {
+    latest_end: u64,
+    /// Windows of every zone by start
+    zones: BTreeMap<u64, BTreeMap<u64, RideWindowedData>>,
+    /// End, zone and start of every window, in eviction order
+    ends: BTreeSet<(u64, u64, u64)>,
+}
+
+impl QueryStore {
+    pub fn new(retention: u64) -> Self {
+        Self {
+            retention,
+            directions: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Stores the aggregate of a zone and window, replacing the previous one.
+    ///
+    /// Returns the keys of the windows that were evicted, so that they can
+    /// be removed from `RideState` as well.
+    pub fn insert(&self, data: RideWindowedData) -> Vec<RideKey> {
+        let mut directions = self.directions.write().expect("query store lock poisoned");
+        let rides = directions.entry(data.direction.clone()).or_default();
+        let (location_id, window_start, window_end) =
+            (data.location_id, data.window_start, data.window_end);
+        let windows = rides.zones.entry(location_id).or_default();
+        if let Some(previous) = windows.insert(window_start, data) {
+            rides
+                .ends
+                .remove(&(previous.window_end, location_id, window_start));
+        }
+        rides.ends.insert((window_end, location_id, window_start));
+        if window_end <= rides.latest_end {
+            return Vec::new();
+        }
+        rides.latest_end = window_end;
+
+        let oldest_end = window_end.saturating_sub(self.retention);
+        let kept = rides.ends.split_off(&(oldest_end, 0, 0));
+        let evicted = std::mem::replace(&mut rides.ends, kept);
+        evicted
+            .into_iter()
+            .filter_map(|(_, location_id, window_start)| {
+                let windows = rides.zones.get_mut(&location_id)?;
+                let data = windows.remove(&window_start)?;
+                if windows.is_empty() {
+                    rides.zones.remove(&location_id);
+                }
+                Some(RideKey::of(&data))
+            })
+            .collect()
+    }
+
+    /// Rebuilds the store from the windows of a restored `RideState`.
+    ///
+    /// Returns the keys of the windows that were evicted, see `insert`.
+    pub fn restore(&self, windows: impl IntoIterator<Item = RideWindowedData>) -> Vec<RideKey> {
+        let mut windows: Vec<_> = windows.into_iter().collect();
+        windows.sort_by_key(|data| data.window_end);
+        let mut evicted = Vec::new();
+        for data in windows {
+            evicted.extend(self.insert(data));
+        }
+        evicted
+    }
+
+    /// The latest window of every zone, or of a single zone.
+    fn latest(&self, direction: Direction, location_id: Option<u64>) -> Vec<RideWindowedData> {
+        let directions = self.directions.read().expect("query store lock poisoned");
+        let rides = match directions.get(direction.as_str()) {
+            Some(rides) => rides,
+            None => return Vec::new(),
+        };
+        rides
+            .zones(location_id)
+            .filter_map(|windows| windows.values().next_back().cloned())
+            .collect()
+    }
+
+    /// Windows that start in `[from, to)`, of every zone or of a single zone.
+    fn range(
+        &self,
+        direction: Direction,
+        location_id: Option<u64>,
+        from: u64,
+        to: u64,
+    ) -> Vec<RideWindowedData> {
+        let directions = self.directions.read().expect("query store lock poisoned");
+        let rides = match directions.get(direction.as_str()) {
+            Some(rides) if from < to => rides,
+            _ => return Vec::new(),
+        };
+        rides
+            .zones(location_id)
+            .flat_map(|windows| windows.range(from..to).map(|(_, data)| data.clone()))
+            .collect()
+    }
+}
+
+impl Rides {
+    /// Windows of every zone, or of a single zone.
+    fn zones<'a>(
+        &'a self,
+        location_id: Option<u64>,
+    ) -> Box<dyn Iterator<Item = &'a BTreeMap<u64, RideWindowedData>> + 'a> {
+        match location_id {
+            Some(id) => Box::new(self.zones.get(&id).into_iter()),
+            None => Box::new(self.zones.values()),
+        }
+    }
+}
+
+/// Serves the query API on `listen` from a background thread.
+///
+/// * `GET /rides/{direction}` returns the latest window of every zone
+/// * `GET /rides/{direction}/{location_id}` returns the latest window of a zone
+///
+/// With `from` and `to` query parameters, both return every window that
+/// starts in that range instead. Times are seconds since the epoch or New
+/// York local `%Y-%m-%d %H:%M:%S` datetimes.
+pub fn serve(store: Arc<QueryStore>, listen: &str) -> Result<(), String> {
+    let server = Server::http(listen).map_err(|err| err.to_string())?;
+    std::thread::spawn(move || {
+        for request in server.incoming_requests() {
+            let (status, body) = match handle(&store, &request) {
+                Ok(rides) => (200, json!(rides)),
+                Err((status, message)) => (status, json!({ "error": message })),
+            };
+            let content_type = Header::from_bytes("Content-Type", "application/json")
+                .expect("static header is valid");
+            let response = Response::from_string(body.to_string())
+                .with_status_code(status)
+                .with_header(content_type);
+            if let Err(err) = request.respond(response) {
+                eprintln!("Failed to answer a query: {}", err);
+            }
+        }
+    });
+    Ok(())
+}
+
+fn handle(store: &QueryStore, request: &Request) -> Result<Vec<RideWindowedData>, (u16, String)> {
+    if *request.method() != Method::Get {
+        return Err((405, "only GET is supported".to_owned()));
+    }
+    let url = request.url();
+    let (path, query) = url.split_once('?').unwrap_or((url, ""));
+    let not_found = || (404, format!("no such endpoint {}", path));
+
+    let mut segments = path.trim_matches('/').split('/');
+    if segments.next() != Some("rides") {
+        return Err(not_found());
+    }
+    let direction: Direction = segments
+        .next()
+        .ok_or_else(not_found)?
+        .parse()
+        .map_err(|err| (404, err))?;
+    let location_id = match segments.next() {
+        Some(id) => Some(
+            id.parse()
+                .map_err(|_| (400, format!("invalid location id '{}'", id)))?,
+        ),
+        None => None,
+    };
+    if segments.next().is_some() {
+        return Err(not_found());
+    }
+
+    let mut params = HashMap::new();
+    for param in query.split('&').filter(|param| !param.is_empty()) {
+        let (name, value) = param.split_once('=').unwrap_or((param, ""));
+        params.insert(name, percent_decode(value));
+    }
+    let time = |name: &str| -> Result<Option<u64>, (u16, String)> {
+        match params.get(name) {
+            Some(value) => value
+                .parse()
+                .ok()
+                .or_else(|| data::datetime_to_u64(value).ok())
+                .map(Some)
+                .ok_or_else(|| (400, format!("invalid {} time '{}'", name, value))),
+            None => Ok(None),
+        }
+    };
+
+    match (time("from")?, time("to")?) {
+        (None, None) => Ok(store.latest(direction, location_id)),
+        (from, to) => Ok(store.range(
+            direction,
+            location_id,
+            from.unwrap_or(0),
+            to.unwrap_or(u64::MAX),
+        )),
+    }
+}
+
+/// Decodes a URL query value, where spaces may be written as `+`.
+fn percent_decode(value: &str) -> String {
+    let mut bytes = Vec::with_capacity(value.len());
+    let mut rest = value.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let hex = tail
+            .get(..2)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (byte, hex) {
+            (b'%', Some(decoded)) => {
+                bytes.push(decoded);
+                rest = &tail[2..];
+                continue;
+            }
+            (b'+', _) => bytes.push(b' '),
+            _ => bytes.push(byte),
+        }
+        rest = tail;
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn window(location_id: u64, window_start: u64, count: u64) -> RideWindowedData {
+        RideWindowedData {
+            direction: Direction::Pickup.to_string(),
+            location_id,
+            window_start,
+            window_end: window_start + 10,
+            count,
+            ..RideWindowedData::default()
+        }
+    }
+
+    fn summary(rides: Vec<RideWindowedData>) -> Vec<(u64, u64, u64)> {
+        rides
+            .iter()
+            .map(|data| (data.location_id, data.window_start, data.count))
+            .collect()
+    }
+
+    #[test]
+    fn latest_and_range_windows_of_every_zone() {
+        let store = QueryStore::new(1000);
+        for data in vec![
+            window(1, 0, 1),
+            window(1, 10, 2),
+            window(2, 0, 3),
+            window(1, 10, 4),
+        ] {
+            store.insert(data);
+        }
+        let latest = store.latest(Direction::Pickup, None);
+        assert_eq!(summary(latest), vec![(1, 10, 4), (2, 0, 3)]);
+        let latest = store.latest(Direction::Pickup, Some(2));
+        assert_eq!(summary(latest), vec![(2, 0, 3)]);
+        let range = store.range(Direction::Pickup, Some(1), 0, 10);
+        assert_eq!(summary(range), vec![(1, 0, 1)]);
+        assert!(store.latest(Direction::Dropoff, None).is_empty());
+    }
+
+    #[test]
+    fn old_windows_are_evicted() {
+        let store = QueryStore::new(15);
+        assert!(store.insert(window(1, 0, 1)).is_empty());
+        assert!(store.insert(window(2, 10, 1)).is_empty());
+        let evicted = store.insert(window(1, 30, 1));
+        assert_eq!(
+            evicted,
+            vec![
+                RideKey::of(&window(1, 0, 1)),
+                RideKey::of(&window(2, 10, 1))
+            ]
+        );
+        let latest = store.latest(Direction::Pickup, None);
+        assert_eq!(summary(latest), vec![(1, 30, 1)]);
+    }
+
+    #[test]
+    fn restore_evicts_old_windows_whatever_their_order() {
+        let store = QueryStore::new(15);
+        let evicted = store.restore(vec![window(1, 30, 1), window(1, 0, 1), window(1, 20, 1)]);
+        assert_eq!(evicted, vec![RideKey::of(&window(1, 0, 1))]);
+        let range = store.range(Direction::Pickup, None, 0, u64::MAX);
+        assert_eq!(summary(range), vec![(1, 20, 1), (1, 30, 1)]);
+    }
+}
@@ -0,0 +1,269 @@
+use arcon::prelude::*;
+
+/// Relative accuracy of the quantiles of a `Distribution`.
+const RELATIVE_ACCURACY: f64 = 0.01;
+
+/// Values closer to zero than this are counted as zero.
+const MIN_VALUE: f64 = 1e-9;
+
+fn gamma() -> f64 {
+    (1.0 + RELATIVE_ACCURACY) / (1.0 - RELATIVE_ACCURACY)
+}
+
+/// Index of the bin that a positive value falls into.
+fn bin(value: f64) -> i32 {
+    (value.ln() / gamma().ln()).ceil() as i32
+}
+
+/// Representative value of a bin, within `RELATIVE_ACCURACY` of every value
+/// in it.
+fn bin_value(bin: i32) -> f64 {
+    2.0 * gamma().powi(bin) / (gamma() + 1.0)
+}
+
+/// Adds `count` to a bin of a store of sorted bins.
+fn insert(bins: &mut Vec<i32>, counts: &mut Vec<u64>, bin: i32, count: u64) {
+    match bins.binary_search(&bin) {
+        Ok(i) => counts[i] += count,
+        Err(i) => {
+            bins.insert(i, bin);
+            counts.insert(i, count);
+        }
+    }
+}
+
+/// Merges two stores of sorted bins.
+fn merge_bins(
+    a_bins: &[i32],
+    a_counts: &[u64],
+    b_bins: &[i32],
+    b_counts: &[u64],
+) -> (Vec<i32>, Vec<u64>) {
+    let mut bins = a_bins.to_vec();
+    let mut counts = a_counts.to_vec();
+    for (&bin, &count) in b_bins.iter().zip(b_counts) {
+        insert(&mut bins, &mut counts, bin, count);
+    }
+    (bins, counts)
+}
+
+/// Mergeable summary of a set of values: a DDSketch for quantiles and
+/// Welford's running moments for the standard deviation.
+///
+/// Quantiles are within 1% of the exact value. The empty distribution is
+/// `Distribution::default()`.
+#[macros::proto]
+#[derive(Arcon, Clone)]
+#[arcon(unsafe_ser_id = 12, reliable_ser_id = 13, version = 1)]
+pub struct Distribution {
+    pub count: u64,
+    pub mean: f64,
+    /// Sum of squared differences from the mean
+    pub m2: f64,
+    /// Bins of the positive values, sorted by index
+    pub positive_bins: Vec<i32>,
+    pub positive_counts: Vec<u64>,
+    /// Bins of the absolute negative values, sorted by index
+    pub negative_bins: Vec<i32>,
+    pub negative_counts: Vec<u64>,
+    pub zero_count: u64,
+}
+
+impl Distribution {
+    /// Distribution of a single value.
+    pub fn of(value: f64) -> Self {
+        let mut dist = Self::default();
+        dist.add(value);
+        dist
+    }
+
+    /// Adds a value in place, with the same result as merging
+    /// `Distribution::of(value)`.
+    pub fn add(&mut self, value: f64) {
+        let a = self.count as f64;
+        self.count += 1;
+        let n = self.count as f64;
+        let delta = value - self.mean;
+        self.mean += delta / n;
+        self.m2 += delta * delta * a / n;
+        if value > MIN_VALUE {
+            insert(
+                &mut self.positive_bins,
+                &mut self.positive_counts,
+                bin(value),
+                1,
+            );
+        } else if value < -MIN_VALUE {
+            insert(
+                &mut self.negative_bins,
+                &mut self.negative_counts,
+                bin(-value),
+                1,
+            );
+        } else {
+            self.zero_count += 1;
+        }
+    }
+
+    /// Combines two distributions.
+    pub fn merge(&self, other: &Self) -> Self {
+        let count = self.count + other.count;
+        if count == 0 {
+            return Self::default();
+        }
+        let delta = other.mean - self.mean;
+        let (a, b, n) = (self.count as f64, other.count as f64, count as f64);
+        let (positive_bins, positive_counts) = merge_bins(
+            &self.positive_bins,
+            &self.positive_counts,
+            &other.positive_bins,
+            &other.positive_counts,
+        );
+        let (negative_bins, negative_counts) = merge_bins(
+            &self.negative_bins,
+            &self.negative_counts,
+            &other.negative_bins,
+            &other.negative_counts,
+        );
+        Self {
+            count,
+            mean: self.mean + delta * b / n,
+            m2: self.m2 + other.m2 + delta * delta * a * b / n,
+            positive_bins,
+            positive_counts,
+            negative_bins,
+            negative_counts,
+            zero_count: self.zero_count + other.zero_count,
+        }
+    }
+
+    /// Population standard deviation, or 0 if the distribution is empty.
+    pub fn stddev(&self) -> f64 {
+        if self.count == 0 {
+            0.0
+        } else {
+            (self.m2 / self.count as f64).max(0.0).sqrt()
+        }
+    }
+
+    /// Approximate `q`-quantile for `q` in `[0, 1]`, or 0 if the
+    /// distribution is empty.
+    pub fn quantile(&self, q: f64) -> f64 {
+        if self.count == 0 {
+            return 0.0;
+        }
+        let rank = (q.max(0.0).min(1.0) * (self.count - 1) as f64) as u64;
+
+        // Negative values in ascending order are the largest bins first
+        let mut seen = 0;
+        for (&bin, &count) in self.negative_bins.iter().zip(&self.negative_counts).rev() {
+            seen += count;
+            if seen > rank {
+                return -bin_value(bin);
+            }
+        }
+        seen += self.zero_count;
+        if seen > rank {
+            return 0.0;
+        }
+        for (&bin, &count) in self.positive_bins.iter().zip(&self.positive_counts) {
+            seen += count;
+            if seen > rank {
+                return bin_value(bin);
+            }
+        }
+        self.positive_bins.last().map_or(0.0, |&bin| bin_value(bin))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    const QUANTILES: [f64; 9] = [0.0, 0.01, 0.1, 0.25, 0.5, 0.75, 0.9, 0.99, 1.0];
+
+    /// Fares in cents, with some zeros and refunds.
+    fn values(seed: u64, count: usize) -> Vec<f64> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        (0..count)
+            .map(|_| match rng.gen_range(0..20) {
+                0 => 0.0,
+                1 => -rng.gen_range(1.0..5000.0),
+                _ => rng.gen_range(1.0..20000.0),
+            })
+            .collect()
+    }
+
+    fn distribution(values: &[f64]) -> Distribution {
+        let mut dist = Distribution::default();
+        for &value in values {
+            dist.add(value);
+        }
+        dist
+    }
+
+    /// Value at the rank `quantile` looks up, of sorted values.
+    fn exact(sorted: &[f64], q: f64) -> f64 {
+        sorted[(q * (sorted.len() - 1) as f64) as usize]
+    }
+
+    fn assert_close(actual: f64, expected: f64, relative: f64) {
+        assert!(
+            (actual - expected).abs() <= relative * expected.abs() + 1e-9,
+            "{} is not within {} of {}",
+            actual,
+            relative,
+            expected
+        );
+    }
+
+    #[test]
+    fn quantiles_are_within_the_relative_accuracy() {
+        let values = values(1, 10_000);
+        let dist = distribution(&values);
+        let mut sorted = values.clone();
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+        for &q in &QUANTILES {
+            assert_close(dist.quantile(q), exact(&sorted, q), RELATIVE_ACCURACY);
+        }
+    }
+
+    #[test]
+    fn merged_sketches_match_a_single_sketch() {
+        let (a, b) = (values(2, 3000), values(3, 7000));
+        let merged = distribution(&a).merge(&distribution(&b));
+        let all: Vec<f64> = a.iter().chain(&b).copied().collect();
+        let single = distribution(&all);
+
+        assert_eq!(merged.count, single.count);
+        assert_eq!(merged.zero_count, single.zero_count);
+        assert_eq!(merged.positive_bins, single.positive_bins);
+        assert_eq!(merged.positive_counts, single.positive_counts);
+        assert_eq!(merged.negative_bins, single.negative_bins);
+        assert_eq!(merged.negative_counts, single.negative_counts);
+
+        let mut sorted = all.clone();
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        for &q in &QUANTILES {
+            assert_close(merged.quantile(q), exact(&sorted, q), RELATIVE_ACCURACY);
+        }
+
+        let mean = all.iter().sum::<f64>() / all.len() as f64;
+        let variance = all.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / all.len() as f64;
+        assert_close(merged.mean, mean, 1e-9);
+        assert_close(merged.stddev(), variance.sqrt(), 1e-9);
+    }
+
+    #[test]
+    fn empty_distribution() {
+        let empty = Distribution::default();
+        assert_eq!(empty.quantile(0.5), 0.0);
+        assert_eq!(empty.stddev(), 0.0);
+        assert_eq!(empty.merge(&empty).count, 0);
+        let one = empty.merge(&Distribution::of(42.0));
+        assert_close(one.quantile(0.5), 42.0, RELATIVE_ACCURACY);
+    }
+}
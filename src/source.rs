@@ -0,0 +1,1033 @@
+use arcon::prelude::*;
+use chrono::NaiveDateTime;
+use parquet::errors::ParquetError;
+use parquet::file::reader::{FileReader, SerializedFileReader};
+use parquet::record::reader::RowIter;
+use parquet::record::{Field, Row};
+use parquet::schema::types::Type as SchemaType;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::ops::Range;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering as AtomicOrdering};
+use std::time::Instant;
+
+use crate::data::{ParquetRow, RawLine, TaxiRideData};
+
+#[derive(Debug)]
+pub enum InputError {
+    Io(PathBuf, io::Error),
+    Parquet(PathBuf, ParquetError),
+    Pattern(String, String),
+    NoFiles(PathBuf),
+}
+
+impl fmt::Display for InputError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InputError::Io(path, err) => write!(f, "failed to read {}: {}", path.display(), err),
+            InputError::Parquet(path, err) => {
+                write!(f, "failed to read {}: {}", path.display(), err)
+            }
+            InputError::Pattern(pattern, err) => {
+                write!(f, "invalid input pattern '{}': {}", pattern, err)
+            }
+            InputError::NoFiles(path) => write!(f, "no input files found at {}", path.display()),
+        }
+    }
+}
+
+impl std::error::Error for InputError {}
+
+/// Files of an input path: the file itself, the files of a directory with the
+/// extension of the format, or the files matching a glob pattern like
+/// `data/yellow_tripdata_*.csv`.
+///
+/// Files are returned sorted by name.
+pub fn input_files(path: &Path, format: InputFormat) -> Result<Vec<PathBuf>, InputError> {
+    let mut files = if path.is_dir() {
+        let entries = std::fs::read_dir(path).map_err(|e| InputError::Io(path.to_owned(), e))?;
+        let mut files = Vec::new();
+        for entry in entries {
+            let file = entry
+                .map_err(|e| InputError::Io(path.to_owned(), e))?
+                .path();
+            if file.is_file()
+                && file
+                    .extension()
+                    .map_or(false, |ext| ext == format.extension())
+            {
+                files.push(file);
+            }
+        }
+        files
+    } else if path.exists() {
+        vec![path.to_owned()]
+    } else {
+        let pattern = path.to_string_lossy();
+        let paths = glob::glob(&pattern)
+            .map_err(|e| InputError::Pattern(pattern.to_string(), e.to_string()))?;
+        let mut files = Vec::new();
+        for file in paths {
+            let file = file.map_err(|e| {
+                let path = e.path().to_owned();
+                InputError::Io(path, e.into_error())
+            })?;
+            if file.is_file() {
+                files.push(file);
+            }
+        }
+        files
+    };
+
+    if files.is_empty() {
+        return Err(InputError::NoFiles(path.to_owned()));
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Timestamp extractor of a source whose records are out of order by at most
+/// `max_delay` seconds of event time.
+///
+/// Arcon takes the watermark of a source from the timestamps of its records,
+/// so every record is stamped with the latest event time seen so far minus
+/// `max_delay`. A record is only late if it is more than `max_delay` behind
+/// the latest one. Only event times within `plausible` count as seen, so an
+/// implausible record is stamped with the watermark as it is, and
+/// `ops::QualityFilter` deals with it once it is parsed.
+pub fn bounded_out_of_orderness<A>(
+    time: fn(&A) -> u64,
+    max_delay: u64,
+    plausible: Range<u64>,
+) -> impl Fn(&A) -> u64 + Send + Sync + 'static {
+    let latest = AtomicU64::new(0);
+    move |record| {
+        let time = time(record);
+        let latest = if plausible.contains(&time) {
+            latest.fetch_max(time, AtomicOrdering::Relaxed).max(time)
+        } else {
+            latest.load(AtomicOrdering::Relaxed)
+        };
+        latest.saturating_sub(max_delay)
+    }
+}
+
+/// Where rides are read from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SourceKind {
+    /// The files of `source.path`
+    File,
+    /// The Kafka topic of the `kafka` section
+    Kafka,
+}
+
+impl std::str::FromStr for SourceKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "file" => Ok(SourceKind::File),
+            "kafka" => Ok(SourceKind::Kafka),
+            _ => Err(format!("unknown source kind '{}'", s)),
+        }
+    }
+}
+
+/// Format of the input files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InputFormat {
+    /// TLC CSV files, read as `RawLine`s
+    Csv,
+    /// TLC Parquet files, read as `ParquetRow`s
+    Parquet,
+}
+
+impl InputFormat {
+    /// Extension of the files of a directory input.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            InputFormat::Csv => "csv",
+            InputFormat::Parquet => "parquet",
+        }
+    }
+}
+
+impl std::str::FromStr for InputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(InputFormat::Csv),
+            "parquet" => Ok(InputFormat::Parquet),
+            _ => Err(format!("unknown input format '{}'", s)),
+        }
+    }
+}
+
+/// Records of a single input file.
+pub type Records<T> = Box<dyn Iterator<Item = T> + Send>;
+
+/// Source that merges the records of several files by pickup time.
+///
+/// The earliest next record of all files is emitted first, so monthly files
+/// that are each in pickup order are read as one stream in event-time order
+/// without sorting them first. A record whose pickup time is not within
+/// `plausible` is ordered at the pickup time of the record before it in its
+/// file, so a future-dated or malformed record does not hold its file back.
+pub struct Merged<T> {
+    files: Vec<Records<T>>,
+    time: fn(&T) -> u64,
+    plausible: Range<u64>,
+    /// Event time and file of the next record of every file that is not
+    /// exhausted
+    heads: BinaryHeap<Reverse<(u64, usize)>>,
+    next: Vec<Option<T>>,
+    /// Latest plausible event time read from every file
+    latest: Vec<u64>,
+}
+
+impl<T> Merged<T> {
+    pub fn new(files: Vec<Records<T>>, time: fn(&T) -> u64, plausible: Range<u64>) -> Self {
+        let mut source = Self {
+            next: files.iter().map(|_| None).collect(),
+            latest: vec![0; files.len()],
+            files,
+            time,
+            plausible,
+            heads: BinaryHeap::new(),
+        };
+        for i in 0..source.files.len() {
+            source.advance(i);
+        }
+        source
+    }
+
+    fn advance(&mut self, i: usize) {
+        if let Some(record) = self.files[i].next() {
+            let time = (self.time)(&record);
+            if self.plausible.contains(&time) {
+                self.latest[i] = self.latest[i].max(time);
+            }
+            self.heads.push(Reverse((self.latest[i], i)));
+            self.next[i] = Some(record);
+        }
+    }
+
+    /// Event time of the next record, or `None` once every file is exhausted.
+    fn peek_time(&self) -> Option<u64> {
+        self.heads.peek().map(|Reverse((time, _))| *time)
+    }
+
+    fn pop(&mut self) -> Option<T> {
+        let Reverse((_, i)) = self.heads.pop()?;
+        let record = self.next[i].take();
+        self.advance(i);
+        record
+    }
+}
+
+impl<T: ArconType> Source for Merged<T> {
+    type Item = T;
+
+    fn poll_next(&mut self) -> SourceResult<Poll<Self::Item>> {
+        match self.pop() {
+            Some(record) => Ok(Poll::Ready(record)),
+            None => Ok(Poll::Done),
+        }
+    }
+
+    /// Skips the records that were emitted before the offset.
+    fn set_offset(&mut self, offset: usize) {
+        for _ in 0..offset {
+            self.pop();
+        }
+    }
+}
+
+/// A replayed record, ordered by the event time at which it is released.
+struct Delayed<T> {
+    release: u64,
+    seq: u64,
+    record: T,
+}
+
+impl<T> PartialEq for Delayed<T> {
+    fn eq(&self, other: &Self) -> bool {
+        (self.release, self.seq) == (other.release, other.seq)
+    }
+}
+
+impl<T> Eq for Delayed<T> {}
+
+impl<T> PartialOrd for Delayed<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for Delayed<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.release, self.seq).cmp(&(other.release, other.seq))
+    }
+}
+
+/// Source that replays merged files like a live stream.
+///
+/// With a speed-up, records are emitted once the wall-clock time since the
+/// first ride, multiplied by the speed-up, reaches their pickup time, so
+/// `3600` replays an hour of rides every second. Without one, records are
+/// emitted as fast as they are read.
+///
+/// With a shuffle, every record is held back by a random delay of up to
+/// `shuffle` seconds of event time, so rides arrive out of order by at most
+/// that much, as they would from a live feed. The delays are drawn from
+/// `seed`, and records are released in the order of their delayed time
+/// whatever the speed-up, so a replay always emits the same sequence.
+pub struct Replay<T> {
+    files: Merged<T>,
+    speedup: Option<f64>,
+    shuffle: u64,
+    rng: StdRng,
+    /// Wall-clock time and pickup time of the first ride
+    clock: Option<(Instant, u64)>,
+    pending: BinaryHeap<Reverse<Delayed<T>>>,
+    seq: u64,
+}
+
+impl<T> Replay<T> {
+    pub fn new(files: Merged<T>, speedup: Option<f64>, shuffle: u64, seed: u64) -> Self {
+        Self {
+            files,
+            speedup,
+            shuffle,
+            rng: StdRng::seed_from_u64(seed),
+            clock: None,
+            pending: BinaryHeap::new(),
+            seq: 0,
+        }
+    }
+
+    /// Event time up to which records are released.
+    fn now(&mut self) -> u64 {
+        let next = self.files.peek_time();
+        match (self.speedup, self.clock) {
+            (None, _) => next.unwrap_or(u64::MAX),
+            (Some(speedup), Some((start, first))) => {
+                first + (start.elapsed().as_secs_f64() * speedup) as u64
+            }
+            // Malformed records have no pickup time and do not start the clock
+            (Some(_), None) => match next {
+                Some(0) => 0,
+                Some(first) => {
+                    self.clock = Some((Instant::now(), first));
+                    first
+                }
+                None => u64::MAX,
+            },
+        }
+    }
+
+    fn pull(&mut self) {
+        let time = self.files.peek_time().unwrap_or(0);
+        if let Some(record) = self.files.pop() {
+            let delay = if self.shuffle > 0 {
+                self.rng.gen_range(0..=self.shuffle)
+            } else {
+                0
+            };
+            self.seq += 1;
+            self.pending.push(Reverse(Delayed {
+                release: time + delay,
+                seq: self.seq,
+                record,
+            }));
+        }
+    }
+
+    /// Releases the earliest pending record if it is due by `now`.
+    ///
+    /// Every record read by `now` is pulled first. A record that is still in
+    /// the files is due after `now`, so records are released in the order of
+    /// their delayed time however fast the clock runs.
+    fn release(&mut self, now: u64) -> Option<T> {
+        while matches!(self.files.peek_time(), Some(time) if time <= now) {
+            self.pull();
+        }
+        match self.pending.peek() {
+            Some(Reverse(next)) if next.release <= now => {
+                let Reverse(next) = self.pending.pop().expect("peeked record");
+                Some(next.record)
+            }
+            _ => None,
+        }
+    }
+
+    fn next_record(&mut self) -> Poll<T> {
+        if self.speedup.is_none() && self.shuffle == 0 && self.pending.is_empty() {
+            return match self.files.pop() {
+                Some(record) => Poll::Ready(record),
+                None => Poll::Done,
+            };
+        }
+        loop {
+            let now = self.now();
+            if let Some(record) = self.release(now) {
+                return Poll::Ready(record);
+            }
+            match self.files.peek_time() {
+                None if self.pending.is_empty() => return Poll::Done,
+                // Without a speed-up, the clock follows the files
+                Some(_) if self.speedup.is_none() => (),
+                _ => return Poll::Pending,
+            }
+        }
+    }
+
+    /// Drops the next record that would be released, without waiting for
+    /// the clock. Returns false once every record was released.
+    fn skip(&mut self) -> bool {
+        loop {
+            let due = match (self.pending.peek(), self.files.peek_time()) {
+                (Some(Reverse(next)), _) => next.release,
+                (None, Some(time)) => time,
+                (None, None) => return false,
+            };
+            if self.release(due).is_some() {
+                return true;
+            }
+        }
+    }
+}
+
+impl<T: ArconType> Source for Replay<T> {
+    type Item = T;
+
+    fn poll_next(&mut self) -> SourceResult<Poll<Self::Item>> {
+        Ok(self.next_record())
+    }
+
+    /// Skips the records that were emitted before the offset. They are
+    /// skipped in the order they were released, without replaying them at
+    /// the speed-up.
+    fn set_offset(&mut self, offset: usize) {
+        for _ in 0..offset {
+            if !self.skip() {
+                break;
+            }
+        }
+    }
+}
+
+/// Opens CSV files as a source of their lines, merged by the pickup times
+/// within `plausible`.
+pub fn csv_files(paths: &[PathBuf], plausible: Range<u64>) -> Result<Merged<RawLine>, InputError> {
+    let mut files = Vec::with_capacity(paths.len());
+    for path in paths {
+        files.push(Box::new(CsvLines::open(path)?) as Records<RawLine>);
+    }
+    Ok(Merged::new(files, RawLine::pickup_time, plausible))
+}
+
+/// Lines of a CSV file, without its header, byte order mark and blank lines.
+struct CsvLines {
+    path: PathBuf,
+    lines: io::Lines<BufReader<File>>,
+    /// Number of the last line read
+    number: u64,
+    /// Whether a line other than a blank one was read
+    started: bool,
+}
+
+impl CsvLines {
+    fn open(path: &Path) -> Result<Self, InputError> {
+        let file = File::open(path).map_err(|e| InputError::Io(path.to_owned(), e))?;
+        Ok(Self {
+            path: path.to_owned(),
+            lines: BufReader::new(file).lines(),
+            number: 0,
+            started: false,
+        })
+    }
+}
+
+impl Iterator for CsvLines {
+    type Item = RawLine;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let mut line = match self.lines.next()? {
+                Ok(line) => line,
+                Err(err) => {
+                    eprintln!(
+                        "Failed to read {}, skipping the rest of it: {}",
+                        self.path.display(),
+                        err
+                    );
+                    return None;
+                }
+            };
+            self.number += 1;
+            if self.number == 1 && line.starts_with('\u{feff}') {
+                line.drain(..'\u{feff}'.len_utf8());
+            }
+            if line.trim().is_empty() {
+                continue;
+            }
+            let line = RawLine {
+                line,
+                path: self.path.display().to_string(),
+                line_number: self.number,
+            };
+            // The first line without a pickup datetime is the header, but
+            // files without one start with a ride
+            let header = !self.started && line.pickup_time() == 0;
+            self.started = true;
+            if header {
+                continue;
+            }
+            return Some(line);
+        }
+    }
+}
+
+/// Columns of the TLC yellow taxi Parquet schema that `RideData` needs.
+const PARQUET_COLUMNS: [&str; 17] = [
+    "VendorID",
+    "tpep_pickup_datetime",
+    "tpep_dropoff_datetime",
+    "passenger_count",
+    "trip_distance",
+    "RatecodeID",
+    "PULocationID",
+    "DOLocationID",
+    "payment_type",
+    "fare_amount",
+    "extra",
+    "mta_tax",
+    "tip_amount",
+    "tolls_amount",
+    "improvement_surcharge",
+    "total_amount",
+    "congestion_surcharge",
+];
+
+/// Opens Parquet files as a source of their trip records, merged by the
+/// pickup times within `plausible`.
+pub fn parquet_files(
+    paths: &[PathBuf],
+    plausible: Range<u64>,
+) -> Result<Merged<ParquetRow>, InputError> {
+    let mut files = Vec::with_capacity(paths.len());
+    for path in paths {
+        let rows = parquet_rows(path).map_err(|e| InputError::Parquet(path.clone(), e))?;
+        let rows = ParquetRows::new(path.display().to_string(), rows);
+        files.push(Box::new(rows) as Records<ParquetRow>);
+    }
+    Ok(Merged::new(files, ParquetRow::pickup_time, plausible))
+}
+
+/// Rows of a Parquet file with their file and index.
+///
+/// The Parquet reader panics on a row it cannot decode. The panic is caught
+/// and the row is emitted with the error instead, and the rest of the file,
+/// which the reader cannot resume, is skipped.
+struct ParquetRows<I> {
+    path: String,
+    rows: Option<I>,
+    /// Index of the next row
+    index: u64,
+}
+
+impl<I: Iterator<Item = Row>> ParquetRows<I> {
+    fn new(path: String, rows: I) -> Self {
+        Self {
+            path,
+            rows: Some(rows),
+            index: 0,
+        }
+    }
+}
+
+impl<I: Iterator<Item = Row>> Iterator for ParquetRows<I> {
+    type Item = ParquetRow;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let rows = self.rows.as_mut()?;
+        let next = panic::catch_unwind(AssertUnwindSafe(|| rows.next()));
+        let row = ParquetRow {
+            path: self.path.clone(),
+            row: self.index,
+            ..ParquetRow::default()
+        };
+        self.index += 1;
+        match next {
+            Ok(Some(next)) => Some(ParquetRow {
+                ride: taxi_ride(next),
+                ..row
+            }),
+            Ok(None) => {
+                self.rows = None;
+                None
+            }
+            Err(panic) => {
+                self.rows = None;
+                let error = match panic.downcast::<String>() {
+                    Ok(message) => *message,
+                    Err(panic) => panic
+                        .downcast_ref::<&str>()
+                        .map_or_else(|| "unknown error".to_owned(), |s| (*s).to_owned()),
+                };
+                eprintln!(
+                    "Failed to read row {} of {}, skipping the rest of it: {}",
+                    row.row, self.path, error
+                );
+                Some(ParquetRow { error, ..row })
+            }
+        }
+    }
+}
+
+/// Rows of a Parquet file, projected on the `PARQUET_COLUMNS` it has.
+///
+/// The projection is taken from the schema of the file itself, since the
+/// physical types of the columns differ between TLC releases.
+fn parquet_rows(path: &Path) -> Result<RowIter<'static>, ParquetError> {
+    let file = File::open(path)?;
+    let reader = SerializedFileReader::new(file)?;
+    let schema = reader.metadata().file_metadata().schema();
+    let mut columns: Vec<_> = schema
+        .get_fields()
+        .iter()
+        .filter(|field| PARQUET_COLUMNS.contains(&field.name()))
+        .cloned()
+        .collect();
+    let projection = SchemaType::group_type_builder(schema.name())
+        .with_fields(&mut columns)
+        .build()?;
+    RowIter::from_file_into(Box::new(reader)).project(Some(projection))
+}
+
+/// Converts a Parquet row into a trip record.
+///
+/// Null columns, which the TLC uses for unknown passenger counts and codes,
+/// are read as 0 and left to the data-quality rules.
+fn taxi_ride(row: Row) -> TaxiRideData {
+    let mut ride = TaxiRideData::default();
+    for (name, field) in row.get_column_iter() {
+        match name.as_str() {
+            "VendorID" => ride.vendor_id = field_u64(field),
+            "tpep_pickup_datetime" => ride.tpep_pickup_datetime = field_datetime(field),
+            "tpep_dropoff_datetime" => ride.tpep_dropoff_datetime = field_datetime(field),
+            "passenger_count" => ride.passenger_count = field_u64(field),
+            "trip_distance" => ride.trip_distance = field_f64(field) as f32,
+            "RatecodeID" => ride.rate_code_id = field_u64(field),
+            "PULocationID" => ride.pu_location_id = field_u64(field),
+            "DOLocationID" => ride.du_location_id = field_u64(field),
+            "payment_type" => ride.payment_type = field_u64(field),
+            "fare_amount" => ride.fare_amount = field_cents(field),
+            "extra" => ride.extra = field_cents(field),
+            "mta_tax" => ride.mta_tax = field_cents(field),
+            "tip_amount" => ride.tip_amount = field_cents(field),
+            "tolls_amount" => ride.tolls_amount = field_cents(field),
+            "improvement_surcharge" => ride.improvement_surcharge = field_cents(field),
+            "total_amount" => ride.total_amount = field_cents(field),
+            "congestion_surcharge" => ride.congestion_surcharge = field_cents(field),
+            _ => {}
+        }
+    }
+    ride
+}
+
+fn field_f64(field: &Field) -> f64 {
+    match *field {
+        Field::Int(value) => f64::from(value),
+        Field::Long(value) => value as f64,
+        Field::Float(value) => f64::from(value),
+        Field::Double(value) => value,
+        _ => 0.0,
+    }
+}
+
+fn field_u64(field: &Field) -> u64 {
+    match *field {
+        Field::Int(value) => value.max(0) as u64,
+        Field::Long(value) => value.max(0) as u64,
+        // Codes and passenger counts are doubles in some releases
+        _ => field_f64(field).max(0.0).round() as u64,
+    }
+}
+
+fn field_cents(field: &Field) -> i64 {
+    (field_f64(field) * 100.0).round() as i64
+}
+
+/// Formats a timestamp column as a TLC datetime.
+///
+/// The TLC writes the New York wall-clock time without a time zone, so the
+/// timestamp is formatted as UTC to get the wall-clock time back.
+fn field_datetime(field: &Field) -> String {
+    let millis = match *field {
+        Field::TimestampMillis(millis) => millis as i64,
+        Field::TimestampMicros(micros) => micros as i64 / 1000,
+        _ => return String::new(),
+    };
+    NaiveDateTime::from_timestamp(millis.div_euclid(1000), 0)
+        .format("%Y-%m-%d %H:%M:%S")
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn implausible_times_do_not_move_the_watermark() {
+        let stamp = bounded_out_of_orderness(|time: &u64| *time, 10, 1..1000);
+        let stamps: Vec<_> = [100, 90, 5000, 0, 110, 95]
+            .iter()
+            .map(|time| stamp(time))
+            .collect();
+        // The outlier at 5000 and the malformed 0 keep the watermark where it
+        // was, so the rides after them are not late
+        assert_eq!(stamps, vec![90, 90, 90, 90, 100, 100]);
+    }
+
+    fn replay(speedup: Option<f64>) -> Replay<u64> {
+        let files = vec![
+            Box::new((1..100).step_by(2)) as Records<u64>,
+            Box::new((2..100).step_by(2)) as Records<u64>,
+        ];
+        Replay::new(
+            Merged::new(files, |time| *time, 1..u64::MAX),
+            speedup,
+            30,
+            7,
+        )
+    }
+
+    fn drain(replay: &mut Replay<u64>) -> Vec<u64> {
+        let mut records = Vec::new();
+        loop {
+            match replay.next_record() {
+                Poll::Ready(record) => records.push(record),
+                Poll::Pending => (),
+                Poll::Done => return records,
+            }
+        }
+    }
+
+    #[test]
+    fn shuffle_is_the_same_at_any_speed() {
+        let records = drain(&mut replay(None));
+        assert_eq!(records.len(), 99);
+        assert_ne!(records, (1..100).collect::<Vec<_>>());
+        assert_eq!(drain(&mut replay(Some(1e12))), records);
+    }
+
+    #[test]
+    fn restore_skips_the_released_records() {
+        let records = drain(&mut replay(None));
+        let mut restored = replay(Some(1e12));
+        for _ in 0..40 {
+            assert!(restored.skip());
+        }
+        assert_eq!(drain(&mut restored), records[40..].to_vec());
+    }
+
+    #[test]
+    fn merged_files_are_read_in_pickup_order() {
+        let files = vec![
+            Box::new(vec![10, 40, 50].into_iter()) as Records<u64>,
+            Box::new(vec![20, 30, 60].into_iter()) as Records<u64>,
+        ];
+        let mut merged = Merged::new(files, |time| *time, 1..1000);
+        let records: Vec<_> = std::iter::from_fn(|| merged.pop()).collect();
+        assert_eq!(records, vec![10, 20, 30, 40, 50, 60]);
+    }
+
+    #[test]
+    fn implausible_records_do_not_hold_their_file_back() {
+        // The future-dated 9000 and the malformed 0 are ordered after the
+        // record before them in their file
+        let files = vec![
+            Box::new(vec![10, 9000, 0, 40].into_iter()) as Records<u64>,
+            Box::new(vec![20, 30, 50].into_iter()) as Records<u64>,
+        ];
+        let mut merged = Merged::new(files, |time| *time, 1..1000);
+        let records: Vec<_> = std::iter::from_fn(|| merged.pop()).collect();
+        assert_eq!(records, vec![10, 9000, 0, 20, 30, 40, 50]);
+    }
+
+    fn csv_lines(name: &str, text: &str) -> Vec<RawLine> {
+        let path =
+            std::env::temp_dir().join(format!("arcon_taxi-{}-{}.csv", name, std::process::id()));
+        std::fs::write(&path, text).unwrap();
+        let lines = CsvLines::open(&path).unwrap().collect();
+        std::fs::remove_file(&path).unwrap();
+        lines
+    }
+
+    fn numbers(lines: &[RawLine]) -> Vec<u64> {
+        lines.iter().map(|line| line.line_number).collect()
+    }
+
+    #[test]
+    fn csv_lines_keep_their_file_and_line_number() {
+        let path =
+            std::env::temp_dir().join(format!("arcon_taxi-lines-{}.csv", std::process::id()));
+        std::fs::write(
+            &path,
+            "VendorID,tpep_pickup_datetime\n1,2020-01-01 00:00:00\n\n2,2020-01-01 00:01:00\n",
+        )
+        .unwrap();
+        let lines: Vec<_> = CsvLines::open(&path).unwrap().collect();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(numbers(&lines), vec![2, 4]);
+        assert!(lines
+            .iter()
+            .all(|line| line.path == path.display().to_string()));
+    }
+
+    #[test]
+    fn csv_header_is_the_first_non_blank_line() {
+        let lines = csv_lines(
+            "blank-header",
+            "\n\nVendorID,tpep_pickup_datetime\n1,2020-01-01 00:00:00\n",
+        );
+        assert_eq!(numbers(&lines), vec![4]);
+    }
+
+    #[test]
+    fn csv_header_may_start_with_a_byte_order_mark() {
+        let lines = csv_lines(
+            "bom",
+            "\u{feff}VendorID,tpep_pickup_datetime\n1,2020-01-01 00:00:00\n",
+        );
+        assert_eq!(numbers(&lines), vec![2]);
+
+        let lines = csv_lines("bom-ride", "\u{feff}1,2020-01-01 00:00:00\n");
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0].line, "1,2020-01-01 00:00:00");
+        assert!(lines[0].pickup_time() > 0);
+    }
+
+    #[test]
+    fn csv_files_without_a_header_keep_their_first_ride() {
+        let lines = csv_lines(
+            "no-header",
+            "1,2020-01-01 00:00:00\nnot a ride\n2,2020-01-01 00:01:00\n",
+        );
+        assert_eq!(numbers(&lines), vec![1, 2, 3]);
+    }
+
+    fn parquet_fixture(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "arcon_taxi-{}-{}.parquet",
+            name,
+            std::process::id()
+        ));
+        parquet_fixture::write(&path);
+        path
+    }
+
+    #[test]
+    fn parquet_rows_are_projected_on_the_ride_columns() {
+        let path = parquet_fixture("projection");
+        let rows: Vec<Row> = parquet_rows(&path).unwrap().collect();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(rows.len(), 3);
+        let columns: Vec<_> = rows[0]
+            .get_column_iter()
+            .map(|(name, _)| name.as_str())
+            .collect();
+        assert_eq!(
+            columns,
+            vec![
+                "VendorID",
+                "tpep_pickup_datetime",
+                "tpep_dropoff_datetime",
+                "passenger_count",
+                "trip_distance",
+                "PULocationID",
+                "DOLocationID",
+                "fare_amount",
+                "total_amount",
+            ]
+        );
+    }
+
+    #[test]
+    fn parquet_rows_are_read_with_their_file_and_index() {
+        let path = parquet_fixture("rows");
+        let rows: Vec<_> = parquet_files(&[path.clone()], 1..u64::MAX)
+            .map(|mut files| std::iter::from_fn(|| files.pop()).collect())
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let path = path.display().to_string();
+        let origins: Vec<_> = rows.iter().map(|row| (&row.path, row.row)).collect();
+        assert_eq!(origins, vec![(&path, 0), (&path, 1), (&path, 2)]);
+        assert!(rows.iter().all(|row| row.error.is_empty()));
+
+        let complete = &rows[0].ride;
+        assert_eq!(complete.vendor_id, 1);
+        assert_eq!(complete.tpep_pickup_datetime, "2020-01-01 00:28:15");
+        assert_eq!(complete.tpep_dropoff_datetime, "2020-01-01 00:33:03");
+        assert_eq!(complete.passenger_count, 1);
+        assert!((complete.trip_distance - 1.2).abs() < 1e-6);
+        assert_eq!(
+            (complete.pu_location_id, complete.du_location_id),
+            (238, 239)
+        );
+        assert_eq!((complete.fare_amount, complete.total_amount), (600, 1127));
+    }
+
+    #[test]
+    fn null_parquet_fields_are_read_as_zero_or_empty() {
+        let path = parquet_fixture("nulls");
+        let rides: Vec<_> = parquet_rows(&path).unwrap().map(taxi_ride).collect();
+        std::fs::remove_file(&path).unwrap();
+
+        let nulls = &rides[1];
+        assert_eq!((nulls.vendor_id, nulls.passenger_count), (0, 0));
+        assert_eq!((nulls.fare_amount, nulls.total_amount), (-450, -830));
+        // Columns the file does not have are left at 0
+        assert_eq!((nulls.rate_code_id, nulls.payment_type), (0, 0));
+
+        let no_times = &rides[2];
+        assert_eq!(no_times.tpep_pickup_datetime, "");
+        assert_eq!(no_times.pickup_time(), 0);
+        let rejected =
+            <crate::data::RideData as std::convert::TryFrom<_>>::try_from(no_times.clone())
+                .unwrap_err();
+        assert_eq!(rejected.kind, crate::parse::ParseErrorKind::DateTime);
+    }
+
+    #[test]
+    fn unreadable_parquet_rows_are_emitted_with_their_error() {
+        let path = parquet_fixture("unreadable");
+        let first = parquet_rows(&path).unwrap().next().unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let mut read = false;
+        let rows = std::iter::from_fn(move || {
+            if read {
+                panic!("corrupt page");
+            }
+            read = true;
+            Some(first.clone())
+        });
+        let rows: Vec<_> = ParquetRows::new("rides.parquet".to_owned(), rows).collect();
+        assert_eq!(rows.len(), 2);
+        assert!(rows[0].error.is_empty());
+        assert_eq!((rows[1].path.as_str(), rows[1].row), ("rides.parquet", 1));
+        assert_eq!(rows[1].error, "corrupt page");
+    }
+
+    mod parquet_fixture {
+        use parquet::column::writer::ColumnWriter;
+        use parquet::file::properties::WriterProperties;
+        use parquet::file::writer::{FileWriter, RowGroupWriter, SerializedFileWriter};
+        use parquet::schema::parser::parse_message_type;
+        use std::path::Path;
+        use std::sync::Arc;
+
+        const SCHEMA: &str = "
+            message schema {
+                OPTIONAL INT64 VendorID;
+                OPTIONAL INT64 tpep_pickup_datetime (TIMESTAMP_MICROS);
+                OPTIONAL INT64 tpep_dropoff_datetime (TIMESTAMP_MICROS);
+                OPTIONAL DOUBLE passenger_count;
+                OPTIONAL DOUBLE trip_distance;
+                OPTIONAL INT64 PULocationID;
+                OPTIONAL INT64 DOLocationID;
+                OPTIONAL DOUBLE fare_amount;
+                OPTIONAL DOUBLE total_amount;
+                OPTIONAL DOUBLE airport_fee;
+            }
+        ";
+
+        fn micros(datetime: &str) -> i64 {
+            chrono::NaiveDateTime::parse_from_str(datetime, "%Y-%m-%d %H:%M:%S")
+                .unwrap()
+                .timestamp()
+                * 1_000_000
+        }
+
+        /// Values and definition levels of an optional column.
+        fn column<T: Copy>(values: &[Option<T>]) -> (Vec<T>, Vec<i16>) {
+            let present = values.iter().filter_map(|value| *value).collect();
+            let levels = values.iter().map(|value| value.is_some() as i16).collect();
+            (present, levels)
+        }
+
+        /// Writes three rides in the schema of recent TLC releases: a
+        /// complete one, one with null codes and counts, and one without
+        /// pickup and dropoff times.
+        pub fn write(path: &Path) {
+            let longs: Vec<Vec<Option<i64>>> = vec![
+                vec![Some(1), None, Some(2)],
+                vec![
+                    Some(micros("2020-01-01 00:28:15")),
+                    Some(micros("2020-01-01 00:35:39")),
+                    None,
+                ],
+                vec![
+                    Some(micros("2020-01-01 00:33:03")),
+                    Some(micros("2020-01-01 00:43:04")),
+                    None,
+                ],
+            ];
+            let doubles: Vec<Vec<Option<f64>>> = vec![
+                vec![Some(1.0), None, Some(2.0)],
+                vec![Some(1.2), Some(0.6), Some(3.0)],
+            ];
+            let locations: Vec<Vec<Option<i64>>> = vec![
+                vec![Some(238), Some(170), Some(161)],
+                vec![Some(239), Some(137), Some(236)],
+            ];
+            let amounts: Vec<Vec<Option<f64>>> = vec![
+                vec![Some(6.0), Some(-4.5), Some(12.0)],
+                vec![Some(11.27), Some(-8.3), Some(15.3)],
+                vec![Some(1.25), None, None],
+            ];
+            let mut longs = longs.iter().chain(&locations);
+            let mut doubles = doubles.iter().chain(&amounts);
+
+            let schema = Arc::new(parse_message_type(SCHEMA).unwrap());
+            let properties = Arc::new(WriterProperties::builder().build());
+            let file = std::fs::File::create(path).unwrap();
+            let mut writer = SerializedFileWriter::new(file, schema, properties).unwrap();
+            let mut row_group = writer.next_row_group().unwrap();
+            // Columns of either type are written in schema order
+            while let Some(mut writer) = row_group.next_column().unwrap() {
+                match writer {
+                    ColumnWriter::Int64ColumnWriter(ref mut writer) => {
+                        let (values, levels) = column(longs.next().unwrap());
+                        writer.write_batch(&values, Some(&levels), None).unwrap();
+                    }
+                    ColumnWriter::DoubleColumnWriter(ref mut writer) => {
+                        let (values, levels) = column(doubles.next().unwrap());
+                        writer.write_batch(&values, Some(&levels), None).unwrap();
+                    }
+                    _ => unreachable!("the fixture only has INT64 and DOUBLE columns"),
+                }
+                row_group.close_column(writer).unwrap();
+            }
+            writer.close_row_group(row_group).unwrap();
+            writer.close().unwrap();
+        }
+    }
+}
@@ -0,0 +1,895 @@
+use arcon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::agg;
+use crate::agg::window_sum;
+use crate::data;
+use crate::data::OdAccumulator;
+use crate::data::OdWindowedData;
+use crate::data::RideAccumulator;
+use crate::data::RideData;
+use crate::data::RideWindowedData;
+
+/// The kind of window rides are aggregated over.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WindowKind {
+    /// Fixed, non-overlapping windows of `length` seconds
+    Tumbling,
+    /// Windows of `length` seconds starting every `slide` seconds
+    Sliding,
+    /// Per-key windows that close after `gap` seconds without rides
+    Session,
+}
+
+impl std::str::FromStr for WindowKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tumbling" => Ok(WindowKind::Tumbling),
+            "sliding" => Ok(WindowKind::Sliding),
+            "session" => Ok(WindowKind::Session),
+            _ => Err(format!("unknown window kind '{}'", s)),
+        }
+    }
+}
+
+/// How a window keeps the rides assigned to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Aggregation {
+    /// Fold every ride into a `RideAccumulator` as it arrives
+    Incremental,
+    /// Buffer every ride and run `window_sum` when the window closes
+    Appender,
+}
+
+impl std::str::FromStr for Aggregation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "incremental" => Ok(Aggregation::Incremental),
+            "appender" => Ok(Aggregation::Appender),
+            _ => Err(format!("unknown aggregation '{}'", s)),
+        }
+    }
+}
+
+/// Builds a tumbling or sliding window that buffers its rides.
+///
+/// Session windows are not supported by `WindowAssigner`, see [`SessionWindow`].
+pub fn appender_window<B: Backend>(
+    backend: Arc<B>,
+    kind: WindowKind,
+    length: Time,
+    slide: Time,
+    late_arrival: Time,
+) -> impl Operator<IN = RideData, OUT = RideWindowedData> {
+    let function = AppenderWindow::new(backend.clone(), &window_sum);
+    assign(function, backend, kind, length, slide, late_arrival)
+}
+
+/// Builds a tumbling or sliding window that keeps one `RideAccumulator` per
+/// window. Its output is turned into `RideWindowedData` with `agg::window_finish`.
+pub fn incremental_window<B: Backend>(
+    backend: Arc<B>,
+    kind: WindowKind,
+    length: Time,
+    slide: Time,
+    late_arrival: Time,
+) -> impl Operator<IN = RideData, OUT = RideAccumulator> {
+    let function =
+        IncrementalWindow::new(backend.clone(), &agg::window_init, &agg::window_aggregate);
+    assign(function, backend, kind, length, slide, late_arrival)
+}
+
+/// Builds a tumbling or sliding window that keeps one `OdAccumulator` per
+/// zone pair. Rides must be keyed with `RideData::keyed_by_pair`.
+pub fn od_window<B: Backend>(
+    backend: Arc<B>,
+    kind: WindowKind,
+    length: Time,
+    slide: Time,
+    late_arrival: Time,
+) -> impl Operator<IN = RideData, OUT = OdAccumulator> {
+    let function = IncrementalWindow::new(backend.clone(), &agg::od_init, &agg::od_aggregate);
+    assign(function, backend, kind, length, slide, late_arrival)
+}
+
+fn assign<OUT, W, B>(
+    function: W,
+    backend: Arc<B>,
+    kind: WindowKind,
+    length: Time,
+    slide: Time,
+    late_arrival: Time,
+) -> impl Operator<IN = RideData, OUT = OUT>
+where
+    OUT: ArconType,
+    W: Window<RideData, OUT>,
+    B: Backend,
+{
+    match kind {
+        WindowKind::Tumbling => {
+            WindowAssigner::tumbling(function, backend, length, late_arrival, true)
+        }
+        WindowKind::Sliding => {
+            WindowAssigner::sliding(function, backend, length, slide, late_arrival, true)
+        }
+        WindowKind::Session => unreachable!("session windows are built with SessionWindow"),
+    }
+}
+
+/// Window output that carries the bounds of its window.
+pub trait Windowed {
+    fn set_window(&mut self, start: u64, end: u64);
+}
+
+impl Windowed for RideWindowedData {
+    fn set_window(&mut self, start: u64, end: u64) {
+        self.window_start = start;
+        self.window_end = end;
+    }
+}
+
+impl Windowed for OdWindowedData {
+    fn set_window(&mut self, start: u64, end: u64) {
+        self.window_start = start;
+        self.window_end = end;
+    }
+}
+
+/// Operator that stamps window bounds onto the output of a `WindowAssigner`.
+///
+/// Arcon emits a window result with the end of the window as its timestamp,
+/// so the start is found by subtracting the window length. Both are event
+/// times and are stamped as seconds since the epoch, see `data::local_time`.
+pub struct WindowBounds<T> {
+    state: (),
+    length: u64,
+    _marker: std::marker::PhantomData<T>,
+}
+
+impl<T> WindowBounds<T> {
+    pub fn new(length: Time) -> Self {
+        Self {
+            state: (),
+            length: length.0,
+            _marker: std::marker::PhantomData,
+        }
+    }
+
+    /// Start and end, in seconds since the epoch, of the window that ends at
+    /// the event time `window_end`.
+    fn bounds(&self, window_end: u64) -> (u64, u64) {
+        let window_start = window_end.saturating_sub(self.length);
+        (data::utc_time(window_start), data::utc_time(window_end))
+    }
+}
+
+impl<T: ArconType + Windowed> Operator for WindowBounds<T> {
+    type IN = T;
+    type OUT = T;
+    type TimerState = ArconNever;
+    type OperatorState = ();
+
+    fn handle_element(
+        &mut self,
+        element: ArconElement<Self::IN>,
+        mut ctx: OperatorContext<Self, impl Backend, impl ComponentDefinition>,
+    ) -> ArconResult<()> {
+        let mut data = element.data;
+        if let Some(window_end) = element.timestamp {
+            let (start, end) = self.bounds(window_end);
+            data.set_window(start, end);
+        }
+        ctx.output(ArconElement {
+            data,
+            timestamp: element.timestamp,
+        });
+        Ok(())
+    }
+
+    arcon::ignore_timeout!();
+    arcon::ignore_persist!();
+
+    fn state(&mut self) -> &mut Self::OperatorState {
+        &mut self.state
+    }
+}
+
+/// Aggregate of the rides of a single key that have not yet been separated
+/// by a gap.
+#[macros::proto]
+#[derive(Arcon, Clone)]
+#[arcon(unsafe_ser_id = 12, reliable_ser_id = 13, version = 1)]
+pub struct RideSession {
+    pub start: u64,
+    pub last: u64,
+    /// Whether the session was emitted since a ride was last added to it
+    pub emitted: bool,
+    pub revision: u64,
+    pub acc: RideAccumulator,
+}
+
+/// Open sessions of a key, sorted by start and separated by more than the gap.
+#[macros::proto]
+#[derive(Arcon, Clone)]
+#[arcon(unsafe_ser_id = 12, reliable_ser_id = 13, version = 1)]
+pub struct RideSessions {
+    pub sessions: Vec<RideSession>,
+}
+
+/// Timer registered for a key at the earliest deadline of its sessions.
+#[macros::proto]
+#[derive(Arcon, Clone)]
+#[arcon(unsafe_ser_id = 12, reliable_ser_id = 13, version = 1)]
+pub struct SessionTimeout {
+    pub key: u64,
+}
+
+#[derive(ArconState)]
+pub struct SessionState<B: Backend> {
+    #[table = "sessions"]
+    sessions: EagerHashTable<u64, RideSessions, B>,
+}
+
+impl<B: Backend> StateConstructor for SessionState<B> {
+    type BackendType = B;
+
+    fn new(backend: Arc<Self::BackendType>) -> Self {
+        Self {
+            sessions: EagerHashTable::new("_sessions", backend),
+        }
+    }
+}
+
+/// Adds a ride at `time` to the sessions of its key. The ride joins every
+/// session it is at most `gap` seconds away from, so a late ride that
+/// bridges two sessions merges them into one. A session that was emitted
+/// already is emitted again with its revision increased.
+fn add_to_sessions(sessions: &mut Vec<RideSession>, ride: RideData, time: u64, gap: u64) {
+    let joins = |session: &RideSession| time <= session.last + gap && session.start <= time + gap;
+    let first = sessions.iter().position(joins);
+    let count = sessions.iter().filter(|session| joins(session)).count();
+    let index = first.unwrap_or_else(|| sessions.iter().take_while(|s| s.start < time).count());
+
+    let mut joined = sessions.drain(index..index + count);
+    let mut session = match joined.next() {
+        Some(mut session) => {
+            agg::window_add(&mut session.acc, &ride);
+            session.start = session.start.min(time);
+            session.last = session.last.max(time);
+            session
+        }
+        None => RideSession {
+            start: time,
+            last: time,
+            emitted: false,
+            revision: 0,
+            acc: agg::window_init(ride),
+        },
+    };
+    for other in joined {
+        session.last = session.last.max(other.last);
+        session.emitted |= other.emitted;
+        session.revision = session.revision.max(other.revision);
+        session.acc = agg::window_merge(&session.acc, &other.acc);
+    }
+    if session.emitted {
+        session.emitted = false;
+        session.revision += 1;
+    }
+    sessions.insert(index, session);
+}
+
+/// Returns the sessions that closed by `now` and were not emitted since
+/// their last ride, marking them as emitted, and drops the sessions that
+/// are past their allowed lateness.
+fn close_sessions(
+    sessions: &mut Vec<RideSession>,
+    gap: u64,
+    late_arrival: u64,
+    now: u64,
+) -> Vec<RideSession> {
+    let mut closed = Vec::new();
+    for session in sessions.iter_mut() {
+        if !session.emitted && session.last + gap <= now {
+            session.emitted = true;
+            closed.push(session.clone());
+        }
+    }
+    sessions.retain(|session| session.last + gap + late_arrival > now);
+    closed
+}
+
+/// Time at which a session is emitted, or dropped once it was.
+fn session_deadline(session: &RideSession, gap: u64, late_arrival: u64) -> u64 {
+    if session.emitted {
+        session.last + gap + late_arrival
+    } else {
+        session.last + gap
+    }
+}
+
+/// Keyed session window over rides.
+///
+/// A session closes once the watermark passes `gap` seconds after its last
+/// ride. Rides are folded into a `RideAccumulator` as they arrive, and the
+/// window spans from the first ride to the last ride plus the gap. A key
+/// keeps every session the watermark has not passed yet, so late rides are
+/// added to the session they belong to rather than to the latest one.
+///
+/// Closed sessions are kept until `late_arrival` seconds after they closed.
+/// A late ride that falls into one emits it again with the ride added and
+/// its `revision` increased, like `UpdatingWindow` does.
+pub struct SessionWindow<B: Backend> {
+    state: SessionState<B>,
+    gap: u64,
+    late_arrival: u64,
+}
+
+impl<B: Backend> SessionWindow<B> {
+    pub fn new(backend: Arc<B>, gap: Time, late_arrival: Time) -> Self {
+        Self {
+            state: SessionState::new(backend),
+            gap: gap.0,
+            late_arrival: late_arrival.0,
+        }
+    }
+
+    fn close(&self, session: &RideSession) -> ArconElement<RideWindowedData> {
+        let window_end = session.last + self.gap;
+        let mut data = agg::window_finish(session.acc.clone());
+        data.window_start = data::utc_time(session.start);
+        data.window_end = data::utc_time(window_end);
+        data.revision = session.revision;
+        ArconElement::with_timestamp(data, window_end)
+    }
+
+    /// Emits the sessions of `key` that closed by `now`, drops the ones past
+    /// their allowed lateness, and arms the timer of the key at the earliest
+    /// deadline of the remaining ones.
+    fn expire(
+        &self,
+        key: u64,
+        sessions: &mut Vec<RideSession>,
+        mut now: u64,
+        ctx: &mut OperatorContext<Self, impl Backend, impl ComponentDefinition>,
+    ) {
+        loop {
+            let (gap, late_arrival) = (self.gap, self.late_arrival);
+            for session in close_sessions(sessions, gap, late_arrival, now) {
+                ctx.output(self.close(&session));
+            }
+
+            let deadlines = sessions
+                .iter()
+                .map(|s| session_deadline(s, gap, late_arrival));
+            let deadline = match deadlines.min() {
+                Some(deadline) => deadline,
+                None => return,
+            };
+            // Fails if the watermark already passed the deadline, in which
+            // case the sessions due by then are closed right away.
+            match ctx.schedule_at(key, deadline, SessionTimeout { key }) {
+                Ok(_) => return,
+                Err(_) => now = deadline,
+            }
+        }
+    }
+}
+
+impl<B: Backend> Operator for SessionWindow<B> {
+    type IN = RideData;
+    type OUT = RideWindowedData;
+    type TimerState = SessionTimeout;
+    type OperatorState = SessionState<B>;
+
+    fn handle_element(
+        &mut self,
+        element: ArconElement<Self::IN>,
+        mut ctx: OperatorContext<Self, impl Backend, impl ComponentDefinition>,
+    ) -> ArconResult<()> {
+        let key = element.data.get_key();
+        let time = element
+            .timestamp
+            .unwrap_or_else(|| data::local_time(element.data.pu_time));
+        let watermark = ctx.current_time()?;
+
+        // Rides too late for every session are counted by `ops::LateRides`
+        if time + self.gap + self.late_arrival <= watermark {
+            return Ok(());
+        }
+
+        let mut sessions = self.state.sessions.remove(&key)?.unwrap_or_default();
+        add_to_sessions(&mut sessions.sessions, element.data, time, self.gap);
+        self.expire(key, &mut sessions.sessions, watermark, &mut ctx);
+        if !sessions.sessions.is_empty() {
+            self.state.sessions.put(key, sessions)?;
+        }
+        Ok(())
+    }
+
+    fn handle_timeout(
+        &mut self,
+        timeout: Self::TimerState,
+        mut ctx: OperatorContext<Self, impl Backend, impl ComponentDefinition>,
+    ) -> ArconResult<()> {
+        let now = ctx.current_time()?;
+        let mut sessions = match self.state.sessions.remove(&timeout.key)? {
+            Some(sessions) => sessions,
+            None => return Ok(()),
+        };
+        self.expire(timeout.key, &mut sessions.sessions, now, &mut ctx);
+        if !sessions.sessions.is_empty() {
+            self.state.sessions.put(timeout.key, sessions)?;
+        }
+        Ok(())
+    }
+
+    fn persist(&mut self) -> Result<(), ArconStateError> {
+        self.state.persist()
+    }
+
+    fn state(&mut self) -> &mut Self::OperatorState {
+        &mut self.state
+    }
+}
+
+/// A tumbling or sliding window of a key that is open or within its allowed
+/// lateness.
+#[macros::proto]
+#[derive(Arcon, Clone)]
+#[arcon(unsafe_ser_id = 12, reliable_ser_id = 13, version = 1)]
+pub struct PendingWindow {
+    pub start: u64,
+    /// Whether the window was emitted since a ride was last added to it
+    pub emitted: bool,
+    pub revision: u64,
+    pub acc: RideAccumulator,
+}
+
+/// Windows of a key, sorted by start.
+#[macros::proto]
+#[derive(Arcon, Clone)]
+#[arcon(unsafe_ser_id = 12, reliable_ser_id = 13, version = 1)]
+pub struct PendingWindows {
+    pub windows: Vec<PendingWindow>,
+}
+
+/// Timer registered for a key at the earliest deadline of its windows: the
+/// end of a window that was not emitted yet, or the end of the allowed
+/// lateness of one that was.
+#[macros::proto]
+#[derive(Arcon, Clone)]
+#[arcon(unsafe_ser_id = 12, reliable_ser_id = 13, version = 1)]
+pub struct WindowTimeout {
+    pub key: u64,
+}
+
+#[derive(ArconState)]
+pub struct UpdatingState<B: Backend> {
+    #[table = "windows"]
+    windows: EagerHashTable<u64, PendingWindows, B>,
+}
+
+impl<B: Backend> StateConstructor for UpdatingState<B> {
+    type BackendType = B;
+
+    fn new(backend: Arc<Self::BackendType>) -> Self {
+        Self {
+            windows: EagerHashTable::new("_windows", backend),
+        }
+    }
+}
+
+/// Tumbling or sliding windows of `length` seconds every `slide` seconds,
+/// kept until `late_arrival` seconds after their end.
+#[derive(Debug, Clone, Copy)]
+struct WindowSpec {
+    length: u64,
+    slide: u64,
+    late_arrival: u64,
+}
+
+impl WindowSpec {
+    /// Starts of the windows that contain `time`.
+    fn starts(&self, time: u64) -> Vec<u64> {
+        let mut starts = Vec::new();
+        let mut start = Some(time / self.slide * self.slide);
+        while let Some(s) = start.filter(|s| s + self.length > time) {
+            starts.push(s);
+            start = s.checked_sub(self.slide);
+        }
+        starts
+    }
+
+    /// Time at which a window is emitted, or dropped once it was.
+    fn deadline(&self, window: &PendingWindow) -> u64 {
+        let end = window.start + self.length;
+        if window.emitted {
+            end + self.late_arrival
+        } else {
+            end
+        }
+    }
+
+    /// Adds a ride at `time` to its windows that are not past their allowed
+    /// lateness by `watermark`. A window that was emitted already is emitted
+    /// again with its revision increased.
+    fn add(&self, windows: &mut Vec<PendingWindow>, ride: &RideData, time: u64, watermark: u64) {
+        for start in self.starts(time) {
+            // Closed windows are counted by `ops::LateRides`
+            if start + self.length + self.late_arrival <= watermark {
+                continue;
+            }
+            match windows.binary_search_by_key(&start, |w| w.start) {
+                Ok(i) => {
+                    let window = &mut windows[i];
+                    agg::window_add(&mut window.acc, ride);
+                    if window.emitted {
+                        window.emitted = false;
+                        window.revision += 1;
+                    }
+                }
+                Err(i) => {
+                    let window = PendingWindow {
+                        start,
+                        emitted: false,
+                        revision: 0,
+                        acc: agg::window_init(ride.clone()),
+                    };
+                    windows.insert(i, window);
+                }
+            }
+        }
+    }
+
+    /// Returns the windows that ended by `now` and were not emitted since
+    /// their last ride, marking them as emitted, and drops the windows that
+    /// are past their allowed lateness.
+    fn close(&self, windows: &mut Vec<PendingWindow>, now: u64) -> Vec<PendingWindow> {
+        let mut closed = Vec::new();
+        for window in windows.iter_mut() {
+            if !window.emitted && window.start + self.length <= now {
+                window.emitted = true;
+                closed.push(window.clone());
+            }
+        }
+        let (length, late_arrival) = (self.length, self.late_arrival);
+        windows.retain(|window| window.start + length + late_arrival > now);
+        closed
+    }
+
+    fn output(&self, window: &PendingWindow) -> ArconElement<RideWindowedData> {
+        let window_end = window.start + self.length;
+        let mut data = agg::window_finish(window.acc.clone());
+        data.window_start = data::utc_time(window.start);
+        data.window_end = data::utc_time(window_end);
+        data.revision = window.revision;
+        ArconElement::with_timestamp(data, window_end)
+    }
+}
+
+/// Keyed tumbling or sliding window that emits updates for late rides.
+///
+/// A window is emitted once the watermark passes its end, like with
+/// `WindowAssigner`. Until `late_arrival` seconds after its end, every late
+/// ride that falls into it emits the window again with the ride added and
+/// its `revision` increased, so the indexed document is replaced by the
+/// corrected one.
+pub struct UpdatingWindow<B: Backend> {
+    state: UpdatingState<B>,
+    spec: WindowSpec,
+}
+
+impl<B: Backend> UpdatingWindow<B> {
+    pub fn new(
+        backend: Arc<B>,
+        kind: WindowKind,
+        length: Time,
+        slide: Time,
+        late_arrival: Time,
+    ) -> Self {
+        let slide = match kind {
+            WindowKind::Sliding => slide.0,
+            _ => length.0,
+        };
+        Self {
+            state: UpdatingState::new(backend),
+            spec: WindowSpec {
+                length: length.0,
+                slide,
+                late_arrival: late_arrival.0,
+            },
+        }
+    }
+
+    /// Emits the windows of `key` that ended by `now`, drops the ones past
+    /// their allowed lateness, and arms the timer of the key at the earliest
+    /// deadline of the remaining ones.
+    fn advance(
+        &self,
+        key: u64,
+        pending: &mut PendingWindows,
+        mut now: u64,
+        ctx: &mut OperatorContext<Self, impl Backend, impl ComponentDefinition>,
+    ) {
+        let spec = &self.spec;
+        loop {
+            for window in spec.close(&mut pending.windows, now) {
+                ctx.output(spec.output(&window));
+            }
+
+            let deadline = match pending.windows.iter().map(|w| spec.deadline(w)).min() {
+                Some(deadline) => deadline,
+                None => return,
+            };
+            // Fails if the watermark already passed the deadline, in which
+            // case the windows due by then are handled right away
+            match ctx.schedule_at(key, deadline, WindowTimeout { key }) {
+                Ok(_) => return,
+                Err(_) => now = deadline,
+            }
+        }
+    }
+}
+
+impl<B: Backend> Operator for UpdatingWindow<B> {
+    type IN = RideData;
+    type OUT = RideWindowedData;
+    type TimerState = WindowTimeout;
+    type OperatorState = UpdatingState<B>;
+
+    fn handle_element(
+        &mut self,
+        element: ArconElement<Self::IN>,
+        mut ctx: OperatorContext<Self, impl Backend, impl ComponentDefinition>,
+    ) -> ArconResult<()> {
+        let key = element.data.get_key();
+        let time = element
+            .timestamp
+            .unwrap_or_else(|| data::local_time(element.data.pu_time));
+        let watermark = ctx.current_time()?;
+        let mut pending = self.state.windows.remove(&key)?.unwrap_or_default();
+        self.spec
+            .add(&mut pending.windows, &element.data, time, watermark);
+        self.advance(key, &mut pending, watermark, &mut ctx);
+        if !pending.windows.is_empty() {
+            self.state.windows.put(key, pending)?;
+        }
+        Ok(())
+    }
+
+    fn handle_timeout(
+        &mut self,
+        timeout: Self::TimerState,
+        mut ctx: OperatorContext<Self, impl Backend, impl ComponentDefinition>,
+    ) -> ArconResult<()> {
+        let now = ctx.current_time()?;
+        let mut pending = match self.state.windows.remove(&timeout.key)? {
+            Some(pending) => pending,
+            None => return Ok(()),
+        };
+        self.advance(timeout.key, &mut pending, now, &mut ctx);
+        if !pending.windows.is_empty() {
+            self.state.windows.put(timeout.key, pending)?;
+        }
+        Ok(())
+    }
+
+    fn persist(&mut self) -> Result<(), ArconStateError> {
+        self.state.persist()
+    }
+
+    fn state(&mut self) -> &mut Self::OperatorState {
+        &mut self.state
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sessions(times: &[u64], gap: u64) -> Vec<RideSession> {
+        let mut sessions = Vec::new();
+        for &time in times {
+            add_to_sessions(&mut sessions, RideData::default(), time, gap);
+        }
+        sessions
+    }
+
+    fn bounds(sessions: &[RideSession]) -> Vec<(u64, u64, u64)> {
+        sessions
+            .iter()
+            .map(|s| (s.start, s.last, s.acc.count))
+            .collect()
+    }
+
+    #[test]
+    fn rides_within_the_gap_share_a_session() {
+        let sessions = sessions(&[100, 105, 115, 200], 10);
+        assert_eq!(bounds(&sessions), vec![(100, 115, 3), (200, 200, 1)]);
+    }
+
+    #[test]
+    fn late_ride_joins_an_earlier_session() {
+        let sessions = sessions(&[100, 200, 95], 10);
+        assert_eq!(bounds(&sessions), vec![(95, 100, 2), (200, 200, 1)]);
+    }
+
+    #[test]
+    fn late_ride_bridging_two_sessions_merges_them() {
+        let sessions = sessions(&[100, 120, 300, 110], 10);
+        assert_eq!(bounds(&sessions), vec![(100, 120, 3), (300, 300, 1)]);
+    }
+
+    #[test]
+    fn late_ride_between_sessions_opens_its_own() {
+        let sessions = sessions(&[100, 300, 200], 10);
+        assert_eq!(
+            bounds(&sessions),
+            vec![(100, 100, 1), (200, 200, 1), (300, 300, 1)]
+        );
+    }
+
+    /// `(start, last, count, revision)` of the given sessions.
+    fn revisions(sessions: &[RideSession]) -> Vec<(u64, u64, u64, u64)> {
+        sessions
+            .iter()
+            .map(|s| (s.start, s.last, s.acc.count, s.revision))
+            .collect()
+    }
+
+    #[test]
+    fn late_ride_emits_a_closed_session_again() {
+        let (gap, late_arrival) = (10, 60);
+        let mut open = sessions(&[100, 105], gap);
+        let closed = close_sessions(&mut open, gap, late_arrival, 115);
+        assert_eq!(revisions(&closed), vec![(100, 105, 2, 0)]);
+        assert!(close_sessions(&mut open, gap, late_arrival, 120).is_empty());
+
+        // Within the allowed lateness, the ride is added and the session is
+        // emitted again right away
+        add_to_sessions(&mut open, RideData::default(), 103, gap);
+        let closed = close_sessions(&mut open, gap, late_arrival, 120);
+        assert_eq!(revisions(&closed), vec![(100, 105, 3, 1)]);
+        assert_eq!(session_deadline(&open[0], gap, late_arrival), 175);
+    }
+
+    #[test]
+    fn late_ride_merging_closed_sessions_increases_the_revision() {
+        let (gap, late_arrival) = (10, 60);
+        let mut open = sessions(&[100, 120], gap);
+        assert_eq!(close_sessions(&mut open, gap, late_arrival, 140).len(), 2);
+
+        add_to_sessions(&mut open, RideData::default(), 110, gap);
+        let closed = close_sessions(&mut open, gap, late_arrival, 140);
+        assert_eq!(revisions(&closed), vec![(100, 120, 3, 1)]);
+    }
+
+    #[test]
+    fn sessions_are_dropped_after_the_allowed_lateness() {
+        let (gap, late_arrival) = (10, 60);
+        let mut open = sessions(&[100, 200], gap);
+        assert_eq!(close_sessions(&mut open, gap, late_arrival, 110).len(), 1);
+        assert_eq!(open.len(), 2);
+        assert_eq!(session_deadline(&open[0], gap, late_arrival), 170);
+        assert_eq!(session_deadline(&open[1], gap, late_arrival), 210);
+
+        assert!(close_sessions(&mut open, gap, late_arrival, 170).is_empty());
+        assert_eq!(bounds(&open), vec![(200, 200, 1)]);
+    }
+
+    /// Seconds since the epoch of a UTC datetime.
+    fn utc(datetime: &str) -> u64 {
+        chrono::NaiveDateTime::parse_from_str(datetime, "%Y-%m-%d %H:%M:%S")
+            .unwrap()
+            .timestamp() as u64
+    }
+
+    #[test]
+    fn day_windows_are_bounded_by_local_midnight_across_dst() {
+        let days = WindowBounds::<RideWindowedData>::new(Time::seconds(86400));
+        let midnight = |date: &str| utc(&format!("{} 00:00:00", date));
+
+        // Clocks are set forward on 2020-03-08, so the day has 23 hours
+        let (start, end) = days.bounds(midnight("2020-03-09"));
+        assert_eq!(start, utc("2020-03-08 05:00:00"));
+        assert_eq!(end, utc("2020-03-09 04:00:00"));
+        assert_eq!(end - start, 23 * 3600);
+
+        // and back on 2020-11-01, so that day has 25
+        let (start, end) = days.bounds(midnight("2020-11-02"));
+        assert_eq!(start, utc("2020-11-01 04:00:00"));
+        assert_eq!(end, utc("2020-11-02 05:00:00"));
+        assert_eq!(end - start, 25 * 3600);
+
+        let (start, end) = days.bounds(midnight("2020-07-02"));
+        assert_eq!(start, utc("2020-07-01 04:00:00"));
+        assert_eq!(end - start, 24 * 3600);
+    }
+
+    const TUMBLING: WindowSpec = WindowSpec {
+        length: 100,
+        slide: 100,
+        late_arrival: 50,
+    };
+
+    fn add_rides(
+        spec: &WindowSpec,
+        windows: &mut Vec<PendingWindow>,
+        times: &[u64],
+        watermark: u64,
+    ) {
+        for &time in times {
+            spec.add(windows, &RideData::default(), time, watermark);
+        }
+    }
+
+    /// Document id of an emitted window with the default id template.
+    fn document_id(spec: &WindowSpec, window: &PendingWindow) -> String {
+        let template = crate::conf::TaxiConf::default().sink.id_template;
+        let document = serde_json::to_value(spec.output(window).data).unwrap();
+        crate::ops::render_id(&template, &document).unwrap()
+    }
+
+    #[test]
+    fn late_ride_within_the_allowed_lateness_updates_the_window() {
+        let spec = TUMBLING;
+        let mut windows = Vec::new();
+        add_rides(&spec, &mut windows, &[30, 60], 0);
+        let first = spec.close(&mut windows, 100);
+        assert_eq!(first.len(), 1);
+        assert_eq!((first[0].acc.count, first[0].revision), (2, 0));
+
+        add_rides(&spec, &mut windows, &[40], 149);
+        let update = spec.close(&mut windows, 149);
+        assert_eq!(update.len(), 1);
+        assert_eq!((update[0].acc.count, update[0].revision), (3, 1));
+        assert_eq!(spec.output(&update[0]).data.revision, 1);
+        assert_eq!(
+            document_id(&spec, &first[0]),
+            document_id(&spec, &update[0])
+        );
+    }
+
+    #[test]
+    fn ride_after_the_allowed_lateness_is_dropped() {
+        let spec = TUMBLING;
+        let mut windows = Vec::new();
+        add_rides(&spec, &mut windows, &[30], 0);
+        assert_eq!(spec.close(&mut windows, 100).len(), 1);
+
+        add_rides(&spec, &mut windows, &[40], 150);
+        assert!(spec.close(&mut windows, 150).is_empty());
+        assert!(windows.is_empty());
+    }
+
+    #[test]
+    fn every_window_of_a_key_fires_from_its_single_timer() {
+        let spec = WindowSpec {
+            length: 200,
+            slide: 100,
+            late_arrival: 50,
+        };
+        let mut windows = Vec::new();
+        add_rides(&spec, &mut windows, &[50, 150, 250], 0);
+        assert_eq!(windows.len(), 3);
+
+        // Follow the timer of the key from one earliest deadline to the next
+        let mut fired = Vec::new();
+        let mut deadlines = Vec::new();
+        while let Some(deadline) = windows.iter().map(|w| spec.deadline(w)).min() {
+            deadlines.push(deadline);
+            for window in spec.close(&mut windows, deadline) {
+                fired.push((window.start, window.acc.count));
+            }
+        }
+        assert_eq!(fired, vec![(0, 2), (100, 2), (200, 1)]);
+        assert_eq!(deadlines, vec![200, 250, 300, 350, 400, 450]);
+    }
+}
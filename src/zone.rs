@@ -0,0 +1,297 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::data::OdWindowedData;
+use crate::data::RideWindowedData;
+
+/// A TLC taxi zone.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Zone {
+    pub location_id: u64,
+    pub borough: String,
+    pub zone: String,
+}
+
+impl Zone {
+    fn unknown(location_id: u64) -> Self {
+        Self {
+            location_id,
+            borough: "Unknown".to_owned(),
+            zone: "Unknown".to_owned(),
+        }
+    }
+
+    /// Display name in the form "Borough, Zone".
+    pub fn name(&self) -> String {
+        format!("{}, {}", self.borough, self.zone)
+    }
+}
+
+#[derive(Debug)]
+pub enum ZoneError {
+    Io(PathBuf, std::io::Error),
+    Csv(PathBuf, csv::Error),
+    Json(PathBuf, serde_json::Error),
+    UnknownFormat(PathBuf),
+}
+
+impl fmt::Display for ZoneError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ZoneError::Io(path, err) => write!(f, "failed to read {}: {}", path.display(), err),
+            ZoneError::Csv(path, err) => write!(f, "invalid CSV in {}: {}", path.display(), err),
+            ZoneError::Json(path, err) => {
+                write!(f, "invalid GeoJSON in {}: {}", path.display(), err)
+            }
+            ZoneError::UnknownFormat(path) => write!(
+                f,
+                "unknown zone table format for {}, expected .csv, .json or .geojson",
+                path.display()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ZoneError {}
+
+/// Row of the official TLC `taxi_zone_lookup.csv`.
+#[derive(Deserialize)]
+struct LookupRow {
+    #[serde(rename = "LocationID")]
+    location_id: u64,
+    #[serde(rename = "Borough")]
+    borough: String,
+    #[serde(rename = "Zone")]
+    zone: String,
+}
+
+#[derive(Deserialize)]
+struct FeatureCollection {
+    features: Vec<Feature>,
+}
+
+#[derive(Deserialize)]
+struct Feature {
+    properties: FeatureProperties,
+}
+
+#[derive(Deserialize)]
+struct FeatureProperties {
+    locationid: u64,
+    borough: String,
+    zone: String,
+}
+
+/// Lookup table from TLC location ids to taxi zones.
+///
+/// Ids that are not in the table resolve to an "Unknown" zone.
+#[derive(Debug, Clone, Default)]
+pub struct ZoneTable {
+    zones: HashMap<u64, Zone>,
+}
+
+impl ZoneTable {
+    /// Loads the table from a `taxi_zone_lookup.csv` file or a GeoJSON file
+    /// with `locationid`, `borough` and `zone` properties, depending on the
+    /// file extension.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ZoneError> {
+        let path = path.as_ref();
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("csv") => Self::from_lookup_csv(path),
+            Some("json") | Some("geojson") => Self::from_geojson(path),
+            _ => Err(ZoneError::UnknownFormat(path.to_owned())),
+        }
+    }
+
+    pub fn from_lookup_csv(path: impl AsRef<Path>) -> Result<Self, ZoneError> {
+        let path = path.as_ref();
+        let mut reader =
+            csv::Reader::from_path(path).map_err(|e| ZoneError::Csv(path.to_owned(), e))?;
+        let mut table = Self::default();
+        for row in reader.deserialize() {
+            let row: LookupRow = row.map_err(|e| ZoneError::Csv(path.to_owned(), e))?;
+            table.insert(row.location_id, row.borough, row.zone);
+        }
+        Ok(table)
+    }
+
+    pub fn from_geojson(path: impl AsRef<Path>) -> Result<Self, ZoneError> {
+        let path = path.as_ref();
+        let file = std::fs::File::open(path).map_err(|e| ZoneError::Io(path.to_owned(), e))?;
+        let collection: FeatureCollection = serde_json::from_reader(std::io::BufReader::new(file))
+            .map_err(|e| ZoneError::Json(path.to_owned(), e))?;
+        let mut table = Self::default();
+        for feature in collection.features {
+            let p = feature.properties;
+            table.insert(p.locationid, p.borough, p.zone);
+        }
+        Ok(table)
+    }
+
+    fn insert(&mut self, location_id: u64, borough: String, zone: String) {
+        self.zones.insert(
+            location_id,
+            Zone {
+                location_id,
+                borough,
+                zone,
+            },
+        );
+    }
+
+    pub fn len(&self) -> usize {
+        self.zones.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.zones.is_empty()
+    }
+
+    /// Returns the zone of a location id, or an "Unknown" zone.
+    pub fn get(&self, location_id: u64) -> Zone {
+        self.zones
+            .get(&location_id)
+            .cloned()
+            .unwrap_or_else(|| Zone::unknown(location_id))
+    }
+
+    /// Fills in the zone fields of a window aggregate.
+    pub fn annotate(&self, mut data: RideWindowedData) -> RideWindowedData {
+        let zone = self.get(data.location_id);
+        data.location_name = zone.name();
+        data.borough = zone.borough;
+        data.zone = zone.zone;
+        data
+    }
+
+    /// Fills in the zone names of a zone pair aggregate.
+    pub fn annotate_pair(&self, mut data: OdWindowedData) -> OdWindowedData {
+        data.pu_location_name = self.get(data.pu_location_id).name();
+        data.du_location_name = self.get(data.du_location_id).name();
+        data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes a zone table fixture to a temporary file with the given
+    /// extension.
+    fn fixture(name: &str, extension: &str, text: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "arcon_taxi-{}-{}.{}",
+            name,
+            std::process::id(),
+            extension
+        ));
+        std::fs::write(&path, text).unwrap();
+        path
+    }
+
+    fn load(name: &str, extension: &str, text: &str) -> Result<ZoneTable, ZoneError> {
+        let path = fixture(name, extension, text);
+        let table = ZoneTable::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        table
+    }
+
+    const LOOKUP_CSV: &str = "\"LocationID\",\"Borough\",\"Zone\",\"service_zone\"
+1,\"EWR\",\"Newark Airport\",\"EWR\"
+161,\"Manhattan\",\"Midtown Center\",\"Yellow Zone\"
+264,\"Unknown\",\"NV\",\"N/A\"
+265,\"Unknown\",\"NA\",\"N/A\"
+";
+
+    const ZONES_GEOJSON: &str = r#"{
+        "type": "FeatureCollection",
+        "features": [
+            {
+                "type": "Feature",
+                "properties": { "objectid": 1, "locationid": 1, "borough": "EWR", "zone": "Newark Airport", "shape_area": 0.00078 },
+                "geometry": { "type": "Polygon", "coordinates": [] }
+            },
+            {
+                "type": "Feature",
+                "properties": { "objectid": 161, "locationid": 161, "borough": "Manhattan", "zone": "Midtown Center", "shape_area": 0.00047 },
+                "geometry": { "type": "Polygon", "coordinates": [] }
+            }
+        ]
+    }"#;
+
+    #[test]
+    fn lookup_csv_is_loaded() {
+        let table = load("zones", "csv", LOOKUP_CSV).unwrap();
+        assert_eq!(table.len(), 4);
+        assert_eq!(table.get(161).name(), "Manhattan, Midtown Center");
+        assert_eq!(
+            table.get(1),
+            Zone {
+                location_id: 1,
+                borough: "EWR".to_owned(),
+                zone: "Newark Airport".to_owned(),
+            }
+        );
+        // The lookup table has its own rows for unknown and outside zones
+        assert_eq!(table.get(264).name(), "Unknown, NV");
+        assert_eq!(table.get(265).name(), "Unknown, NA");
+    }
+
+    #[test]
+    fn geojson_is_loaded() {
+        for extension in &["json", "geojson"] {
+            let table = load("zones", extension, ZONES_GEOJSON).unwrap();
+            assert_eq!(table.len(), 2);
+            assert_eq!(table.get(1).name(), "EWR, Newark Airport");
+            assert_eq!(table.get(161).name(), "Manhattan, Midtown Center");
+        }
+    }
+
+    #[test]
+    fn missing_ids_resolve_to_unknown() {
+        // The shapefile has no features for 264 and 265
+        let table = load("unknown", "geojson", ZONES_GEOJSON).unwrap();
+        for &id in &[264, 265, 0] {
+            assert_eq!(table.get(id), Zone::unknown(id));
+            assert_eq!(table.get(id).name(), "Unknown, Unknown");
+        }
+
+        let data = RideWindowedData {
+            location_id: 265,
+            ..Default::default()
+        };
+        let data = table.annotate(data);
+        assert_eq!(
+            (data.location_name.as_str(), data.borough.as_str()),
+            ("Unknown, Unknown", "Unknown")
+        );
+
+        let pair = OdWindowedData {
+            pu_location_id: 161,
+            du_location_id: 264,
+            ..Default::default()
+        };
+        let pair = table.annotate_pair(pair);
+        assert_eq!(pair.pu_location_name, "Manhattan, Midtown Center");
+        assert_eq!(pair.du_location_name, "Unknown, Unknown");
+    }
+
+    #[test]
+    fn invalid_tables_are_rejected() {
+        let missing_column = "\"LocationID\",\"Borough\"\n1,\"EWR\"\n";
+        assert!(matches!(
+            load("invalid", "csv", missing_column),
+            Err(ZoneError::Csv(..))
+        ));
+        assert!(matches!(
+            load("invalid", "geojson", "{\"features\": [{}]}"),
+            Err(ZoneError::Json(..))
+        ));
+        assert!(matches!(
+            load("invalid", "txt", LOOKUP_CSV),
+            Err(ZoneError::UnknownFormat(..))
+        ));
+    }
+}